@@ -1,21 +1,34 @@
+use gettextrs::gettext;
 use glib::clone;
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
 use gtk::{gio, glib};
 use log::{debug, info};
+use std::time::Duration;
+use tdgrand::enums::{InputMessageContent, InternalLinkType, ProxyType};
+use tdgrand::{functions, types};
 
 use crate::config::{APP_ID, PKGDATADIR, PROFILE, VERSION};
-use crate::{PreferencesWindow, Window};
+use crate::session::Chat;
+use crate::session_manager::ClientState;
+use crate::{spawn, PreferencesWindow, ProxyWindow, Window};
+
+/// How long a link is kept waiting for a session to be logged in before being discarded.
+const PENDING_LINK_TIMEOUT: Duration = Duration::from_secs(60);
 
 mod imp {
     use super::*;
     use adw::subclass::prelude::AdwApplicationImpl;
     use glib::WeakRef;
     use once_cell::sync::OnceCell;
+    use std::cell::RefCell;
 
     #[derive(Debug, Default)]
     pub struct Application {
         pub window: OnceCell<WeakRef<Window>>,
+        /// The link that will be opened as soon as a session is logged in
+        pub pending_link: RefCell<Option<String>>,
+        pub pending_link_source_id: RefCell<Option<glib::SourceId>>,
     }
 
     #[glib::object_subclass]
@@ -43,9 +56,26 @@ mod imp {
                 .set(window.downgrade())
                 .expect("Window already set.");
 
+            // Open the links received before any session was logged in
+            window
+                .session_manager()
+                .connect_active_session_changed(clone!(@weak app => move |_| {
+                    app.open_pending_link();
+                }));
+
             app.main_window().present();
         }
 
+        fn open(&self, app: &Self::Type, files: &[gio::File], _hint: &str) {
+            debug!("GtkApplication<Application>::open");
+
+            self.activate(app);
+
+            for file in files {
+                app.open_link(file.uri().into());
+            }
+        }
+
         fn startup(&self, app: &Self::Type) {
             debug!("GtkApplication<Application>::startup");
 
@@ -84,7 +114,7 @@ impl Application {
     pub fn new() -> Self {
         glib::Object::new(&[
             ("application-id", &Some(APP_ID)),
-            ("flags", &gio::ApplicationFlags::empty()),
+            ("flags", &gio::ApplicationFlags::HANDLES_OPEN),
             (
                 "resource-base-path",
                 &Some("/com/github/melix99/telegrand/"),
@@ -137,6 +167,50 @@ impl Application {
             app.main_window().session_manager().add_new_session(true);
         }));
         self.add_action(&action_new_login_test_server);
+
+        // Open the chat of a notification
+        let action_open_chat =
+            gio::SimpleAction::new("open-chat", Some(&<(i32, i64)>::static_variant_type()));
+        action_open_chat.connect_activate(clone!(@weak self as app => move |_, parameter| {
+            let (client_id, chat_id) = parameter.and_then(|p| p.get()).unwrap();
+            app.activate();
+            app.main_window().session_manager().select_chat(client_id, chat_id);
+        }));
+        self.add_action(&action_open_chat);
+
+        // Mark the chat of a notification as read
+        let action_mark_chat_as_read = gio::SimpleAction::new(
+            "mark-chat-as-read",
+            Some(&<(i32, i64, i32)>::static_variant_type()),
+        );
+        action_mark_chat_as_read.connect_activate(
+            clone!(@weak self as app => move |_, parameter| {
+                let (client_id, chat_id, notification_id) =
+                    parameter.and_then(|p| p.get()).unwrap();
+                if let Some(chat) = app.logged_in_chat(client_id, chat_id) {
+                    chat.mark_as_read();
+                }
+                app.withdraw_notification(&notification_id.to_string());
+            }),
+        );
+        self.add_action(&action_mark_chat_as_read);
+
+        // Reply to the chat of a notification
+        let action_reply_to_chat = gio::SimpleAction::new(
+            "reply-to-chat",
+            Some(&<(i32, i64, i32)>::static_variant_type()),
+        );
+        action_reply_to_chat.connect_activate(clone!(@weak self as app => move |_, parameter| {
+            let (client_id, chat_id, notification_id) = parameter.and_then(|p| p.get()).unwrap();
+            if let Some(chat) = app.logged_in_chat(client_id, chat_id) {
+                app.show_reply_dialog(&chat, notification_id);
+            }
+        }));
+        self.add_action(&action_reply_to_chat);
+
+        // Do not disturb
+        let settings = gio::Settings::new(APP_ID);
+        self.add_action(&settings.create_action("do-not-disturb"));
     }
 
     // Sets up keyboard shortcuts
@@ -154,8 +228,161 @@ impl Application {
         }
     }
 
+    /// Returns the chat with the given id of the session of the given client id, if it's logged in.
+    fn logged_in_chat(&self, client_id: i32, chat_id: i64) -> Option<Chat> {
+        // The window may not exist yet if the application was started by a notification
+        self.imp()
+            .window
+            .get()
+            .and_then(|window| window.upgrade())?
+            .session_manager()
+            .client(client_id)
+            .filter(|client| matches!(client.state, ClientState::LoggedIn))
+            .map(|client| client.session.chat_list().get(chat_id))
+    }
+
+    /// Shows a small dialog to reply to the chat without having to open the main window. The
+    /// notification is withdrawn once the reply is sent.
+    fn show_reply_dialog(&self, chat: &Chat, notification_id: i32) {
+        let entry = gtk::Entry::builder()
+            .placeholder_text(&gettext("Message"))
+            .activates_default(true)
+            .build();
+
+        let dialog = gtk::MessageDialog::builder()
+            .application(self)
+            .transient_for(&self.main_window())
+            .modal(true)
+            .text(&gettext!("Reply to {}", chat.title()))
+            .buttons(gtk::ButtonsType::Cancel)
+            .build();
+        dialog.message_area().append(&entry);
+
+        let send_button = gtk::Button::builder()
+            .use_underline(true)
+            .label(&gettext("_Send"))
+            .css_classes(vec!["suggested-action".to_string()])
+            .build();
+        dialog.add_action_widget(&send_button, gtk::ResponseType::Accept);
+        dialog.set_default_widget(Some(&send_button));
+
+        dialog.run_async(
+            clone!(@weak self as app, @weak chat, @weak entry => move |dialog, response_id| {
+                dialog.close();
+
+                let text = entry.text().trim().to_string();
+                if response_id != gtk::ResponseType::Accept || text.is_empty() {
+                    return;
+                }
+
+                let client_id = chat.session().client_id();
+                let chat_id = chat.id();
+                let content = InputMessageContent::InputMessageText(types::InputMessageText {
+                    text: types::FormattedText {
+                        text,
+                        ..Default::default()
+                    },
+                    clear_draft: true,
+                    ..Default::default()
+                });
+
+                spawn!(clone!(@weak app => async move {
+                    let result = functions::SendMessage::new()
+                        .chat_id(chat_id)
+                        .input_message_content(content)
+                        .send(client_id)
+                        .await;
+
+                    match result {
+                        Ok(_) => app.withdraw_notification(&notification_id.to_string()),
+                        Err(e) => log::warn!("Failed to send the reply: {:?}", e),
+                    }
+                }));
+            }),
+        );
+    }
+
+    /// Opens a `tg://proxy` or `tg://socks` link in the active session or, if no session is
+    /// logged in yet, as soon as one is. Only the last link is kept and it's discarded if no
+    /// session is logged in within `PENDING_LINK_TIMEOUT`.
+    fn open_link(&self, uri: String) {
+        let lowercase_uri = uri.to_lowercase();
+        let is_proxy_link = lowercase_uri
+            .strip_prefix("tg://")
+            .and_then(|link| link.split(&['/', '?'][..]).next())
+            .map_or(false, |host| matches!(host, "proxy" | "socks"));
+        if !is_proxy_link {
+            log::warn!("Unsupported link: {}", uri);
+            return;
+        }
+
+        let imp = self.imp();
+        imp.pending_link.replace(Some(uri));
+
+        if let Some(source_id) = imp.pending_link_source_id.take() {
+            source_id.remove();
+        }
+        let source_id = glib::timeout_add_local_once(
+            PENDING_LINK_TIMEOUT,
+            clone!(@weak self as app => move || {
+                let imp = app.imp();
+                imp.pending_link_source_id.take();
+                if let Some(uri) = imp.pending_link.take() {
+                    log::warn!("No session was logged in to open the link: {}", uri);
+                }
+            }),
+        );
+        imp.pending_link_source_id.replace(Some(source_id));
+
+        self.open_pending_link();
+    }
+
+    fn open_pending_link(&self) {
+        let client_id = match self
+            .main_window()
+            .session_manager()
+            .active_logged_in_client_id()
+        {
+            Some(client_id) => client_id,
+            None => return,
+        };
+
+        let imp = self.imp();
+        if let Some(uri) = imp.pending_link.take() {
+            if let Some(source_id) = imp.pending_link_source_id.take() {
+                source_id.remove();
+            }
+
+            spawn!(clone!(@weak self as app => async move {
+                app.handle_link(client_id, uri).await;
+            }));
+        }
+    }
+
+    async fn handle_link(&self, client_id: i32, uri: String) {
+        let result = functions::GetInternalLinkType::new()
+            .link(uri.clone())
+            .send(client_id)
+            .await;
+
+        match result {
+            Ok(InternalLinkType::Proxy(data)) => {
+                self.open_proxy_window(client_id, &data.server, data.port, &data.r#type)
+            }
+            Ok(_) => log::warn!("Unsupported link: {}", uri),
+            Err(e) => log::warn!("Failed to get the type of the link {}: {:?}", uri, e),
+        }
+    }
+
+    /// Opens the proxy settings of the session, with the proxy of a link filled in.
+    fn open_proxy_window(&self, client_id: i32, server: &str, port: i32, r#type: &ProxyType) {
+        let proxy_window = ProxyWindow::new(&Some(self.main_window().upcast()), client_id);
+        proxy_window.set_new_proxy(server, port, r#type);
+        proxy_window.present();
+    }
+
     fn show_preferences(&self) {
-        let preferences = PreferencesWindow::new();
+        let preferences = PreferencesWindow::new(self.main_window().session_manager());
         preferences.set_transient_for(Some(&self.main_window()));
         preferences.present();
     }
@@ -9,6 +9,7 @@ use tdgrand::{functions, types};
 use crate::session::Session;
 use crate::session_manager::SessionManager;
 use crate::utils::{do_async, log_out, parse_formatted_text, send_tdlib_parameters};
+use crate::ProxyWindow;
 
 mod imp {
     use super::*;
@@ -100,6 +101,9 @@ mod imp {
             klass.install_action("login.use-qr-code", None, move |widget, _, _| {
                 widget.request_qr_code();
             });
+            klass.install_action("login.show-proxy-window", None, move |widget, _, _| {
+                widget.show_proxy_window();
+            });
             klass.install_action(
                 "login.go-to-forgot-password-page",
                 None,
@@ -436,6 +440,10 @@ impl Login {
         self.action_set_enabled("login.previous", is_previous_valid);
         self.action_set_enabled("login.next", is_next_valid);
         self.action_set_enabled("login.use-qr-code", visible_page == "phone-number-page");
+        self.action_set_enabled(
+            "login.show-proxy-window",
+            visible_page == "phone-number-page",
+        );
         self.action_set_enabled(
             "login.go-to-forgot-password-page",
             visible_page == "password-page",
@@ -567,6 +575,14 @@ impl Login {
             .add_new_session(use_test_dc);
     }
 
+    /// Shows the proxy settings of the client, so that a proxy can be set up before sending the
+    /// phone number.
+    fn show_proxy_window(&self) {
+        let parent_window = self.root().and_then(|root| root.downcast().ok());
+        let proxy_window = ProxyWindow::new(&parent_window, self.imp().client_id.get());
+        proxy_window.present();
+    }
+
     fn show_tos_dialog(&self, user_needs_to_accept: bool) {
         let builder = gtk::MessageDialog::builder()
             .use_markup(true)
@@ -603,6 +619,7 @@ impl Login {
         self.action_set_enabled("login.previous", false);
         self.action_set_enabled("login.next", false);
         self.action_set_enabled("login.use-qr-code", false);
+        self.action_set_enabled("login.show-proxy-window", false);
         self.action_set_enabled("login.go-to-forgot-password-page", false);
         self.action_set_enabled("login.recover-password", false);
         self.action_set_enabled("login.show-no-email-access-dialog", false);
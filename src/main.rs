@@ -3,7 +3,9 @@ mod application;
 #[allow(clippy::all)]
 mod config;
 mod login;
+mod notification_policy;
 mod preferences_window;
+mod proxy_window;
 mod session;
 mod session_manager;
 mod utils;
@@ -12,6 +14,7 @@ mod window;
 use self::application::Application;
 use self::login::Login;
 use self::preferences_window::PreferencesWindow;
+use self::proxy_window::ProxyWindow;
 use self::session::Session;
 use self::window::Window;
 
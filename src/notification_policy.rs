@@ -0,0 +1,64 @@
+use gtk::prelude::*;
+use gtk::{gio, glib};
+
+use crate::session::Chat;
+
+/// Returns whether the notifications of the chat should be shown. These are suppressed if the
+/// chat is muted, if it's the chat currently visible to the user, if its session isn't the active
+/// one and only the active session should notify or if do not disturb is active.
+pub fn should_notify(
+    chat: &Chat,
+    is_chat_visible: bool,
+    is_session_active: bool,
+    settings: &gio::Settings,
+) -> bool {
+    !is_chat_visible
+        && (is_session_active || settings.boolean("notifications-all-sessions"))
+        && !chat.is_muted()
+        && !is_do_not_disturb_active(settings)
+}
+
+/// Returns whether the notifications of the chat should show the content of the messages.
+pub fn should_show_preview(chat: &Chat, settings: &gio::Settings) -> bool {
+    if !settings.boolean("notifications-show-body") {
+        return false;
+    }
+
+    let notification_settings = chat.notification_settings().0;
+    if notification_settings.use_default_show_preview {
+        if let Some(scope_settings) = chat.session().scope_notification_settings(chat.type_()) {
+            return scope_settings.0.show_preview;
+        }
+    }
+
+    notification_settings.show_preview
+}
+
+/// Returns whether the notifications should show the name and the picture of the chat.
+pub fn should_show_sender(settings: &gio::Settings) -> bool {
+    settings.boolean("notifications-show-sender")
+}
+
+/// Returns whether do not disturb is active, either because it's enabled or because the current
+/// time is in the do not disturb hours of its schedule.
+pub fn is_do_not_disturb_active(settings: &gio::Settings) -> bool {
+    if settings.boolean("do-not-disturb") {
+        return true;
+    }
+
+    if !settings.boolean("do-not-disturb-scheduled") {
+        return false;
+    }
+
+    let now = glib::DateTime::now_local().unwrap();
+    let minutes = now.hour() * 60 + now.minute();
+    let start = settings.int("do-not-disturb-start");
+    let end = settings.int("do-not-disturb-end");
+
+    if start <= end {
+        (start..end).contains(&minutes)
+    } else {
+        // The schedule goes past midnight
+        minutes >= start || minutes < end
+    }
+}
@@ -1,21 +1,68 @@
+use adw::prelude::{ActionRowExt, ComboRowExt, PreferencesGroupExt, PreferencesPageExt};
+use gettextrs::gettext;
 use glib::clone;
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
 use gtk::{gio, glib, CompositeTemplate};
+use tdgrand::enums::NotificationSettingsScope;
+use tdgrand::functions;
 
 use crate::config::APP_ID;
+use crate::session::{BoxedScopeNotificationSettings, User};
+use crate::session_manager::SessionManager;
+use crate::utils::MUTE_FOREVER_DURATION;
+use crate::{ProxyWindow, Session, RUNTIME};
+
+/// The rows of the notification settings of a scope of chats
+#[derive(Debug)]
+pub struct ScopeRows {
+    scope: NotificationSettingsScope,
+    /// The name of the `Session` property holding the notification settings of the scope
+    property_name: &'static str,
+    notifications_switch: gtk::Switch,
+    preview_switch: gtk::Switch,
+    sound_switch: gtk::Switch,
+    group: adw::PreferencesGroup,
+}
 
 mod imp {
     use super::*;
     use adw::subclass::prelude::*;
+    use once_cell::sync::{Lazy, OnceCell};
+    use std::cell::{Cell, RefCell};
 
     #[derive(Debug, Default, CompositeTemplate)]
     #[template(resource = "/com/github/melix99/telegrand/ui/preferences-window.ui")]
     pub struct PreferencesWindow {
+        pub session_manager: OnceCell<SessionManager>,
+        pub scope_rows: OnceCell<Vec<ScopeRows>>,
+        /// The session whose notification settings are shown, with its signal handlers
+        pub session: RefCell<Option<(Session, Vec<glib::SignalHandlerId>)>>,
+        pub is_updating_scope_rows: Cell<bool>,
         #[template_child]
         pub follow_system_colors_switch: TemplateChild<gtk::Switch>,
         #[template_child]
         pub dark_theme_switch: TemplateChild<gtk::Switch>,
+        #[template_child]
+        pub notifications_page: TemplateChild<adw::PreferencesPage>,
+        #[template_child]
+        pub do_not_disturb_switch: TemplateChild<gtk::Switch>,
+        #[template_child]
+        pub do_not_disturb_schedule_row: TemplateChild<adw::ExpanderRow>,
+        #[template_child]
+        pub do_not_disturb_start_spin_button: TemplateChild<gtk::SpinButton>,
+        #[template_child]
+        pub do_not_disturb_end_spin_button: TemplateChild<gtk::SpinButton>,
+        #[template_child]
+        pub show_sender_switch: TemplateChild<gtk::Switch>,
+        #[template_child]
+        pub show_body_switch: TemplateChild<gtk::Switch>,
+        #[template_child]
+        pub all_sessions_switch: TemplateChild<gtk::Switch>,
+        #[template_child]
+        pub session_combo_row: TemplateChild<adw::ComboRow>,
+        #[template_child]
+        pub proxy_session_list: TemplateChild<gtk::ListBox>,
     }
 
     #[glib::object_subclass]
@@ -34,6 +81,39 @@ mod imp {
     }
 
     impl ObjectImpl for PreferencesWindow {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                vec![glib::ParamSpecObject::new(
+                    "session-manager",
+                    "Session Manager",
+                    "The session manager holding the sessions to configure",
+                    SessionManager::static_type(),
+                    glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
+                )]
+            });
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(
+            &self,
+            _obj: &Self::Type,
+            _id: usize,
+            value: &glib::Value,
+            pspec: &glib::ParamSpec,
+        ) {
+            match pspec.name() {
+                "session-manager" => self.session_manager.set(value.get().unwrap()).unwrap(),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn property(&self, obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            match pspec.name() {
+                "session-manager" => obj.session_manager().to_value(),
+                _ => unimplemented!(),
+            }
+        }
+
         fn constructed(&self, obj: &Self::Type) {
             self.parent_constructed(obj);
 
@@ -50,6 +130,16 @@ mod imp {
             }
 
             obj.setup_bindings();
+            obj.setup_notifications();
+            obj.setup_proxy();
+        }
+
+        fn dispose(&self, _obj: &Self::Type) {
+            if let Some((session, handler_ids)) = self.session.take() {
+                for handler_id in handler_ids {
+                    session.disconnect(handler_id);
+                }
+            }
         }
     }
 
@@ -64,15 +154,14 @@ glib::wrapper! {
         @extends gtk::Widget, gtk::Window, adw::Window, adw::PreferencesWindow;
 }
 
-impl Default for PreferencesWindow {
-    fn default() -> Self {
-        Self::new()
+impl PreferencesWindow {
+    pub fn new(session_manager: &SessionManager) -> Self {
+        glib::Object::new(&[("session-manager", session_manager)])
+            .expect("Failed to create PreferencesWindow")
     }
-}
 
-impl PreferencesWindow {
-    pub fn new() -> Self {
-        glib::Object::new(&[]).expect("Failed to create PreferencesWindow")
+    pub fn session_manager(&self) -> &SessionManager {
+        self.imp().session_manager.get().unwrap()
     }
 
     fn setup_bindings(&self) {
@@ -128,4 +217,272 @@ impl PreferencesWindow {
             .flags(glib::BindingFlags::SYNC_CREATE)
             .build();
     }
+
+    fn setup_notifications(&self) {
+        let imp = self.imp();
+        let settings = gio::Settings::new(APP_ID);
+
+        settings
+            .bind("do-not-disturb", &*imp.do_not_disturb_switch, "active")
+            .build();
+        settings
+            .bind(
+                "do-not-disturb-scheduled",
+                &*imp.do_not_disturb_schedule_row,
+                "enable-expansion",
+            )
+            .build();
+        settings
+            .bind(
+                "notifications-show-sender",
+                &*imp.show_sender_switch,
+                "active",
+            )
+            .build();
+        settings
+            .bind("notifications-show-body", &*imp.show_body_switch, "active")
+            .build();
+        settings
+            .bind(
+                "notifications-all-sessions",
+                &*imp.all_sessions_switch,
+                "active",
+            )
+            .build();
+
+        for (spin_button, key) in [
+            (
+                &*imp.do_not_disturb_start_spin_button,
+                "do-not-disturb-start",
+            ),
+            (&*imp.do_not_disturb_end_spin_button, "do-not-disturb-end"),
+        ] {
+            // Show the minutes since midnight as a time of the day
+            spin_button.connect_output(|spin_button| {
+                let minutes = spin_button.value_as_int();
+                spin_button.set_text(&format!("{:02}:{:02}", minutes / 60, minutes % 60));
+                gtk::Inhibit(true)
+            });
+            spin_button.connect_input(|spin_button| {
+                let text = spin_button.text();
+                let (hours, minutes) = text.split_once(':')?;
+                match (hours.trim().parse::<u32>(), minutes.trim().parse::<u32>()) {
+                    (Ok(hours), Ok(minutes)) if hours < 24 && minutes < 60 => {
+                        Some(Ok((hours * 60 + minutes) as f64))
+                    }
+                    _ => Some(Err(())),
+                }
+            });
+
+            spin_button.set_value(settings.int(key) as f64);
+            spin_button.connect_value_changed(clone!(@strong settings => move |spin_button| {
+                settings.set_int(key, spin_button.value_as_int()).unwrap();
+            }));
+        }
+
+        // Notification settings of the scopes of chats of the selected session
+        let scope_rows = [
+            (
+                NotificationSettingsScope::PrivateChats,
+                "private-chats-notification-settings",
+                gettext("Private Chats"),
+            ),
+            (
+                NotificationSettingsScope::GroupChats,
+                "group-chats-notification-settings",
+                gettext("Groups"),
+            ),
+            (
+                NotificationSettingsScope::ChannelChats,
+                "channel-chats-notification-settings",
+                gettext("Channels"),
+            ),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(index, (scope, property_name, title))| {
+            let group = adw::PreferencesGroup::builder().title(&title).build();
+            imp.notifications_page.add(&group);
+
+            let add_switch_row = |title: &str| {
+                let switch = gtk::Switch::builder().valign(gtk::Align::Center).build();
+                switch.connect_active_notify(clone!(@weak self as obj => move |_| {
+                    obj.set_scope_notification_settings(index);
+                }));
+
+                let row = adw::ActionRow::builder()
+                    .title(title)
+                    .activatable_widget(&switch)
+                    .build();
+                row.add_suffix(&switch);
+                group.add(&row);
+
+                switch
+            };
+
+            ScopeRows {
+                scope,
+                property_name,
+                notifications_switch: add_switch_row(&gettext("Notifications")),
+                preview_switch: add_switch_row(&gettext("Message Preview")),
+                sound_switch: add_switch_row(&gettext("Sound")),
+                group,
+            }
+        })
+        .collect();
+        imp.scope_rows.set(scope_rows).unwrap();
+
+        let me_expression =
+            gtk::StackPage::this_expression("child").chain_property::<Session>("me");
+        imp.session_combo_row
+            .set_expression(Some(&User::full_name_expression(&me_expression.upcast())));
+        imp.session_combo_row
+            .set_model(Some(&self.session_manager().sessions()));
+        imp.session_combo_row
+            .connect_selected_item_notify(clone!(@weak self as obj => move |_| {
+                obj.on_selected_session_changed();
+            }));
+        self.on_selected_session_changed();
+    }
+
+    fn setup_proxy(&self) {
+        // A row for each session, opening the proxy settings of its client
+        self.imp().proxy_session_list.bind_model(
+            Some(&self.session_manager().sessions()),
+            clone!(@weak self as obj => @default-panic, move |item| {
+                let session = item
+                    .downcast_ref::<gtk::StackPage>()
+                    .unwrap()
+                    .child()
+                    .downcast::<Session>()
+                    .unwrap();
+                let me = session.me();
+
+                let row = adw::ActionRow::builder()
+                    .title(format!("{} {}", me.first_name(), me.last_name()).trim())
+                    .activatable(true)
+                    .build();
+                row.add_suffix(&gtk::Image::from_icon_name("go-next-symbolic"));
+                row.connect_activated(clone!(@weak obj => move |_| {
+                    let parent_window = Some(obj.clone().upcast());
+                    ProxyWindow::new(&parent_window, session.client_id()).present();
+                }));
+
+                row.upcast()
+            }),
+        );
+    }
+
+    fn selected_session(&self) -> Option<Session> {
+        self.imp()
+            .session_combo_row
+            .selected_item()
+            .and_then(|page| page.downcast::<gtk::StackPage>().ok())
+            .and_then(|page| page.child().downcast::<Session>().ok())
+    }
+
+    fn on_selected_session_changed(&self) {
+        let imp = self.imp();
+
+        if let Some((session, handler_ids)) = imp.session.take() {
+            for handler_id in handler_ids {
+                session.disconnect(handler_id);
+            }
+        }
+
+        if let Some(session) = self.selected_session() {
+            let handler_ids = imp
+                .scope_rows
+                .get()
+                .unwrap()
+                .iter()
+                .map(|rows| {
+                    session.connect_notify_local(
+                        Some(rows.property_name),
+                        clone!(@weak self as obj => move |_, _| {
+                            obj.update_scope_rows();
+                        }),
+                    )
+                })
+                .collect();
+
+            imp.session.replace(Some((session, handler_ids)));
+        }
+
+        self.update_scope_rows();
+    }
+
+    fn update_scope_rows(&self) {
+        let imp = self.imp();
+        let session = self.selected_session();
+
+        imp.is_updating_scope_rows.set(true);
+
+        for rows in imp.scope_rows.get().unwrap() {
+            let settings = session.as_ref().and_then(|session| {
+                session.property::<Option<BoxedScopeNotificationSettings>>(rows.property_name)
+            });
+
+            rows.group.set_sensitive(settings.is_some());
+
+            if let Some(settings) = settings {
+                rows.notifications_switch
+                    .set_active(settings.0.mute_for == 0);
+                rows.preview_switch.set_active(settings.0.show_preview);
+                rows.sound_switch.set_active(!settings.0.sound.is_empty());
+            }
+        }
+
+        imp.is_updating_scope_rows.set(false);
+    }
+
+    fn set_scope_notification_settings(&self, index: usize) {
+        let imp = self.imp();
+        if imp.is_updating_scope_rows.get() {
+            return;
+        }
+
+        let session = match self.selected_session() {
+            Some(session) => session,
+            None => return,
+        };
+        let rows = &imp.scope_rows.get().unwrap()[index];
+        let old_settings =
+            match session.property::<Option<BoxedScopeNotificationSettings>>(rows.property_name) {
+                Some(settings) => settings.0,
+                None => return,
+            };
+
+        let mut settings = old_settings.clone();
+        if rows.notifications_switch.is_active() != (settings.mute_for == 0) {
+            settings.mute_for = if rows.notifications_switch.is_active() {
+                0
+            } else {
+                MUTE_FOREVER_DURATION
+            };
+        }
+        settings.show_preview = rows.preview_switch.is_active();
+        settings.sound = if rows.sound_switch.is_active() {
+            "default".to_string()
+        } else {
+            String::new()
+        };
+
+        if settings == old_settings {
+            return;
+        }
+
+        let client_id = session.client_id();
+        let scope = rows.scope.clone();
+        RUNTIME.spawn(async move {
+            if let Err(e) = functions::SetScopeNotificationSettings::new()
+                .scope(scope)
+                .notification_settings(settings)
+                .send(client_id)
+                .await
+            {
+                log::warn!("Failed to update the notification settings: {:?}", e);
+            }
+        });
+    }
 }
@@ -0,0 +1,361 @@
+use adw::prelude::{ActionRowExt, ComboRowExt};
+use gettextrs::gettext;
+use glib::clone;
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+use gtk::{glib, CompositeTemplate};
+use tdgrand::enums::{self, ProxyType};
+use tdgrand::{functions, types};
+
+use crate::spawn;
+
+/// The positions of the proxy types in the type combo row
+const SOCKS5_POSITION: u32 = 0;
+const HTTP_POSITION: u32 = 1;
+const MTPROTO_POSITION: u32 = 2;
+
+mod imp {
+    use super::*;
+    use adw::subclass::prelude::AdwWindowImpl;
+    use once_cell::sync::Lazy;
+    use std::cell::Cell;
+
+    #[derive(Debug, Default, CompositeTemplate)]
+    #[template(resource = "/com/github/melix99/telegrand/ui/proxy-window.ui")]
+    pub struct ProxyWindow {
+        pub client_id: Cell<i32>,
+        #[template_child]
+        pub proxy_list: TemplateChild<gtk::ListBox>,
+        #[template_child]
+        pub type_combo_row: TemplateChild<adw::ComboRow>,
+        #[template_child]
+        pub server_entry: TemplateChild<gtk::Entry>,
+        #[template_child]
+        pub port_spin_button: TemplateChild<gtk::SpinButton>,
+        #[template_child]
+        pub username_row: TemplateChild<adw::ActionRow>,
+        #[template_child]
+        pub username_entry: TemplateChild<gtk::Entry>,
+        #[template_child]
+        pub password_row: TemplateChild<adw::ActionRow>,
+        #[template_child]
+        pub password_entry: TemplateChild<gtk::PasswordEntry>,
+        #[template_child]
+        pub secret_row: TemplateChild<adw::ActionRow>,
+        #[template_child]
+        pub secret_entry: TemplateChild<gtk::Entry>,
+        #[template_child]
+        pub error_label: TemplateChild<gtk::Label>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for ProxyWindow {
+        const NAME: &'static str = "ProxyWindow";
+        type Type = super::ProxyWindow;
+        type ParentType = adw::Window;
+
+        fn class_init(klass: &mut Self::Class) {
+            Self::bind_template(klass);
+
+            klass.install_action("proxy-window.add-proxy", None, move |widget, _, _| {
+                widget.add_proxy();
+            });
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for ProxyWindow {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                vec![glib::ParamSpecInt::new(
+                    "client-id",
+                    "Client Id",
+                    "The id of the client whose proxies are managed",
+                    std::i32::MIN,
+                    std::i32::MAX,
+                    0,
+                    glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
+                )]
+            });
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(
+            &self,
+            _obj: &Self::Type,
+            _id: usize,
+            value: &glib::Value,
+            pspec: &glib::ParamSpec,
+        ) {
+            match pspec.name() {
+                "client-id" => self.client_id.set(value.get().unwrap()),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn property(&self, obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            match pspec.name() {
+                "client-id" => obj.client_id().to_value(),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn constructed(&self, obj: &Self::Type) {
+            self.parent_constructed(obj);
+
+            // Only show the fields used by the selected proxy type
+            self.type_combo_row
+                .connect_selected_notify(clone!(@weak obj => move |_| {
+                    obj.update_type_rows();
+                }));
+            obj.update_type_rows();
+
+            obj.load_proxies();
+        }
+    }
+
+    impl WidgetImpl for ProxyWindow {}
+    impl WindowImpl for ProxyWindow {}
+    impl AdwWindowImpl for ProxyWindow {}
+}
+
+glib::wrapper! {
+    pub struct ProxyWindow(ObjectSubclass<imp::ProxyWindow>)
+        @extends gtk::Widget, gtk::Window, adw::Window;
+}
+
+impl ProxyWindow {
+    pub fn new(parent_window: &Option<gtk::Window>, client_id: i32) -> Self {
+        glib::Object::new(&[("transient-for", parent_window), ("client-id", &client_id)])
+            .expect("Failed to create ProxyWindow")
+    }
+
+    pub fn client_id(&self) -> i32 {
+        self.imp().client_id.get()
+    }
+
+    /// Fills the fields of the new proxy, like with the ones of a proxy link.
+    pub fn set_new_proxy(&self, server: &str, port: i32, r#type: &ProxyType) {
+        let imp = self.imp();
+
+        imp.server_entry.set_text(server);
+        imp.port_spin_button.set_value(port as f64);
+
+        match r#type {
+            ProxyType::Socks5(data) => {
+                imp.type_combo_row.set_selected(SOCKS5_POSITION);
+                imp.username_entry.set_text(&data.username);
+                imp.password_entry.set_text(&data.password);
+            }
+            ProxyType::Http(data) => {
+                imp.type_combo_row.set_selected(HTTP_POSITION);
+                imp.username_entry.set_text(&data.username);
+                imp.password_entry.set_text(&data.password);
+            }
+            ProxyType::Mtproto(data) => {
+                imp.type_combo_row.set_selected(MTPROTO_POSITION);
+                imp.secret_entry.set_text(&data.secret);
+            }
+        }
+    }
+
+    fn update_type_rows(&self) {
+        let imp = self.imp();
+        let is_mtproto = imp.type_combo_row.selected() == MTPROTO_POSITION;
+
+        imp.username_row.set_visible(!is_mtproto);
+        imp.password_row.set_visible(!is_mtproto);
+        imp.secret_row.set_visible(is_mtproto);
+    }
+
+    fn load_proxies(&self) {
+        let client_id = self.client_id();
+        spawn!(clone!(@weak self as obj => async move {
+            match functions::GetProxies::new().send(client_id).await {
+                Ok(enums::Proxies::Proxies(data)) => obj.set_proxies(data.proxies),
+                Err(e) => log::warn!("Failed to get the proxies: {:?}", e),
+            }
+        }));
+    }
+
+    fn set_proxies(&self, proxies: Vec<types::Proxy>) {
+        let proxy_list = &*self.imp().proxy_list;
+        while let Some(row) = proxy_list.first_child() {
+            proxy_list.remove(&row);
+        }
+
+        let no_proxy_check_button = gtk::CheckButton::builder()
+            .valign(gtk::Align::Center)
+            .active(!proxies.iter().any(|proxy| proxy.is_enabled))
+            .build();
+        no_proxy_check_button.connect_toggled(clone!(@weak self as obj => move |button| {
+            if button.is_active() {
+                obj.disable_proxy();
+            }
+        }));
+
+        let row = adw::ActionRow::builder()
+            .title(&gettext("No Proxy"))
+            .activatable_widget(&no_proxy_check_button)
+            .build();
+        row.add_prefix(&no_proxy_check_button);
+        proxy_list.append(&row);
+
+        for proxy in proxies {
+            let proxy_id = proxy.id;
+            let type_name = match proxy.r#type {
+                ProxyType::Socks5(_) => "SOCKS5",
+                ProxyType::Http(_) => "HTTP",
+                ProxyType::Mtproto(_) => "MTProto",
+            };
+
+            let check_button = gtk::CheckButton::builder()
+                .valign(gtk::Align::Center)
+                .active(proxy.is_enabled)
+                .build();
+            check_button.set_group(Some(&no_proxy_check_button));
+            check_button.connect_toggled(clone!(@weak self as obj => move |button| {
+                if button.is_active() {
+                    obj.enable_proxy(proxy_id);
+                }
+            }));
+
+            let remove_button = gtk::Button::builder()
+                .icon_name("user-trash-symbolic")
+                .tooltip_text(&gettext("Remove"))
+                .valign(gtk::Align::Center)
+                .css_classes(vec!["flat".to_string()])
+                .build();
+            remove_button.connect_clicked(clone!(@weak self as obj => move |_| {
+                obj.remove_proxy(proxy_id);
+            }));
+
+            let row = adw::ActionRow::builder()
+                .title(&format!("{}:{}", proxy.server, proxy.port))
+                .subtitle(type_name)
+                .activatable_widget(&check_button)
+                .build();
+            row.add_prefix(&check_button);
+            row.add_suffix(&remove_button);
+            proxy_list.append(&row);
+
+            self.ping_proxy(proxy_id, &row, type_name);
+        }
+    }
+
+    /// Shows the response time of the proxy in the subtitle of its row.
+    fn ping_proxy(&self, proxy_id: i32, row: &adw::ActionRow, type_name: &'static str) {
+        let client_id = self.client_id();
+        spawn!(clone!(@weak row => async move {
+            let result = functions::PingProxy::new()
+                .proxy_id(proxy_id)
+                .send(client_id)
+                .await;
+
+            let status = match result {
+                Ok(enums::Seconds::Seconds(data)) => {
+                    gettext!("{} ms", (data.seconds * 1000.0).round() as i64)
+                }
+                Err(_) => gettext("Unavailable"),
+            };
+            row.set_subtitle(&format!("{} · {}", type_name, status));
+        }));
+    }
+
+    fn enable_proxy(&self, proxy_id: i32) {
+        let client_id = self.client_id();
+        spawn!(clone!(@weak self as obj => async move {
+            let result = functions::EnableProxy::new()
+                .proxy_id(proxy_id)
+                .send(client_id)
+                .await;
+
+            if let Err(e) = result {
+                log::warn!("Failed to enable the proxy: {:?}", e);
+                obj.load_proxies();
+            }
+        }));
+    }
+
+    fn disable_proxy(&self) {
+        let client_id = self.client_id();
+        spawn!(clone!(@weak self as obj => async move {
+            if let Err(e) = functions::DisableProxy::new().send(client_id).await {
+                log::warn!("Failed to disable the proxy: {:?}", e);
+                obj.load_proxies();
+            }
+        }));
+    }
+
+    fn remove_proxy(&self, proxy_id: i32) {
+        let client_id = self.client_id();
+        spawn!(clone!(@weak self as obj => async move {
+            let result = functions::RemoveProxy::new()
+                .proxy_id(proxy_id)
+                .send(client_id)
+                .await;
+
+            if let Err(e) = result {
+                log::warn!("Failed to remove the proxy: {:?}", e);
+            }
+
+            obj.load_proxies();
+        }));
+    }
+
+    fn add_proxy(&self) {
+        let imp = self.imp();
+
+        let server = imp.server_entry.text().trim().to_string();
+        if server.is_empty() {
+            imp.server_entry.grab_focus();
+            return;
+        }
+
+        let port = imp.port_spin_button.value_as_int();
+        let username = imp.username_entry.text().to_string();
+        let password = imp.password_entry.text().to_string();
+        let r#type = match imp.type_combo_row.selected() {
+            SOCKS5_POSITION => ProxyType::Socks5(types::ProxyTypeSocks5 { username, password }),
+            HTTP_POSITION => ProxyType::Http(types::ProxyTypeHttp {
+                username,
+                password,
+                http_only: false,
+            }),
+            _ => ProxyType::Mtproto(types::ProxyTypeMtproto {
+                secret: imp.secret_entry.text().trim().to_string(),
+            }),
+        };
+
+        let client_id = self.client_id();
+        spawn!(clone!(@weak self as obj => async move {
+            let result = functions::AddProxy::new()
+                .server(server)
+                .port(port)
+                .enable(true)
+                .r#type(r#type)
+                .send(client_id)
+                .await;
+
+            let imp = obj.imp();
+            match result {
+                Ok(_) => {
+                    imp.error_label.set_visible(false);
+                    imp.server_entry.set_text("");
+                    imp.username_entry.set_text("");
+                    imp.password_entry.set_text("");
+                    imp.secret_entry.set_text("");
+                }
+                Err(e) => {
+                    imp.error_label.set_label(&e.message);
+                    imp.error_label.set_visible(true);
+                }
+            }
+
+            obj.load_proxies();
+        }));
+    }
+}
@@ -1,10 +1,10 @@
 use glib::clone;
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
-use gtk::{gdk, gio, glib};
+use gtk::{gdk, glib};
 use tdgrand::types::{ChatPhotoInfo, File, ProfilePhoto};
 
-use crate::Session;
+use crate::{spawn, Session};
 
 mod imp {
     use super::*;
@@ -118,10 +118,33 @@ impl Avatar {
 
         if let Some(file) = &*self.imp().image_file.borrow() {
             if file.local.is_downloading_completed {
-                let gfile = gio::File::for_path(&file.local.path);
-                let texture = gdk::Texture::from_file(&gfile).unwrap();
-
-                self.set_image(Some(texture.upcast()));
+                let file_id = file.id;
+                let path = file.local.path.clone();
+
+                spawn!(clone!(@weak self as obj => async move {
+                    let result = obj.session().load_image(file_id, &path, None).await;
+
+                    // Check that the image hasn't been changed in the meantime
+                    let is_current_file = obj
+                        .imp()
+                        .image_file
+                        .borrow()
+                        .as_ref()
+                        .map(|file| file.id == file_id)
+                        .unwrap_or_default();
+                    if !is_current_file {
+                        return;
+                    }
+
+                    match result {
+                        Ok(texture) => obj.set_image(Some(texture.upcast())),
+                        Err(e) => {
+                            // Fall back to the initials of the name
+                            log::warn!("Failed to load an avatar: {}", e);
+                            obj.set_image(None);
+                        }
+                    }
+                }));
             } else if file.local.can_be_downloaded && !file.local.is_downloading_active {
                 let (sender, receiver) =
                     glib::MainContext::sync_channel::<File>(Default::default(), 5);
@@ -151,6 +174,16 @@ impl Avatar {
         }
     }
 
+    /// Return the path of the image file, if it's already downloaded.
+    pub fn image_path(&self) -> Option<String> {
+        self.imp()
+            .image_file
+            .borrow()
+            .as_ref()
+            .filter(|file| file.local.is_downloading_completed)
+            .map(|file| file.local.path.clone())
+    }
+
     pub fn image(&self) -> Option<gdk::Paintable> {
         self.imp().image.borrow().clone()
     }
@@ -2,6 +2,7 @@ use glib::clone;
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
 use gtk::{gio, glib};
+use std::cell::Cell;
 use std::cmp::Ordering;
 use std::collections::hash_map::Entry;
 use tdgrand::enums::{self, Update};
@@ -10,10 +11,15 @@ use tdgrand::types::Message as TelegramMessage;
 
 use crate::session::chat::{Item, ItemType, Message};
 use crate::session::Chat;
+use crate::spawn;
 use crate::utils::do_async;
 
+/// The number of newer messages to request when loading the messages around a message
+const MESSAGES_AROUND_OFFSET: i32 = 10;
+
 mod imp {
     use super::*;
+    use glib::subclass::Signal;
     use glib::WeakRef;
     use once_cell::sync::Lazy;
     use std::cell::{Cell, RefCell};
@@ -23,6 +29,9 @@ mod imp {
     pub struct History {
         pub chat: WeakRef<Chat>,
         pub loading: Cell<bool>,
+        /// Whether there are messages newer than the ones in the list, which happens when
+        /// the history has been loaded around a message instead of from the last one
+        pub has_newer_messages: Cell<bool>,
         pub list: RefCell<VecDeque<Item>>,
         pub message_map: RefCell<HashMap<i64, Message>>,
     }
@@ -35,6 +44,16 @@ mod imp {
     }
 
     impl ObjectImpl for History {
+        fn signals() -> &'static [Signal] {
+            static SIGNALS: Lazy<Vec<Signal>> = Lazy::new(|| {
+                vec![
+                    Signal::builder("outgoing-message-added", &[], <()>::static_type().into())
+                        .build(),
+                ]
+            });
+            SIGNALS.as_ref()
+        }
+
         fn properties() -> &'static [glib::ParamSpec] {
             static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
                 vec![
@@ -52,6 +71,13 @@ mod imp {
                         false,
                         glib::ParamFlags::READWRITE | glib::ParamFlags::EXPLICIT_NOTIFY,
                     ),
+                    glib::ParamSpecBoolean::new(
+                        "has-newer-messages",
+                        "Has Newer Messages",
+                        "Whether the history is missing messages newer than the loaded ones",
+                        false,
+                        glib::ParamFlags::READABLE | glib::ParamFlags::EXPLICIT_NOTIFY,
+                    ),
                 ]
             });
 
@@ -76,6 +102,7 @@ mod imp {
             match pspec.name() {
                 "chat" => obj.chat().to_value(),
                 "loading" => obj.loading().to_value(),
+                "has-newer-messages" => obj.has_newer_messages().to_value(),
                 _ => unimplemented!(),
             }
         }
@@ -115,17 +142,10 @@ impl History {
             return;
         }
 
-        let imp = self.imp();
         let chat = self.chat();
         let client_id = chat.session().client_id();
         let chat_id = chat.id();
-        let oldest_message_id = imp
-            .list
-            .borrow()
-            .iter()
-            .find_map(|item| item.message())
-            .map(|m| m.id())
-            .unwrap_or_default();
+        let oldest_message_id = self.oldest_message_id();
 
         self.set_loading(true);
 
@@ -140,9 +160,12 @@ impl History {
                     .await
             },
             clone!(@weak self as obj => move |result| async move {
-                if let Ok(enums::Messages::Messages(result)) = result {
-                    if let Some(messages) = result.messages {
-                        obj.prepend(messages);
+                // Ignore the messages if the history has been replaced in the meantime
+                if obj.oldest_message_id() == oldest_message_id {
+                    if let Ok(enums::Messages::Messages(result)) = result {
+                        if let Some(messages) = result.messages {
+                            obj.prepend(messages);
+                        }
                     }
                 }
 
@@ -151,6 +174,182 @@ impl History {
         );
     }
 
+    /// Loads the messages following the newest loaded one, if the history doesn't already
+    /// contain the last message of the chat.
+    pub fn load_newer_messages(&self) {
+        if self.loading() || !self.has_newer_messages() {
+            return;
+        }
+
+        let chat = self.chat();
+        let client_id = chat.session().client_id();
+        let chat_id = chat.id();
+        let newest_message_id = self.newest_message_id();
+
+        self.set_loading(true);
+
+        do_async(
+            glib::PRIORITY_DEFAULT_IDLE,
+            async move {
+                // A negative offset makes TDLib return the messages newer than the specified
+                // one, in addition to the message itself
+                functions::GetChatHistory::new()
+                    .chat_id(chat_id)
+                    .from_message_id(newest_message_id)
+                    .offset(-20)
+                    .limit(21)
+                    .send(client_id)
+                    .await
+            },
+            clone!(@weak self as obj => move |result| async move {
+                // Ignore the messages if the history has been replaced in the meantime
+                if obj.newest_message_id() == newest_message_id {
+                    if let Ok(enums::Messages::Messages(result)) = result {
+                        let mut messages: Vec<TelegramMessage> = result
+                            .messages
+                            .unwrap_or_default()
+                            .into_iter()
+                            .filter(|message| message.id > newest_message_id)
+                            .collect();
+
+                        if messages.is_empty() {
+                            obj.set_has_newer_messages(false);
+                        } else {
+                            messages.sort_by_key(|message| message.id);
+                            for message in messages {
+                                obj.append(message);
+                            }
+                            obj.update_has_newer_messages();
+                        }
+                    }
+                }
+
+                obj.set_loading(false);
+            }),
+        );
+    }
+
+    /// Replaces the loaded messages with the ones around the specified message, so that the
+    /// history can be shown starting from it.
+    pub async fn load_messages_around(&self, message_id: i64) {
+        self.load_messages_from(message_id, -MESSAGES_AROUND_OFFSET)
+            .await;
+    }
+
+    /// Replaces the loaded messages with the last ones of the chat.
+    pub async fn load_latest_messages(&self) {
+        self.load_messages_from(0, 0).await;
+    }
+
+    /// Replaces the loaded messages with the ones around the last read message of the chat
+    /// and adds an unread messages divider before the first unread one. Returns the position
+    /// of the divider, if it has been added.
+    pub async fn load_unread_messages(&self) -> Option<u32> {
+        let last_read_message_id = self.chat().last_read_inbox_message_id();
+
+        self.load_messages_around(last_read_message_id).await;
+        self.add_unread_divider(last_read_message_id)
+    }
+
+    /// Removes the unread messages divider, if the list contains one.
+    pub fn remove_unread_divider(&self) {
+        let index = self
+            .imp()
+            .list
+            .borrow()
+            .iter()
+            .position(|item| matches!(item.type_(), ItemType::UnreadDivider(_)));
+
+        if let Some(index) = index {
+            self.imp().list.borrow_mut().remove(index);
+            self.upcast_ref::<gio::ListModel>()
+                .items_changed(index as u32, 1, 0);
+        }
+    }
+
+    /// Returns the position of the message in the list, if it has been loaded.
+    pub fn message_position(&self, message_id: i64) -> Option<u32> {
+        self.imp()
+            .list
+            .borrow()
+            .iter()
+            .position(|item| matches!(item.message(), Some(message) if message.id() == message_id))
+            .map(|position| position as u32)
+    }
+
+    async fn load_messages_from(&self, from_message_id: i64, offset: i32) {
+        let chat = self.chat();
+        let client_id = chat.session().client_id();
+
+        // Wait for the messages that are already being loaded, so that the batches don't
+        // overlap
+        while self.loading() {
+            let (sender, receiver) = tokio::sync::oneshot::channel();
+            let sender = Cell::new(Some(sender));
+            let handler_id = self.connect_notify_local(Some("loading"), move |_, _| {
+                if let Some(sender) = sender.take() {
+                    let _ = sender.send(());
+                }
+            });
+
+            let _ = receiver.await;
+            self.disconnect(handler_id);
+        }
+
+        self.set_loading(true);
+
+        let result = functions::GetChatHistory::new()
+            .chat_id(chat.id())
+            .from_message_id(from_message_id)
+            .offset(offset)
+            .limit(20)
+            .send(client_id)
+            .await;
+
+        match result {
+            Ok(enums::Messages::Messages(result)) => {
+                self.replace(result.messages.unwrap_or_default());
+            }
+            Err(e) => log::warn!("Failed to load the chat history: {:?}", e),
+        }
+
+        self.set_loading(false);
+    }
+
+    /// Adds the unread messages divider before the first message newer than the last read
+    /// one, returning its position.
+    fn add_unread_divider(&self, last_read_message_id: i64) -> Option<u32> {
+        self.remove_unread_divider();
+
+        let position = {
+            let mut list = self.imp().list.borrow_mut();
+            let position = list.iter().position(|item| {
+                item.message()
+                    .map_or(false, |message| message.id() > last_read_message_id)
+            })?;
+
+            list.insert(position, Item::for_unread_divider(last_read_message_id));
+            position as u32
+        };
+
+        self.items_changed(position, 0, 1);
+        Some(position)
+    }
+
+    /// Connects to the signal emitted when an outgoing message is added to the end of the
+    /// history, loading the latest messages first if needed.
+    pub fn connect_outgoing_message_added<F: Fn(&Self) + 'static>(
+        &self,
+        f: F,
+    ) -> glib::SignalHandlerId {
+        self.connect_local("outgoing-message-added", true, move |values| {
+            let obj = values[0].get::<Self>().unwrap();
+            f(&obj);
+
+            None
+        })
+    }
+
     pub fn message_by_id(&self, id: i64) -> Option<Message> {
         let imp = self.imp();
         imp.message_map.borrow().get(&id).cloned()
@@ -161,16 +360,45 @@ impl History {
 
         match update {
             Update::NewMessage(update) => {
-                self.append(update.message);
+                let is_outgoing = update.message.is_outgoing;
+
+                if !self.has_newer_messages() {
+                    self.append(update.message);
+
+                    if is_outgoing {
+                        self.emit_by_name::<()>("outgoing-message-added", &[]);
+                    }
+                } else if is_outgoing {
+                    // Appending the message would leave a gap in the history, so load the
+                    // latest messages to show the message that has just been sent
+                    spawn!(clone!(@weak self as obj => async move {
+                        obj.load_latest_messages().await;
+                        obj.emit_by_name::<()>("outgoing-message-added", &[]);
+                    }));
+                }
+                // Otherwise just wait for the message to be loaded with the other newer
+                // messages
             }
             Update::MessageSendSucceeded(update) => {
                 self.remove(update.old_message_id);
             }
+            Update::MessageSendFailed(update) => {
+                // The message gets a new id, so replace it to show that it failed to be sent
+                self.remove(update.old_message_id);
+                if !self.has_newer_messages() {
+                    self.append(update.message);
+                }
+            }
             Update::MessageContent(ref update_) => {
                 if let Some(message) = imp.message_map.borrow().get(&update_.message_id) {
                     message.handle_update(update);
                 }
             }
+            Update::MessageEdited(ref update_) => {
+                if let Some(message) = imp.message_map.borrow().get(&update_.message_id) {
+                    message.handle_update(update);
+                }
+            }
             Update::DeleteMessages(update) => {
                 if !update.from_cache {
                     for message_id in update.message_ids {
@@ -191,12 +419,10 @@ impl History {
             let added = added as usize;
 
             let mut list = imp.list.borrow_mut();
-            let mut previous_timestamp = if position > 0 {
-                list.get(position - 1)
-                    .and_then(|item| item.message_timestamp())
-            } else {
-                None
-            };
+            let mut previous_timestamp = list
+                .range(..position)
+                .rev()
+                .find_map(|item| item.message_timestamp());
             let mut dividers: Vec<(usize, Item)> = vec![];
             let mut index = position;
 
@@ -252,31 +478,49 @@ impl History {
         };
 
         // Check and remove no more needed day divider after adding messages
-        let removed = {
+        let (removed, added) = {
             let mut removed = removed;
+            let mut added = added;
 
             if added > 0 {
                 let position = position as usize;
-                let added = added as usize;
+                let added_ = added as usize;
 
                 let mut list = imp.list.borrow_mut();
                 let last_added_timestamp = list
-                    .get(position + added - 1)
-                    .unwrap()
-                    .message_timestamp()
-                    .unwrap();
-                let next_item = list.get(position + added);
+                    .get(position + added_ - 1)
+                    .and_then(|item| item.message_timestamp());
+
+                // The unread messages divider can be followed by a day divider
+                let mut next_position = position + added_;
+                let skips_unread_divider = matches!(
+                    list.get(next_position).map(|item| item.type_()),
+                    Some(ItemType::UnreadDivider(_))
+                );
+                if skips_unread_divider {
+                    next_position += 1;
+                }
+                let next_item = list.get(next_position);
 
-                if let Some(ItemType::DayDivider(date)) = next_item.map(|item| item.type_()) {
+                if let (Some(last_added_timestamp), Some(ItemType::DayDivider(date))) =
+                    (last_added_timestamp, next_item.map(|item| item.type_()))
+                {
                     if date.ymd() == last_added_timestamp.ymd() {
-                        list.remove(position + added);
+                        list.remove(next_position);
 
                         removed += 1;
+
+                        // Report the unread messages divider as replaced, so that the change
+                        // covers a contiguous range
+                        if skips_unread_divider {
+                            removed += 1;
+                            added += 1;
+                        }
                     }
                 }
             }
 
-            removed
+            (removed, added)
         };
 
         self.upcast_ref::<gio::ListModel>()
@@ -324,6 +568,31 @@ impl History {
         self.items_changed(0, 0, added as u32);
     }
 
+    /// Replaces all the loaded messages with the specified ones, ordered from the newest.
+    fn replace(&self, messages: Vec<TelegramMessage>) {
+        let imp = self.imp();
+        let chat = self.chat();
+        let removed = imp.list.borrow().len();
+        let added = messages.len();
+
+        {
+            let mut list = imp.list.borrow_mut();
+            let mut message_map = imp.message_map.borrow_mut();
+
+            list.clear();
+            message_map.clear();
+
+            for message in messages.into_iter().rev() {
+                let message = Message::new(message, &chat);
+                message_map.insert(message.id(), message.clone());
+                list.push_back(Item::for_message(message));
+            }
+        }
+
+        self.items_changed(0, removed as u32, added as u32);
+        self.update_has_newer_messages();
+    }
+
     fn remove(&self, message_id: i64) {
         let imp = self.imp();
 
@@ -351,6 +620,14 @@ impl History {
                                 ordering
                             }
                         }
+                        ItemType::UnreadDivider(last_read_message_id) => {
+                            // The divider is placed right after the last read message
+                            if *last_read_message_id < message_id {
+                                Ordering::Less
+                            } else {
+                                Ordering::Greater
+                            }
+                        }
                     })
                     .unwrap();
 
@@ -374,4 +651,47 @@ impl History {
     pub fn loading(&self) -> bool {
         self.imp().loading.get()
     }
+
+    fn set_has_newer_messages(&self, has_newer_messages: bool) {
+        if self.has_newer_messages() == has_newer_messages {
+            return;
+        }
+        self.imp().has_newer_messages.set(has_newer_messages);
+        self.notify("has-newer-messages");
+    }
+
+    pub fn has_newer_messages(&self) -> bool {
+        self.imp().has_newer_messages.get()
+    }
+
+    fn update_has_newer_messages(&self) {
+        let newest_message_id = self.newest_message_id();
+        let has_newer_messages = self
+            .chat()
+            .last_message()
+            .map_or(false, |message| message.id() > newest_message_id);
+
+        self.set_has_newer_messages(has_newer_messages);
+    }
+
+    fn oldest_message_id(&self) -> i64 {
+        self.imp()
+            .list
+            .borrow()
+            .iter()
+            .find_map(|item| item.message())
+            .map(|m| m.id())
+            .unwrap_or_default()
+    }
+
+    fn newest_message_id(&self) -> i64 {
+        self.imp()
+            .list
+            .borrow()
+            .iter()
+            .rev()
+            .find_map(|item| item.message())
+            .map(|m| m.id())
+            .unwrap_or_default()
+    }
 }
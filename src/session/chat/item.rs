@@ -10,6 +10,9 @@ use crate::session::chat::Message;
 pub enum ItemType {
     Message(Message),
     DayDivider(DateTime),
+    /// The divider placed before the first unread message, containing the id of the last
+    /// read one
+    UnreadDivider(i64),
 }
 
 mod imp {
@@ -75,6 +78,11 @@ impl Item {
         glib::Object::new(&[("type", &type_)]).expect("Failed to create Item")
     }
 
+    pub fn for_unread_divider(last_read_message_id: i64) -> Self {
+        let type_ = ItemType::UnreadDivider(last_read_message_id);
+        glib::Object::new(&[("type", &type_)]).expect("Failed to create Item")
+    }
+
     pub fn type_(&self) -> &ItemType {
         self.imp().type_.get().unwrap()
     }
@@ -1,8 +1,13 @@
+use gettextrs::gettext;
 use gtk::glib;
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
-use tdgrand::enums::{MessageSender as TdMessageSender, Update};
-use tdgrand::types::Message as TdMessage;
+use tdgrand::enums::{
+    self, MessageForwardOrigin as TdMessageForwardOrigin, MessageSender as TdMessageSender,
+    MessageSendingState, Update,
+};
+use tdgrand::functions;
+use tdgrand::types::{Error as TdError, Message as TdMessage};
 
 use crate::session::chat::BoxedMessageContent;
 use crate::session::{Chat, Session, User};
@@ -43,6 +48,70 @@ impl MessageSender {
     }
 }
 
+#[derive(Clone, Debug, glib::Boxed)]
+#[boxed_type(name = "MessageForwardOrigin", nullable)]
+pub enum MessageForwardOrigin {
+    User(User),
+    Chat(Chat),
+    HiddenUser(String),
+    Channel(Chat),
+    MessageImport(String),
+    /// A chat that isn't known by the session, with the signature of the author as its name
+    UnknownChat(String),
+}
+
+impl MessageForwardOrigin {
+    pub fn from_td_object(origin: &TdMessageForwardOrigin, session: &Session) -> Self {
+        match origin {
+            TdMessageForwardOrigin::User(data) => {
+                let user = session.user_list().get(data.sender_user_id);
+                MessageForwardOrigin::User(user)
+            }
+            TdMessageForwardOrigin::Chat(data) => {
+                match session.chat_list().try_get(data.sender_chat_id) {
+                    Some(chat) => MessageForwardOrigin::Chat(chat),
+                    None => Self::unknown_chat(&data.author_signature),
+                }
+            }
+            TdMessageForwardOrigin::HiddenUser(data) => {
+                MessageForwardOrigin::HiddenUser(data.sender_name.clone())
+            }
+            TdMessageForwardOrigin::Channel(data) => {
+                match session.chat_list().try_get(data.chat_id) {
+                    Some(chat) => MessageForwardOrigin::Channel(chat),
+                    None => Self::unknown_chat(&data.author_signature),
+                }
+            }
+            TdMessageForwardOrigin::MessageImport(data) => {
+                MessageForwardOrigin::MessageImport(data.sender_name.clone())
+            }
+        }
+    }
+
+    fn unknown_chat(author_signature: &str) -> Self {
+        if author_signature.is_empty() {
+            MessageForwardOrigin::UnknownChat(gettext("Unknown Chat"))
+        } else {
+            MessageForwardOrigin::UnknownChat(author_signature.to_owned())
+        }
+    }
+
+    pub fn name_expression(&self) -> gtk::Expression {
+        match self {
+            Self::User(user) => {
+                let user_expression = gtk::ConstantExpression::new(user);
+                User::full_name_expression(&user_expression)
+            }
+            Self::Chat(chat) | Self::Channel(chat) => gtk::ConstantExpression::new(chat)
+                .chain_property::<Chat>("title")
+                .upcast(),
+            Self::HiddenUser(sender_name)
+            | Self::MessageImport(sender_name)
+            | Self::UnknownChat(sender_name) => gtk::ConstantExpression::new(sender_name).upcast(),
+        }
+    }
+}
+
 mod imp {
     use super::*;
     use glib::WeakRef;
@@ -54,7 +123,16 @@ mod imp {
         pub id: Cell<i64>,
         pub sender: OnceCell<MessageSender>,
         pub is_outgoing: Cell<bool>,
+        pub is_pending: Cell<bool>,
+        pub is_failed: Cell<bool>,
         pub date: Cell<i32>,
+        pub edit_date: Cell<i32>,
+        pub can_be_edited: Cell<bool>,
+        pub can_be_forwarded: Cell<bool>,
+        pub can_be_deleted_only_for_self: Cell<bool>,
+        pub can_be_deleted_for_all_users: Cell<bool>,
+        pub reply_to_message_id: Cell<i64>,
+        pub forward_origin: OnceCell<Option<MessageForwardOrigin>>,
         pub content: RefCell<Option<BoxedMessageContent>>,
         pub chat: WeakRef<Chat>,
     }
@@ -92,6 +170,20 @@ mod imp {
                         false,
                         glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
                     ),
+                    glib::ParamSpecBoolean::new(
+                        "is-pending",
+                        "Is Pending",
+                        "Whether this message is still being sent or not",
+                        false,
+                        glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
+                    ),
+                    glib::ParamSpecBoolean::new(
+                        "is-failed",
+                        "Is Failed",
+                        "Whether this message failed to be sent or not",
+                        false,
+                        glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
+                    ),
                     glib::ParamSpecInt::new(
                         "date",
                         "Date",
@@ -101,6 +193,61 @@ mod imp {
                         0,
                         glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
                     ),
+                    glib::ParamSpecInt::new(
+                        "edit-date",
+                        "Edit Date",
+                        "The point in time when this message was last edited",
+                        std::i32::MIN,
+                        std::i32::MAX,
+                        0,
+                        glib::ParamFlags::READWRITE
+                            | glib::ParamFlags::CONSTRUCT
+                            | glib::ParamFlags::EXPLICIT_NOTIFY,
+                    ),
+                    glib::ParamSpecBoolean::new(
+                        "can-be-edited",
+                        "Can Be Edited",
+                        "Whether this message can be edited or not",
+                        false,
+                        glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
+                    ),
+                    glib::ParamSpecBoolean::new(
+                        "can-be-forwarded",
+                        "Can Be Forwarded",
+                        "Whether this message can be forwarded or not",
+                        false,
+                        glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
+                    ),
+                    glib::ParamSpecBoolean::new(
+                        "can-be-deleted-only-for-self",
+                        "Can Be Deleted Only For Self",
+                        "Whether this message can be deleted only for the current user or not",
+                        false,
+                        glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
+                    ),
+                    glib::ParamSpecBoolean::new(
+                        "can-be-deleted-for-all-users",
+                        "Can Be Deleted For All Users",
+                        "Whether this message can be deleted for all users or not",
+                        false,
+                        glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
+                    ),
+                    glib::ParamSpecInt64::new(
+                        "reply-to-message-id",
+                        "Reply To Message Id",
+                        "The id of the message this message is replying to",
+                        std::i64::MIN,
+                        std::i64::MAX,
+                        0,
+                        glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
+                    ),
+                    glib::ParamSpecBoxed::new(
+                        "forward-origin",
+                        "Forward Origin",
+                        "The origin of the original message, if this message is forwarded",
+                        MessageForwardOrigin::static_type(),
+                        glib::ParamFlags::WRITABLE | glib::ParamFlags::CONSTRUCT_ONLY,
+                    ),
                     glib::ParamSpecBoxed::new(
                         "content",
                         "Content",
@@ -133,7 +280,20 @@ mod imp {
                 "id" => self.id.set(value.get().unwrap()),
                 "sender" => self.sender.set(value.get().unwrap()).unwrap(),
                 "is-outgoing" => self.is_outgoing.set(value.get().unwrap()),
+                "is-pending" => self.is_pending.set(value.get().unwrap()),
+                "is-failed" => self.is_failed.set(value.get().unwrap()),
                 "date" => self.date.set(value.get().unwrap()),
+                "edit-date" => obj.set_edit_date(value.get().unwrap()),
+                "can-be-edited" => self.can_be_edited.set(value.get().unwrap()),
+                "can-be-forwarded" => self.can_be_forwarded.set(value.get().unwrap()),
+                "can-be-deleted-only-for-self" => {
+                    self.can_be_deleted_only_for_self.set(value.get().unwrap())
+                }
+                "can-be-deleted-for-all-users" => {
+                    self.can_be_deleted_for_all_users.set(value.get().unwrap())
+                }
+                "reply-to-message-id" => self.reply_to_message_id.set(value.get().unwrap()),
+                "forward-origin" => self.forward_origin.set(value.get().unwrap()).unwrap(),
                 "content" => obj.set_content(value.get().unwrap()),
                 "chat" => self.chat.set(Some(&value.get().unwrap())),
                 _ => unimplemented!(),
@@ -144,7 +304,15 @@ mod imp {
             match pspec.name() {
                 "id" => obj.id().to_value(),
                 "is-outgoing" => obj.is_outgoing().to_value(),
+                "is-pending" => obj.is_pending().to_value(),
+                "is-failed" => obj.is_failed().to_value(),
                 "date" => obj.date().to_value(),
+                "edit-date" => obj.edit_date().to_value(),
+                "can-be-edited" => obj.can_be_edited().to_value(),
+                "can-be-forwarded" => obj.can_be_forwarded().to_value(),
+                "can-be-deleted-only-for-self" => obj.can_be_deleted_only_for_self().to_value(),
+                "can-be-deleted-for-all-users" => obj.can_be_deleted_for_all_users().to_value(),
+                "reply-to-message-id" => obj.reply_to_message_id().to_value(),
                 "content" => obj.content().to_value(),
                 "chat" => obj.chat().to_value(),
                 _ => unimplemented!(),
@@ -160,6 +328,9 @@ glib::wrapper! {
 impl Message {
     pub fn new(message: TdMessage, chat: &Chat) -> Self {
         let content = BoxedMessageContent(message.content);
+        let forward_origin = message.forward_info.as_ref().map(|forward_info| {
+            MessageForwardOrigin::from_td_object(&forward_info.origin, &chat.session())
+        });
 
         glib::Object::new(&[
             ("id", &message.id),
@@ -168,7 +339,28 @@ impl Message {
                 &MessageSender::from_td_object(&message.sender_id, &chat.session()),
             ),
             ("is-outgoing", &message.is_outgoing),
+            (
+                "is-pending",
+                &matches!(message.sending_state, Some(MessageSendingState::Pending)),
+            ),
+            (
+                "is-failed",
+                &matches!(message.sending_state, Some(MessageSendingState::Failed(_))),
+            ),
             ("date", &message.date),
+            ("edit-date", &message.edit_date),
+            ("can-be-edited", &message.can_be_edited),
+            ("can-be-forwarded", &message.can_be_forwarded),
+            (
+                "can-be-deleted-only-for-self",
+                &message.can_be_deleted_only_for_self,
+            ),
+            (
+                "can-be-deleted-for-all-users",
+                &message.can_be_deleted_for_all_users,
+            ),
+            ("reply-to-message-id", &message.reply_to_message_id),
+            ("forward-origin", &forward_origin),
             ("content", &content),
             ("chat", chat),
         ])
@@ -176,9 +368,13 @@ impl Message {
     }
 
     pub fn handle_update(&self, update: Update) {
-        if let Update::MessageContent(data) = update {
-            let new_content = BoxedMessageContent(data.new_content);
-            self.set_content(new_content);
+        match update {
+            Update::MessageContent(data) => {
+                let new_content = BoxedMessageContent(data.new_content);
+                self.set_content(new_content);
+            }
+            Update::MessageEdited(data) => self.set_edit_date(data.edit_date),
+            _ => {}
         }
     }
 
@@ -194,10 +390,81 @@ impl Message {
         self.imp().is_outgoing.get()
     }
 
+    pub fn is_pending(&self) -> bool {
+        self.imp().is_pending.get()
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.imp().is_failed.get()
+    }
+
     pub fn date(&self) -> i32 {
         self.imp().date.get()
     }
 
+    pub fn edit_date(&self) -> i32 {
+        self.imp().edit_date.get()
+    }
+
+    pub fn set_edit_date(&self, edit_date: i32) {
+        if self.edit_date() == edit_date {
+            return;
+        }
+        self.imp().edit_date.set(edit_date);
+        self.notify("edit-date");
+    }
+
+    pub fn can_be_edited(&self) -> bool {
+        self.imp().can_be_edited.get()
+    }
+
+    pub fn can_be_forwarded(&self) -> bool {
+        self.imp().can_be_forwarded.get()
+    }
+
+    pub fn can_be_deleted_only_for_self(&self) -> bool {
+        self.imp().can_be_deleted_only_for_self.get()
+    }
+
+    pub fn can_be_deleted_for_all_users(&self) -> bool {
+        self.imp().can_be_deleted_for_all_users.get()
+    }
+
+    pub fn reply_to_message_id(&self) -> i64 {
+        self.imp().reply_to_message_id.get()
+    }
+
+    pub fn forward_origin(&self) -> Option<&MessageForwardOrigin> {
+        self.imp().forward_origin.get().unwrap().as_ref()
+    }
+
+    /// Returns the message this message is replying to. The message is taken from the chat
+    /// history, if it's already loaded, or requested to TDLib otherwise.
+    pub async fn reply_to_message(&self) -> Result<Option<Message>, TdError> {
+        let reply_to_message_id = self.reply_to_message_id();
+        if reply_to_message_id == 0 {
+            return Ok(None);
+        }
+
+        let chat = self.chat();
+        if let Some(message) = chat.history().message_by_id(reply_to_message_id) {
+            return Ok(Some(message));
+        }
+
+        let session = chat.session();
+        let enums::Message::Message(message) = functions::GetRepliedMessage::new()
+            .chat_id(chat.id())
+            .message_id(self.id())
+            .send(session.client_id())
+            .await?;
+
+        // The replied message could also be in another chat (e.g. in case of a comment to a
+        // channel post)
+        let chat = session.chat_list().get(message.chat_id);
+
+        Ok(Some(Message::new(message, &chat)))
+    }
+
     pub fn content(&self) -> BoxedMessageContent {
         self.imp().content.borrow().as_ref().unwrap().to_owned()
     }
@@ -15,11 +15,14 @@ pub use self::sponsored_message::SponsoredMessage;
 use gtk::glib;
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
-use tdgrand::enums::{self, ChatType as TdChatType, MessageContent, Update};
-use tdgrand::types::{Chat as TelegramChat, ChatNotificationSettings, DraftMessage};
+use std::collections::HashMap;
+use tdgrand::enums::{ChatType as TdChatType, MessageContent, Update};
+use tdgrand::functions;
+use tdgrand::types::{Chat as TelegramChat, ChatNotificationSettings, ChatPosition, DraftMessage};
 
-use crate::session::{Avatar, BasicGroup, SecretChat, Supergroup, User};
-use crate::Session;
+use crate::session::{Avatar, BasicGroup, ChatListType, SecretChat, Supergroup, User};
+use crate::utils::is_mute_active;
+use crate::{Session, RUNTIME};
 
 #[derive(Clone, Debug, glib::Boxed)]
 #[boxed_type(name = "ChatType")]
@@ -69,6 +72,11 @@ pub struct BoxedDraftMessage(pub DraftMessage);
 #[boxed_type(name = "BoxedChatNotificationSettings")]
 pub struct BoxedChatNotificationSettings(pub ChatNotificationSettings);
 
+/// The positions of a chat in the chat lists that it's part of
+#[derive(Clone, Debug, Default, PartialEq, glib::Boxed)]
+#[boxed_type(name = "BoxedChatPositions")]
+pub struct BoxedChatPositions(pub HashMap<ChatListType, ChatPosition>);
+
 #[derive(Clone, Debug, PartialEq, glib::Boxed)]
 #[boxed_type(name = "BoxedMessageContent")]
 pub struct BoxedMessageContent(pub MessageContent);
@@ -87,12 +95,16 @@ mod imp {
         pub title: RefCell<String>,
         pub avatar: OnceCell<Avatar>,
         pub last_message: RefCell<Option<Message>>,
-        pub order: Cell<i64>,
-        pub is_pinned: Cell<bool>,
+        pub positions: RefCell<BoxedChatPositions>,
         pub unread_mention_count: Cell<i32>,
+        pub is_marked_as_unread: Cell<bool>,
         pub unread_count: Cell<i32>,
+        pub last_read_inbox_message_id: Cell<i64>,
+        pub last_read_outbox_message_id: Cell<i64>,
         pub draft_message: RefCell<Option<BoxedDraftMessage>>,
         pub notification_settings: RefCell<Option<BoxedChatNotificationSettings>>,
+        /// The unix time at which the notification settings were received
+        pub notification_settings_date: Cell<i64>,
         pub history: OnceCell<History>,
         pub actions: OnceCell<ChatActionList>,
         pub session: WeakRef<Session>,
@@ -147,21 +159,21 @@ mod imp {
                         Message::static_type(),
                         glib::ParamFlags::READWRITE | glib::ParamFlags::EXPLICIT_NOTIFY,
                     ),
-                    glib::ParamSpecInt64::new(
-                        "order",
-                        "Order",
-                        "The parameter to determine the order of this chat in the chat list",
-                        std::i64::MIN,
-                        std::i64::MAX,
-                        0,
-                        glib::ParamFlags::READWRITE | glib::ParamFlags::EXPLICIT_NOTIFY,
+                    glib::ParamSpecBoxed::new(
+                        "positions",
+                        "Positions",
+                        "The order and the pinned state of this chat in each of its chat lists",
+                        BoxedChatPositions::static_type(),
+                        glib::ParamFlags::READABLE,
                     ),
                     glib::ParamSpecBoolean::new(
-                        "is-pinned",
-                        "Is Pinned",
-                        "The parameter to determine if this chat is pinned in the chat list",
+                        "is-marked-as-unread",
+                        "Is Marked As Unread",
+                        "Whether this chat is marked as unread by the user",
                         false,
-                        glib::ParamFlags::READWRITE | glib::ParamFlags::EXPLICIT_NOTIFY,
+                        glib::ParamFlags::READWRITE
+                            | glib::ParamFlags::CONSTRUCT
+                            | glib::ParamFlags::EXPLICIT_NOTIFY,
                     ),
                     glib::ParamSpecInt::new(
                         "unread-mention-count",
@@ -185,6 +197,28 @@ mod imp {
                             | glib::ParamFlags::CONSTRUCT
                             | glib::ParamFlags::EXPLICIT_NOTIFY,
                     ),
+                    glib::ParamSpecInt64::new(
+                        "last-read-inbox-message-id",
+                        "Last Read Inbox Message Id",
+                        "The id of the last read incoming message of this chat",
+                        std::i64::MIN,
+                        std::i64::MAX,
+                        0,
+                        glib::ParamFlags::READWRITE
+                            | glib::ParamFlags::CONSTRUCT
+                            | glib::ParamFlags::EXPLICIT_NOTIFY,
+                    ),
+                    glib::ParamSpecInt64::new(
+                        "last-read-outbox-message-id",
+                        "Last Read Outbox Message Id",
+                        "The id of the last outgoing message of this chat read by the recipient",
+                        std::i64::MIN,
+                        std::i64::MAX,
+                        0,
+                        glib::ParamFlags::READWRITE
+                            | glib::ParamFlags::CONSTRUCT
+                            | glib::ParamFlags::EXPLICIT_NOTIFY,
+                    ),
                     glib::ParamSpecBoxed::new(
                         "draft-message",
                         "Draft Message",
@@ -244,10 +278,15 @@ mod imp {
                 }
                 "avatar" => self.avatar.set(value.get().unwrap()).unwrap(),
                 "last-message" => obj.set_last_message(value.get().unwrap()),
-                "order" => obj.set_order(value.get().unwrap()),
-                "is-pinned" => obj.set_is_pinned(value.get().unwrap()),
+                "is-marked-as-unread" => obj.set_is_marked_as_unread(value.get().unwrap()),
                 "unread-mention-count" => obj.set_unread_mention_count(value.get().unwrap()),
                 "unread-count" => obj.set_unread_count(value.get().unwrap()),
+                "last-read-inbox-message-id" => {
+                    obj.set_last_read_inbox_message_id(value.get().unwrap())
+                }
+                "last-read-outbox-message-id" => {
+                    obj.set_last_read_outbox_message_id(value.get().unwrap())
+                }
                 "draft-message" => obj.set_draft_message(value.get().unwrap()),
                 "notification-settings" => obj.set_notification_settings(value.get().unwrap()),
                 "session" => self.session.set(Some(&value.get().unwrap())),
@@ -262,10 +301,12 @@ mod imp {
                 "title" => obj.title().to_value(),
                 "avatar" => obj.avatar().to_value(),
                 "last-message" => obj.last_message().to_value(),
-                "order" => obj.order().to_value(),
-                "is-pinned" => obj.is_pinned().to_value(),
+                "positions" => obj.positions().to_value(),
+                "is-marked-as-unread" => obj.is_marked_as_unread().to_value(),
                 "unread-mention-count" => obj.unread_mention_count().to_value(),
                 "unread-count" => obj.unread_count().to_value(),
+                "last-read-inbox-message-id" => obj.last_read_inbox_message_id().to_value(),
+                "last-read-outbox-message-id" => obj.last_read_outbox_message_id().to_value(),
                 "draft-message" => obj.draft_message().to_value(),
                 "notification-settings" => obj.notification_settings().to_value(),
                 "history" => obj.history().to_value(),
@@ -296,28 +337,45 @@ impl Chat {
 
         avatar.update_from_chat_photo(chat.photo);
 
-        glib::Object::new(&[
+        let obj: Self = glib::Object::new(&[
             ("id", &chat.id),
             ("type", &type_),
             ("title", &chat.title),
             ("avatar", &avatar),
             ("draft-message", &draft_message),
+            ("is-marked-as-unread", &chat.is_marked_as_unread),
             ("unread-mention-count", &chat.unread_mention_count),
             ("unread-count", &chat.unread_count),
+            (
+                "last-read-inbox-message-id",
+                &chat.last_read_inbox_message_id,
+            ),
+            (
+                "last-read-outbox-message-id",
+                &chat.last_read_outbox_message_id,
+            ),
             (
                 "notification-settings",
                 &BoxedChatNotificationSettings(chat.notification_settings),
             ),
             ("session", &session),
         ])
-        .expect("Failed to create Chat")
+        .expect("Failed to create Chat");
+
+        for position in chat.positions {
+            obj.set_position(position);
+        }
+
+        obj
     }
 
     pub fn handle_update(&self, update: Update) {
         match update {
             Update::NewMessage(_)
             | Update::MessageSendSucceeded(_)
+            | Update::MessageSendFailed(_)
             | Update::MessageContent(_)
+            | Update::MessageEdited(_)
             | Update::DeleteMessages(_) => {
                 self.history().handle_update(update);
             }
@@ -332,6 +390,11 @@ impl Chat {
                     Some(last_message) => {
                         let message = match self.history().message_by_id(last_message.id) {
                             Some(message) => message,
+                            // The message can't be added to a history that doesn't contain
+                            // the latest messages
+                            None if self.history().has_newer_messages() => {
+                                Message::new(last_message, self)
+                            }
                             None => {
                                 let last_message_id = last_message.id;
 
@@ -346,10 +409,7 @@ impl Chat {
                 }
 
                 for position in update.positions {
-                    if let enums::ChatList::Main = position.list {
-                        self.set_order(position.order);
-                        break;
-                    }
+                    self.set_position(position);
                 }
             }
             Update::ChatNotificationSettings(update) => {
@@ -358,10 +418,10 @@ impl Chat {
                 ));
             }
             Update::ChatPosition(update) => {
-                if let enums::ChatList::Main = update.position.list {
-                    self.set_order(update.position.order);
-                    self.set_is_pinned(update.position.is_pinned);
-                }
+                self.set_position(update.position);
+            }
+            Update::ChatIsMarkedAsUnread(update) => {
+                self.set_is_marked_as_unread(update.is_marked_as_unread);
             }
             Update::ChatUnreadMentionCount(update) => {
                 self.set_unread_mention_count(update.unread_mention_count);
@@ -371,6 +431,10 @@ impl Chat {
             }
             Update::ChatReadInbox(update) => {
                 self.set_unread_count(update.unread_count);
+                self.set_last_read_inbox_message_id(update.last_read_inbox_message_id);
+            }
+            Update::ChatReadOutbox(update) => {
+                self.set_last_read_outbox_message_id(update.last_read_outbox_message_id);
             }
             Update::ChatDraftMessage(update) => {
                 self.set_draft_message(update.draft_message.map(BoxedDraftMessage));
@@ -421,35 +485,68 @@ impl Chat {
         self.notify("last-message");
     }
 
-    pub fn order(&self) -> i64 {
-        self.imp().order.get()
+    pub fn positions(&self) -> BoxedChatPositions {
+        self.imp().positions.borrow().clone()
     }
 
-    pub fn set_order(&self, order: i64) {
-        if self.order() == order {
-            return;
+    /// Sets the position of the chat in a chat list, removing it from the list if its
+    /// order is zero.
+    fn set_position(&self, position: ChatPosition) {
+        {
+            let mut positions = self.imp().positions.borrow_mut();
+            let chat_list = ChatListType::from_td_object(&position.list);
+
+            if position.order == 0 {
+                if positions.0.remove(&chat_list).is_none() {
+                    return;
+                }
+            } else if positions.0.get(&chat_list) == Some(&position) {
+                return;
+            } else {
+                positions.0.insert(chat_list, position);
+            }
         }
-        self.imp().order.set(order);
-        self.notify("order");
+
+        self.notify("positions");
     }
 
-    pub fn connect_order_notify<F: Fn(&Self, &glib::ParamSpec) + 'static>(
+    pub fn connect_positions_notify<F: Fn(&Self, &glib::ParamSpec) + 'static>(
         &self,
         f: F,
     ) -> glib::SignalHandlerId {
-        self.connect_notify_local(Some("order"), f)
+        self.connect_notify_local(Some("positions"), f)
+    }
+
+    /// Returns the parameter to determine the order of this chat in the chat list, or zero
+    /// if it isn't part of it.
+    pub fn order(&self, chat_list: ChatListType) -> i64 {
+        self.imp()
+            .positions
+            .borrow()
+            .0
+            .get(&chat_list)
+            .map_or(0, |position| position.order)
     }
 
-    pub fn is_pinned(&self) -> bool {
-        self.imp().is_pinned.get()
+    pub fn is_pinned(&self, chat_list: ChatListType) -> bool {
+        self.imp()
+            .positions
+            .borrow()
+            .0
+            .get(&chat_list)
+            .map_or(false, |position| position.is_pinned)
+    }
+
+    pub fn is_marked_as_unread(&self) -> bool {
+        self.imp().is_marked_as_unread.get()
     }
 
-    pub fn set_is_pinned(&self, is_pinned: bool) {
-        if self.is_pinned() == is_pinned {
+    pub fn set_is_marked_as_unread(&self, is_marked_as_unread: bool) {
+        if self.is_marked_as_unread() == is_marked_as_unread {
             return;
         }
-        self.imp().is_pinned.set(is_pinned);
-        self.notify("is-pinned");
+        self.imp().is_marked_as_unread.set(is_marked_as_unread);
+        self.notify("is-marked-as-unread");
     }
 
     pub fn unread_mention_count(&self) -> i32 {
@@ -476,6 +573,41 @@ impl Chat {
         self.notify("unread-count");
     }
 
+    pub fn last_read_inbox_message_id(&self) -> i64 {
+        self.imp().last_read_inbox_message_id.get()
+    }
+
+    pub fn set_last_read_inbox_message_id(&self, last_read_inbox_message_id: i64) {
+        if self.last_read_inbox_message_id() == last_read_inbox_message_id {
+            return;
+        }
+        self.imp()
+            .last_read_inbox_message_id
+            .set(last_read_inbox_message_id);
+        self.notify("last-read-inbox-message-id");
+    }
+
+    pub fn last_read_outbox_message_id(&self) -> i64 {
+        self.imp().last_read_outbox_message_id.get()
+    }
+
+    pub fn set_last_read_outbox_message_id(&self, last_read_outbox_message_id: i64) {
+        if self.last_read_outbox_message_id() == last_read_outbox_message_id {
+            return;
+        }
+        self.imp()
+            .last_read_outbox_message_id
+            .set(last_read_outbox_message_id);
+        self.notify("last-read-outbox-message-id");
+    }
+
+    pub fn connect_last_read_outbox_message_id_notify<F: Fn(&Self, &glib::ParamSpec) + 'static>(
+        &self,
+        f: F,
+    ) -> glib::SignalHandlerId {
+        self.connect_notify_local(Some("last-read-outbox-message-id"), f)
+    }
+
     pub fn draft_message(&self) -> Option<BoxedDraftMessage> {
         self.imp().draft_message.borrow().to_owned()
     }
@@ -498,6 +630,9 @@ impl Chat {
     }
 
     pub fn set_notification_settings(&self, notification_settings: BoxedChatNotificationSettings) {
+        self.imp()
+            .notification_settings_date
+            .set(glib::real_time() / 1_000_000);
         if self.imp().notification_settings.borrow().as_ref() == Some(&notification_settings) {
             return;
         }
@@ -507,6 +642,22 @@ impl Chat {
         self.notify("notification-settings");
     }
 
+    /// Returns whether the notifications of this chat are muted, either by its own settings or
+    /// by the default settings of its scope.
+    pub fn is_muted(&self) -> bool {
+        let notification_settings = self.notification_settings().0;
+        if notification_settings.use_default_mute_for {
+            if let Some(is_muted) = self.session().is_scope_muted(self.type_()) {
+                return is_muted;
+            }
+        }
+
+        is_mute_active(
+            notification_settings.mute_for,
+            self.imp().notification_settings_date.get(),
+        )
+    }
+
     pub fn history(&self) -> &History {
         self.imp().history.get_or_init(|| History::new(self))
     }
@@ -520,4 +671,54 @@ impl Chat {
     pub fn session(&self) -> Session {
         self.imp().session.upgrade().unwrap()
     }
+
+    /// Mark all the messages of this chat as read, also removing the unread mark, if any.
+    pub fn mark_as_read(&self) {
+        let client_id = self.session().client_id();
+        let chat_id = self.id();
+        let last_message_id = self.last_message().map(|m| m.id());
+        let is_marked_as_unread = self.is_marked_as_unread();
+
+        RUNTIME.spawn(async move {
+            if let Some(last_message_id) = last_message_id {
+                if let Err(e) = functions::ViewMessages::new()
+                    .chat_id(chat_id)
+                    .message_ids(vec![last_message_id])
+                    .force_read(true)
+                    .send(client_id)
+                    .await
+                {
+                    log::warn!("Failed to view the messages: {:?}", e);
+                }
+            }
+
+            if is_marked_as_unread {
+                if let Err(e) = functions::ToggleChatIsMarkedAsUnread::new()
+                    .chat_id(chat_id)
+                    .is_marked_as_unread(false)
+                    .send(client_id)
+                    .await
+                {
+                    log::warn!("Failed to mark the chat as read: {:?}", e);
+                }
+            }
+        });
+    }
+
+    /// Marks this chat as unread, even if all of its messages have been read.
+    pub fn mark_as_unread(&self) {
+        let client_id = self.session().client_id();
+        let chat_id = self.id();
+
+        RUNTIME.spawn(async move {
+            if let Err(e) = functions::ToggleChatIsMarkedAsUnread::new()
+                .chat_id(chat_id)
+                .is_marked_as_unread(true)
+                .send(client_id)
+                .await
+            {
+                log::warn!("Failed to mark the chat as unread: {:?}", e);
+            }
+        });
+    }
 }
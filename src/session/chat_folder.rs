@@ -0,0 +1,154 @@
+use gtk::glib;
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+use tdgrand::enums::ChatList as TdChatList;
+use tdgrand::types::ChatListFilter;
+
+/// A list of chats that a chat can be part of, each with its own ordering.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, glib::Boxed)]
+#[boxed_type(name = "ChatListType")]
+pub enum ChatListType {
+    Main,
+    Archive,
+    /// A chat folder created by the user, with the id of its chat filter
+    Filter(i32),
+}
+
+impl Default for ChatListType {
+    fn default() -> Self {
+        Self::Main
+    }
+}
+
+impl ChatListType {
+    pub fn from_td_object(chat_list: &TdChatList) -> Self {
+        match chat_list {
+            TdChatList::Main => Self::Main,
+            TdChatList::Archive => Self::Archive,
+            TdChatList::Filter(data) => Self::Filter(data.chat_filter_id),
+        }
+    }
+
+    pub fn to_td_object(self) -> TdChatList {
+        match self {
+            Self::Main => TdChatList::Main,
+            Self::Archive => TdChatList::Archive,
+            Self::Filter(chat_filter_id) => TdChatList::Filter(ChatListFilter { chat_filter_id }),
+        }
+    }
+}
+
+mod imp {
+    use super::*;
+    use once_cell::sync::{Lazy, OnceCell};
+    use std::cell::{Cell, RefCell};
+
+    #[derive(Debug, Default)]
+    pub struct ChatFolder {
+        pub chat_list: OnceCell<ChatListType>,
+        pub title: RefCell<String>,
+        pub unread_count: Cell<i32>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for ChatFolder {
+        const NAME: &'static str = "ChatFolder";
+        type Type = super::ChatFolder;
+    }
+
+    impl ObjectImpl for ChatFolder {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                vec![
+                    glib::ParamSpecBoxed::new(
+                        "chat-list",
+                        "Chat List",
+                        "The chat list shown by this folder",
+                        ChatListType::static_type(),
+                        glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
+                    ),
+                    glib::ParamSpecString::new(
+                        "title",
+                        "Title",
+                        "The title of this folder",
+                        None,
+                        glib::ParamFlags::READWRITE | glib::ParamFlags::EXPLICIT_NOTIFY,
+                    ),
+                    glib::ParamSpecInt::new(
+                        "unread-count",
+                        "Unread Count",
+                        "The number of unread messages in the chats of this folder",
+                        0,
+                        std::i32::MAX,
+                        0,
+                        glib::ParamFlags::READWRITE | glib::ParamFlags::EXPLICIT_NOTIFY,
+                    ),
+                ]
+            });
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(
+            &self,
+            obj: &Self::Type,
+            _id: usize,
+            value: &glib::Value,
+            pspec: &glib::ParamSpec,
+        ) {
+            match pspec.name() {
+                "chat-list" => self.chat_list.set(value.get().unwrap()).unwrap(),
+                "title" => obj.set_title(value.get().unwrap()),
+                "unread-count" => obj.set_unread_count(value.get().unwrap()),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn property(&self, obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            match pspec.name() {
+                "chat-list" => obj.chat_list().to_value(),
+                "title" => obj.title().to_value(),
+                "unread-count" => obj.unread_count().to_value(),
+                _ => unimplemented!(),
+            }
+        }
+    }
+}
+
+glib::wrapper! {
+    pub struct ChatFolder(ObjectSubclass<imp::ChatFolder>);
+}
+
+impl ChatFolder {
+    pub fn new(chat_list: ChatListType, title: &str) -> Self {
+        glib::Object::new(&[("chat-list", &chat_list), ("title", &title)])
+            .expect("Failed to create ChatFolder")
+    }
+
+    pub fn chat_list(&self) -> ChatListType {
+        *self.imp().chat_list.get().unwrap()
+    }
+
+    pub fn title(&self) -> String {
+        self.imp().title.borrow().clone()
+    }
+
+    pub fn set_title(&self, title: String) {
+        if self.title() == title {
+            return;
+        }
+        self.imp().title.replace(title);
+        self.notify("title");
+    }
+
+    pub fn unread_count(&self) -> i32 {
+        self.imp().unread_count.get()
+    }
+
+    pub fn set_unread_count(&self, unread_count: i32) {
+        if self.unread_count() == unread_count {
+            return;
+        }
+        self.imp().unread_count.set(unread_count);
+        self.notify("unread-count");
+    }
+}
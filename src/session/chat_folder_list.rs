@@ -0,0 +1,122 @@
+use gettextrs::gettext;
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+use gtk::{gio, glib};
+use tdgrand::enums::Update;
+
+use crate::session::{ChatFolder, ChatListType};
+
+mod imp {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[derive(Debug, Default)]
+    pub struct ChatFolderList {
+        /// The main chat list, followed by the folders of the user and the archive
+        pub list: RefCell<Vec<ChatFolder>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for ChatFolderList {
+        const NAME: &'static str = "ChatFolderList";
+        type Type = super::ChatFolderList;
+        type Interfaces = (gio::ListModel,);
+    }
+
+    impl ObjectImpl for ChatFolderList {
+        fn constructed(&self, obj: &Self::Type) {
+            self.parent_constructed(obj);
+
+            self.list.replace(vec![
+                ChatFolder::new(ChatListType::Main, &gettext("All Chats")),
+                ChatFolder::new(ChatListType::Archive, &gettext("Archived")),
+            ]);
+        }
+    }
+
+    impl ListModelImpl for ChatFolderList {
+        fn item_type(&self, _list_model: &Self::Type) -> glib::Type {
+            ChatFolder::static_type()
+        }
+
+        fn n_items(&self, _list_model: &Self::Type) -> u32 {
+            self.list.borrow().len() as u32
+        }
+
+        fn item(&self, _list_model: &Self::Type, position: u32) -> Option<glib::Object> {
+            self.list
+                .borrow()
+                .get(position as usize)
+                .map(|f| f.upcast_ref())
+                .cloned()
+        }
+    }
+}
+
+glib::wrapper! {
+    pub struct ChatFolderList(ObjectSubclass<imp::ChatFolderList>)
+        @implements gio::ListModel;
+}
+
+impl Default for ChatFolderList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChatFolderList {
+    pub fn new() -> Self {
+        glib::Object::new(&[]).expect("Failed to create ChatFolderList")
+    }
+
+    /// Return the `ChatFolder` showing the specified chat list, if any.
+    pub fn get(&self, chat_list: ChatListType) -> Option<ChatFolder> {
+        self.imp()
+            .list
+            .borrow()
+            .iter()
+            .find(|folder| folder.chat_list() == chat_list)
+            .cloned()
+    }
+
+    pub fn handle_update(&self, update: &Update) {
+        match update {
+            Update::ChatFilters(data) => {
+                let (removed, added) = {
+                    let mut list = self.imp().list.borrow_mut();
+
+                    // Keep the existing folders, so that their unread count isn't lost
+                    let folders: Vec<ChatFolder> = data
+                        .chat_filters
+                        .iter()
+                        .map(|info| {
+                            let chat_list = ChatListType::Filter(info.id);
+                            match list.iter().find(|f| f.chat_list() == chat_list) {
+                                Some(folder) => {
+                                    folder.set_title(info.title.clone());
+                                    folder.clone()
+                                }
+                                None => ChatFolder::new(chat_list, &info.title),
+                            }
+                        })
+                        .collect();
+
+                    let removed = list.len() - 2;
+                    let added = folders.len();
+                    let archive_position = list.len() - 1;
+                    list.splice(1..archive_position, folders);
+
+                    (removed, added)
+                };
+
+                self.items_changed(1, removed as u32, added as u32);
+            }
+            Update::UnreadMessageCount(data) => {
+                if let Some(folder) = self.get(ChatListType::from_td_object(&data.chat_list)) {
+                    folder.set_unread_count(data.unread_count);
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -2,11 +2,11 @@ use glib::clone;
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
 use gtk::{gio, glib};
-use tdgrand::enums::Update;
+use tdgrand::enums::{self, Update};
 use tdgrand::functions;
-use tdgrand::types::Chat as TelegramChat;
+use tdgrand::types::{Chat as TelegramChat, Error as TdError};
 
-use crate::session::Chat;
+use crate::session::{Chat, ChatListType};
 use crate::utils::do_async;
 use crate::Session;
 
@@ -16,10 +16,13 @@ mod imp {
     use indexmap::IndexMap;
     use once_cell::sync::{Lazy, OnceCell};
     use std::cell::{Cell, RefCell};
+    use std::collections::HashSet;
 
     #[derive(Debug, Default)]
     pub struct ChatList {
         pub list: RefCell<IndexMap<i64, Chat>>,
+        /// The chat lists whose chats have been requested to TDLib
+        pub fetched_chat_lists: RefCell<HashSet<ChatListType>>,
         pub unread_count: Cell<i32>,
         pub session: OnceCell<Session>,
     }
@@ -122,10 +125,20 @@ impl ChatList {
         glib::Object::new(&[("session", session)]).expect("Failed to create ChatList")
     }
 
-    pub fn fetch(&self, client_id: i32) {
+    /// Loads all the chats of the chat list, if it hasn't been done yet.
+    pub fn fetch(&self, client_id: i32, chat_list: ChatListType) {
+        if self.imp().fetched_chat_lists.borrow_mut().insert(chat_list) {
+            self.load_chats(client_id, chat_list);
+        }
+    }
+
+    fn load_chats(&self, client_id: i32, chat_list: ChatListType) {
         do_async(
             glib::PRIORITY_DEFAULT_IDLE,
-            functions::LoadChats::new().limit(20).send(client_id),
+            functions::LoadChats::new()
+                .chat_list(chat_list.to_td_object())
+                .limit(20)
+                .send(client_id),
             clone!(@weak self as obj => move |result| async move {
                 if let Err(err) = result {
                     // Error 404 means that all chats have been loaded
@@ -133,7 +146,7 @@ impl ChatList {
                         log::error!("Received an error for LoadChats: {}", err.code);
                     }
                 } else {
-                    obj.fetch(client_id);
+                    obj.load_chats(client_id, chat_list);
                 }
             }),
         );
@@ -156,11 +169,21 @@ impl ChatList {
                     chat.handle_update(update);
                 }
             }
+            Update::MessageSendFailed(ref update_) => {
+                if let Some(chat) = imp.list.borrow().get(&update_.message.chat_id) {
+                    chat.handle_update(update);
+                }
+            }
             Update::MessageContent(ref update_) => {
                 if let Some(chat) = imp.list.borrow().get(&update_.chat_id) {
                     chat.handle_update(update);
                 }
             }
+            Update::MessageEdited(ref update_) => {
+                if let Some(chat) = imp.list.borrow().get(&update_.chat_id) {
+                    chat.handle_update(update);
+                }
+            }
             Update::NewChat(update) => {
                 self.insert_chat(update.chat);
             }
@@ -189,6 +212,11 @@ impl ChatList {
                     chat.handle_update(update);
                 }
             }
+            Update::ChatIsMarkedAsUnread(ref update_) => {
+                if let Some(chat) = imp.list.borrow().get(&update_.chat_id) {
+                    chat.handle_update(update);
+                }
+            }
             Update::ChatUnreadMentionCount(ref update_) => {
                 if let Some(chat) = imp.list.borrow().get(&update_.chat_id) {
                     chat.handle_update(update);
@@ -204,6 +232,11 @@ impl ChatList {
                     chat.handle_update(update);
                 }
             }
+            Update::ChatReadOutbox(ref update_) => {
+                if let Some(chat) = imp.list.borrow().get(&update_.chat_id) {
+                    chat.handle_update(update);
+                }
+            }
             Update::ChatDraftMessage(ref update_) => {
                 if let Some(chat) = imp.list.borrow().get(&update_.chat_id) {
                     chat.handle_update(update);
@@ -223,6 +256,18 @@ impl ChatList {
         }
     }
 
+    /// Search the chats matching the specified query, ordered by relevance.
+    pub async fn search(&self, query: String) -> Result<Vec<Chat>, TdError> {
+        let client_id = self.session().client_id();
+        let enums::Chats::Chats(chats) = functions::SearchChats::new()
+            .query(query)
+            .limit(100)
+            .send(client_id)
+            .await?;
+
+        Ok(chats.chat_ids.into_iter().map(|id| self.get(id)).collect())
+    }
+
     /// Return the `Chat` of the specified `id`. Panics if the chat is not present.
     /// Note that TDLib guarantees that types are always returned before their ids,
     /// so if you use an `id` returned by TDLib, it should be expected that the
@@ -236,13 +281,18 @@ impl ChatList {
             .to_owned()
     }
 
+    /// Return the `Chat` of the specified `id`, if it's present in the list.
+    pub fn try_get(&self, id: i64) -> Option<Chat> {
+        self.imp().list.borrow().get(&id).cloned()
+    }
+
     fn insert_chat(&self, chat: TelegramChat) {
         {
             let mut list = self.imp().list.borrow_mut();
             let chat_id = chat.id;
             let chat = Chat::new(chat, self.session());
 
-            chat.connect_order_notify(clone!(@weak self as obj => move |_, _| {
+            chat.connect_positions_notify(clone!(@weak self as obj => move |_, _| {
                 obj.emit_by_name::<()>("positions-changed", &[]);
             }));
 
@@ -1,15 +1,21 @@
+use gettextrs::gettext;
 use glib::clone;
 use glib::signal::Inhibit;
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
-use gtk::{gdk, glib, CompositeTemplate};
-use tdgrand::enums::{ChatAction, InputMessageContent};
+use gtk::{gdk, gio, glib, CompositeTemplate};
+use std::cell::Cell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use tdgrand::enums::{self, ChatAction, InputFile, InputMessageContent, MessageContent};
 use tdgrand::{functions, types};
 
-use crate::session::chat::BoxedDraftMessage;
-use crate::session::Chat;
-use crate::utils::do_async;
-use crate::RUNTIME;
+use crate::session::chat::{BoxedDraftMessage, Message};
+use crate::session::content::message_row::MessageReply;
+use crate::session::content::{SendMediaDialog, StickerChooser};
+use crate::session::{Chat, Session};
+use crate::utils::{do_async, formatted_text_to_markdown, parse_markdown};
+use crate::{spawn, RUNTIME};
 
 mod imp {
     use super::*;
@@ -21,11 +27,31 @@ mod imp {
     pub struct ChatActionBar {
         pub chat: RefCell<Option<Chat>>,
         pub chat_action_in_cooldown: Cell<bool>,
+        pub reply_to_message: RefCell<Option<Message>>,
+        pub editing_message: RefCell<Option<Message>>,
+        /// The text of the message entry and the reply before editing a message, restored
+        /// when the editing ends
+        pub composed_message: RefCell<Option<(String, Option<Message>)>>,
+        pub file_chooser: RefCell<Option<gtk::FileChooserNative>>,
+        #[template_child]
+        pub message_bar: TemplateChild<gtk::Box>,
+        #[template_child]
+        pub message_bar_icon: TemplateChild<gtk::Image>,
+        #[template_child]
+        pub message_bar_preview: TemplateChild<MessageReply>,
+        #[template_child]
+        pub main_box: TemplateChild<gtk::Box>,
         #[template_child]
         pub scrolled_window: TemplateChild<gtk::ScrolledWindow>,
         #[template_child]
         pub message_entry: TemplateChild<gtk::TextView>,
         #[template_child]
+        pub emoji_chooser: TemplateChild<gtk::EmojiChooser>,
+        #[template_child]
+        pub sticker_popover: TemplateChild<gtk::Popover>,
+        #[template_child]
+        pub sticker_chooser: TemplateChild<StickerChooser>,
+        #[template_child]
         pub send_message_button: TemplateChild<gtk::Button>,
     }
 
@@ -36,6 +62,7 @@ mod imp {
         type ParentType = gtk::Widget;
 
         fn class_init(klass: &mut Self::Class) {
+            StickerChooser::static_type();
             Self::bind_template(klass);
 
             klass.install_action(
@@ -45,6 +72,16 @@ mod imp {
                     widget.send_text_message();
                 },
             );
+            klass.install_action("chat-action-bar.attach-file", None, move |widget, _, _| {
+                widget.open_file_chooser();
+            });
+            klass.install_action(
+                "chat-action-bar.dismiss-message-bar",
+                None,
+                move |widget, _, _| {
+                    widget.dismiss_message_bar();
+                },
+            );
         }
 
         fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
@@ -55,13 +92,29 @@ mod imp {
     impl ObjectImpl for ChatActionBar {
         fn properties() -> &'static [glib::ParamSpec] {
             static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
-                vec![glib::ParamSpecObject::new(
-                    "chat",
-                    "Chat",
-                    "The chat associated with this widget",
-                    Chat::static_type(),
-                    glib::ParamFlags::READWRITE | glib::ParamFlags::EXPLICIT_NOTIFY,
-                )]
+                vec![
+                    glib::ParamSpecObject::new(
+                        "chat",
+                        "Chat",
+                        "The chat associated with this widget",
+                        Chat::static_type(),
+                        glib::ParamFlags::READWRITE | glib::ParamFlags::EXPLICIT_NOTIFY,
+                    ),
+                    glib::ParamSpecObject::new(
+                        "reply-to-message",
+                        "Reply To Message",
+                        "The message that the composed message is replying to",
+                        Message::static_type(),
+                        glib::ParamFlags::READWRITE | glib::ParamFlags::EXPLICIT_NOTIFY,
+                    ),
+                    glib::ParamSpecObject::new(
+                        "editing-message",
+                        "Editing Message",
+                        "The message that is being edited",
+                        Message::static_type(),
+                        glib::ParamFlags::READWRITE | glib::ParamFlags::EXPLICIT_NOTIFY,
+                    ),
+                ]
             });
             PROPERTIES.as_ref()
         }
@@ -78,6 +131,14 @@ mod imp {
                     let chat = value.get().unwrap();
                     obj.set_chat(chat);
                 }
+                "reply-to-message" => {
+                    let reply_to_message = value.get().unwrap();
+                    obj.set_reply_to_message(reply_to_message);
+                }
+                "editing-message" => {
+                    let editing_message = value.get().unwrap();
+                    obj.set_editing_message(editing_message);
+                }
                 _ => unimplemented!(),
             }
         }
@@ -85,6 +146,8 @@ mod imp {
         fn property(&self, obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
             match pspec.name() {
                 "chat" => obj.chat().to_value(),
+                "reply-to-message" => obj.reply_to_message().to_value(),
+                "editing-message" => obj.editing_message().to_value(),
                 _ => unimplemented!(),
             }
         }
@@ -102,23 +165,67 @@ mod imp {
                 obj.send_chat_action(ChatAction::Typing);
             }));
 
+            // Send the images pasted in the message entry as photos
+            self.message_entry
+                .connect_paste_clipboard(clone!(@weak obj => move |entry| {
+                    let clipboard = entry.clipboard();
+                    if clipboard.formats().contain_gtype(gdk::Texture::static_type()) {
+                        entry.stop_signal_emission("paste-clipboard");
+                        obj.paste_image(&clipboard);
+                    }
+                }));
+
+            self.emoji_chooser
+                .connect_emoji_picked(clone!(@weak obj => move |_, emoji| {
+                    obj.insert_text(emoji);
+                }));
+
+            // Load the stickers only when they're requested for the first time
+            self.sticker_popover
+                .connect_show(clone!(@weak obj => move |_| {
+                    if let Some(chat) = obj.chat() {
+                        obj.imp().sticker_chooser.load(&chat.session());
+                    }
+                }));
+            self.sticker_chooser
+                .connect_sticker_selected(clone!(@weak obj => move |_, sticker| {
+                    obj.send_sticker_message(sticker);
+                    obj.imp().sticker_popover.popdown();
+                }));
+
             // The message entry is always empty at this point, so disable the
             // send-text-message action
             obj.action_set_enabled("chat-action-bar.send-text-message", false);
 
             // Handle the enter key to send the message and also the combination of if with the
-            // right modifier keys to add new lines to the entry
+            // right modifier keys to add new lines to the entry. The escape key cancels the
+            // reply or the editing, if any, and the ctrl+b/i/u combinations wrap the selected
+            // text with the relative markdown formatting.
             let key_events = gtk::EventControllerKey::new();
             self.message_entry.add_controller(&key_events);
             key_events.connect_key_pressed(
                 clone!(@weak obj => @default-return Inhibit(false), move |_, key, _, modifier| {
-                    if !modifier.contains(gdk::ModifierType::CONTROL_MASK)
+                    let is_control_pressed = modifier.contains(gdk::ModifierType::CONTROL_MASK);
+                    let formatting_delimiter = match key {
+                        gdk::Key::b if is_control_pressed => Some("**"),
+                        gdk::Key::i if is_control_pressed => Some("_"),
+                        gdk::Key::u if is_control_pressed => Some("__"),
+                        _ => None,
+                    };
+
+                    if let Some(delimiter) = formatting_delimiter {
+                        obj.wrap_selection(delimiter);
+                        Inhibit(true)
+                    } else if !modifier.contains(gdk::ModifierType::CONTROL_MASK)
                         && !modifier.contains(gdk::ModifierType::SHIFT_MASK)
                         && (key == gdk::Key::Return
                             || key == gdk::Key::KP_Enter)
                     {
                         obj.activate_action("chat-action-bar.send-text-message", None).unwrap();
                         Inhibit(true)
+                    } else if key == gdk::Key::Escape && obj.imp().message_bar.is_visible() {
+                        obj.dismiss_message_bar();
+                        Inhibit(true)
                     } else {
                         Inhibit(false)
                     }
@@ -127,8 +234,8 @@ mod imp {
         }
 
         fn dispose(&self, _obj: &Self::Type) {
-            self.scrolled_window.unparent();
-            self.send_message_button.unparent();
+            self.message_bar.unparent();
+            self.main_box.unparent();
         }
     }
 
@@ -160,10 +267,7 @@ impl ChatActionBar {
     }
 
     fn compose_text_message(&self) -> InputMessageContent {
-        let text = types::FormattedText {
-            text: self.message_entry_text(),
-            ..Default::default()
-        };
+        let text = parse_markdown(&self.message_entry_text());
         let content = types::InputMessageText {
             text,
             clear_draft: true,
@@ -173,31 +277,257 @@ impl ChatActionBar {
         InputMessageContent::InputMessageText(content)
     }
 
-    fn send_text_message(&self) {
+    /// Wraps the selected text of the message entry with the delimiter, or inserts the
+    /// delimiter twice with the cursor in the middle if there's no selection.
+    fn wrap_selection(&self, delimiter: &str) {
+        let buffer = self.imp().message_entry.buffer();
+
+        buffer.begin_user_action();
+
+        if let Some((mut start, mut end)) = buffer.selection_bounds() {
+            let text = buffer.text(&start, &end, true);
+            buffer.delete(&mut start, &mut end);
+            buffer.insert(&mut start, &format!("{0}{1}{0}", delimiter, text));
+        } else {
+            let mut iter = buffer.iter_at_mark(&buffer.get_insert());
+            buffer.insert(&mut iter, &format!("{0}{0}", delimiter));
+            iter.backward_chars(delimiter.chars().count() as i32);
+            buffer.place_cursor(&iter);
+        }
+
+        buffer.end_user_action();
+    }
+
+    /// Inserts the text at the cursor position of the message entry and focuses it.
+    pub fn insert_text(&self, text: &str) {
+        let imp = self.imp();
+        imp.message_entry.buffer().insert_at_cursor(text);
+        imp.message_entry.grab_focus();
+    }
+
+    fn reply_to_message_id(&self) -> i64 {
+        self.reply_to_message()
+            .map(|message| message.id())
+            .unwrap_or_default()
+    }
+
+    fn send_message(&self, content: InputMessageContent) {
+        self.send_message_and_then(content, |_| {});
+    }
+
+    /// Sends the message to the chat, then calls `f` with the message being sent or with `None`
+    /// if it couldn't be sent.
+    fn send_message_and_then<F: FnOnce(Option<types::Message>) + 'static>(
+        &self,
+        content: InputMessageContent,
+        f: F,
+    ) {
         if let Some(chat) = self.chat() {
-            let message = self.compose_text_message();
             let client_id = chat.session().client_id();
             let chat_id = chat.id();
+            let reply_to_message_id = self.reply_to_message_id();
 
             // Send the message
-            RUNTIME.spawn(async move {
-                functions::SendMessage::new()
+            spawn!(async move {
+                let result = functions::SendMessage::new()
                     .chat_id(chat_id)
-                    .input_message_content(message)
+                    .reply_to_message_id(reply_to_message_id)
+                    .input_message_content(content)
                     .send(client_id)
-                    .await
-                    .unwrap();
+                    .await;
+
+                match result {
+                    Ok(enums::Message::Message(message)) => f(Some(message)),
+                    Err(e) => {
+                        log::warn!("Failed to send the message: {:?}", e);
+                        f(None);
+                    }
+                }
             });
 
-            // Reset message entry
+            // Reset the reply
+            self.set_reply_to_message(None);
+        }
+    }
+
+    fn send_text_message(&self) {
+        if let Some(message) = self.editing_message() {
+            self.edit_text_message(&message);
+        } else if self.chat().is_some() {
+            self.send_message(self.compose_text_message());
             self.imp().message_entry.buffer().set_text("");
         }
     }
 
+    pub fn send_sticker_message(&self, sticker: &types::Sticker) {
+        let content = types::InputMessageSticker {
+            sticker: InputFile::Remote(types::InputFileRemote {
+                id: sticker.sticker.remote.id.clone(),
+            }),
+            width: sticker.width,
+            height: sticker.height,
+            emoji: sticker.emoji.clone(),
+            ..Default::default()
+        };
+
+        self.send_message(InputMessageContent::InputMessageSticker(content));
+    }
+
+    fn open_file_chooser(&self) {
+        let file_chooser = gtk::FileChooserNative::new(
+            Some(&gettext("Attach File")),
+            self.root()
+                .and_then(|root| root.downcast::<gtk::Window>().ok())
+                .as_ref(),
+            gtk::FileChooserAction::Open,
+            Some(&gettext("_Open")),
+            Some(&gettext("_Cancel")),
+        );
+        file_chooser.set_modal(true);
+
+        file_chooser.connect_response(clone!(@weak self as obj => move |file_chooser, response| {
+            if response == gtk::ResponseType::Accept {
+                if let Some(file) = file_chooser.file() {
+                    obj.attach_file(&file);
+                }
+            }
+
+            obj.imp().file_chooser.replace(None);
+        }));
+
+        file_chooser.show();
+
+        // Keep the file chooser alive until the user responds
+        self.imp().file_chooser.replace(Some(file_chooser));
+    }
+
+    fn paste_image(&self, clipboard: &gdk::Clipboard) {
+        clipboard.read_texture_async(
+            gio::Cancellable::NONE,
+            clone!(@weak self as obj => move |result| {
+                match result {
+                    Ok(Some(texture)) => {
+                        // Save the image to a temporary file, so that TDLib can upload it
+                        let path = glib::tmp_dir()
+                            .join(format!("telegrand-paste-{}.png", glib::real_time()));
+
+                        if texture.save_to_png(&path) {
+                            obj.attach_temporary_file(path);
+                        } else {
+                            log::warn!("Failed to save the pasted image to {:?}", path);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => log::warn!("Failed to read the image from the clipboard: {:?}", e),
+                }
+            }),
+        );
+    }
+
+    /// Shows a dialog to add a caption to the file before sending it to the chat.
+    pub fn attach_file(&self, file: &gio::File) {
+        if let Some(dialog) = self.send_media_dialog(file) {
+            dialog.connect_send(clone!(@weak self as obj => move |dialog| {
+                match dialog.input_message_content() {
+                    Some(content) => obj.send_message(content),
+                    None => {
+                        log::warn!("Failed to get the path of the file: {}", dialog.file().uri())
+                    }
+                }
+            }));
+            dialog.show();
+        }
+    }
+
+    /// Like `attach_file`, but the file is deleted if it isn't sent or after it's uploaded.
+    fn attach_temporary_file(&self, path: PathBuf) {
+        let dialog = match self.send_media_dialog(&gio::File::for_path(&path)) {
+            Some(dialog) => dialog,
+            None => {
+                remove_temporary_file(&path);
+                return;
+            }
+        };
+
+        let is_sent = Rc::new(Cell::new(false));
+        dialog.connect_send(
+            clone!(@weak self as obj, @strong is_sent, @strong path => move |dialog| {
+                let content = match dialog.input_message_content() {
+                    Some(content) => content,
+                    None => return,
+                };
+                let session = match obj.chat() {
+                    Some(chat) => chat.session(),
+                    None => return,
+                };
+
+                is_sent.set(true);
+
+                let path = path.clone();
+                obj.send_message_and_then(content, move |message| match message {
+                    Some(message) => remove_file_after_upload(&session, &message.content, path),
+                    None => remove_temporary_file(&path),
+                });
+            }),
+        );
+        dialog.connect_close_request(move |_| {
+            if !is_sent.get() {
+                remove_temporary_file(&path);
+            }
+            Inhibit(false)
+        });
+        dialog.show();
+    }
+
+    fn send_media_dialog(&self, file: &gio::File) -> Option<SendMediaDialog> {
+        self.chat()?;
+
+        let parent_window = self.root().and_then(|root| root.downcast().ok());
+        Some(SendMediaDialog::new(&parent_window, file))
+    }
+
+    fn edit_text_message(&self, message: &Message) {
+        let content = self.compose_text_message();
+        let chat = message.chat();
+        let client_id = chat.session().client_id();
+        let chat_id = chat.id();
+        let message_id = message.id();
+
+        // Edit the message
+        RUNTIME.spawn(async move {
+            if let Err(e) = functions::EditMessageText::new()
+                .chat_id(chat_id)
+                .message_id(message_id)
+                .input_message_content(content)
+                .send(client_id)
+                .await
+            {
+                log::warn!("Failed to edit the message: {:?}", e);
+            }
+        });
+
+        // Go back to the draft message
+        self.set_editing_message(None);
+    }
+
     fn save_message_as_draft(&self) {
+        // The message entry contains the text of the edited message, not a draft
+        if self.editing_message().is_some() {
+            return;
+        }
+
         if let Some(chat) = self.chat() {
-            let message = self.compose_text_message();
+            // Keep the markdown in the draft message, so that the formatting isn't lost when
+            // the draft message is restored
+            let message = InputMessageContent::InputMessageText(types::InputMessageText {
+                text: types::FormattedText {
+                    text: self.message_entry_text(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            });
             let draft_message = types::DraftMessage {
+                reply_to_message_id: self.reply_to_message_id(),
                 input_message_text: message,
                 ..Default::default()
             };
@@ -284,10 +614,16 @@ impl ChatActionBar {
             return;
         }
 
+        // Stop editing the message of the previous chat, so that the composed message is
+        // restored and saved as draft
+        self.set_editing_message(None);
         self.save_message_as_draft();
 
         let imp = self.imp();
 
+        self.set_reply_to_message(draft_reply_to_message(chat.as_ref()));
+        self.update_message_bar();
+
         if let Some(ref chat) = chat {
             self.load_draft_message(chat.draft_message());
 
@@ -297,4 +633,159 @@ impl ChatActionBar {
         imp.chat.replace(chat);
         self.notify("chat");
     }
+
+    fn update_message_bar(&self) {
+        let imp = self.imp();
+
+        if let Some(message) = self.editing_message() {
+            imp.message_bar_icon
+                .set_icon_name(Some("document-edit-symbolic"));
+            imp.message_bar_preview.set_message(Some(message));
+            imp.message_bar.set_visible(true);
+        } else if let Some(message) = self.reply_to_message() {
+            imp.message_bar_icon
+                .set_icon_name(Some("mail-reply-sender-symbolic"));
+            imp.message_bar_preview.set_message(Some(message));
+            imp.message_bar.set_visible(true);
+        } else {
+            imp.message_bar.set_visible(false);
+            imp.message_bar_preview.set_message(None);
+        }
+    }
+
+    fn dismiss_message_bar(&self) {
+        if self.editing_message().is_some() {
+            self.set_editing_message(None);
+        } else {
+            self.set_reply_to_message(None);
+        }
+    }
+
+    pub fn reply_to_message(&self) -> Option<Message> {
+        self.imp().reply_to_message.borrow().clone()
+    }
+
+    pub fn set_reply_to_message(&self, reply_to_message: Option<Message>) {
+        if self.reply_to_message() == reply_to_message {
+            return;
+        }
+
+        // Replying and editing are mutually exclusive
+        if reply_to_message.is_some() {
+            self.set_editing_message(None);
+        }
+
+        let imp = self.imp();
+        if reply_to_message.is_some() {
+            imp.message_entry.grab_focus();
+        }
+
+        imp.reply_to_message.replace(reply_to_message);
+        self.update_message_bar();
+        self.notify("reply-to-message");
+    }
+
+    pub fn editing_message(&self) -> Option<Message> {
+        self.imp().editing_message.borrow().clone()
+    }
+
+    pub fn set_editing_message(&self, editing_message: Option<Message>) {
+        if self.editing_message() == editing_message {
+            return;
+        }
+
+        let imp = self.imp();
+
+        match editing_message {
+            Some(ref message) => {
+                // Keep the composed message, so that it can be restored after editing
+                if imp.composed_message.borrow().is_none() {
+                    let buffer = imp.message_entry.buffer();
+                    let text = buffer
+                        .text(&buffer.start_iter(), &buffer.end_iter(), true)
+                        .to_string();
+                    imp.composed_message
+                        .replace(Some((text, self.reply_to_message())));
+                }
+
+                if imp.reply_to_message.take().is_some() {
+                    self.notify("reply-to-message");
+                }
+
+                // Keep the formatting of the message, which is parsed again when it's edited
+                let text = match message.content().0 {
+                    MessageContent::MessageText(content) => {
+                        formatted_text_to_markdown(&content.text)
+                    }
+                    _ => String::new(),
+                };
+
+                imp.editing_message.replace(editing_message.clone());
+                imp.message_entry.buffer().set_text(&text);
+                imp.message_entry.grab_focus();
+            }
+            None => {
+                imp.editing_message.replace(None);
+
+                let (text, reply_to_message) = imp.composed_message.take().unwrap_or_default();
+                if self.reply_to_message() != reply_to_message {
+                    imp.reply_to_message.replace(reply_to_message);
+                    self.notify("reply-to-message");
+                }
+                imp.message_entry.buffer().set_text(&text);
+            }
+        }
+
+        self.update_message_bar();
+        self.notify("editing-message");
+    }
+}
+
+/// Returns the message the draft message of the chat is replying to, if it's loaded in the
+/// chat history.
+fn draft_reply_to_message(chat: Option<&Chat>) -> Option<Message> {
+    chat.and_then(|chat| {
+        chat.draft_message()
+            .and_then(|message| chat.history().message_by_id(message.0.reply_to_message_id))
+    })
+}
+
+/// Removes the temporary file of a message once it has been uploaded.
+fn remove_file_after_upload(session: &Session, content: &MessageContent, path: PathBuf) {
+    let file = match content {
+        MessageContent::MessagePhoto(data) => {
+            let path = path.to_string_lossy();
+            let sizes = &data.photo.sizes;
+            sizes
+                .iter()
+                .find(|size| size.photo.local.path == path)
+                .or_else(|| sizes.last())
+                .map(|size| &size.photo)
+        }
+        MessageContent::MessageDocument(data) => Some(&data.document.document),
+        _ => None,
+    };
+
+    match file {
+        Some(file) if !file.remote.is_uploading_completed => {
+            let (sender, receiver) =
+                glib::MainContext::sync_channel::<types::File>(Default::default(), 5);
+
+            receiver.attach(None, move |file| {
+                if file.remote.is_uploading_completed {
+                    remove_temporary_file(&path);
+                }
+                glib::Continue(!file.remote.is_uploading_completed)
+            });
+
+            session.watch_file_upload(file.id, sender);
+        }
+        _ => remove_temporary_file(&path),
+    }
+}
+
+fn remove_temporary_file(path: &Path) {
+    if let Err(e) = std::fs::remove_file(path) {
+        log::warn!("Failed to remove the temporary file {:?}: {:?}", path, e);
+    }
 }
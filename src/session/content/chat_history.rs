@@ -1,12 +1,17 @@
+use gettextrs::gettext;
 use glib::clone;
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
-use gtk::{gio, glib, CompositeTemplate};
+use gtk::{gdk, gio, glib, CompositeTemplate};
+use tdgrand::{enums, functions};
 
-use crate::session::chat::SponsoredMessage;
-use crate::session::content::{ChatActionBar, ItemRow, UserDialog};
+use crate::session::chat::{Item, SponsoredMessage};
+use crate::session::content::{
+    ChatActionBar, ChatSearchBar, ForwardDialog, ItemRow, MediaViewer, StickerSetDialog, UserDialog,
+};
 use crate::session::{Chat, ChatType, Session};
-use crate::spawn;
+use crate::utils::parse_internal_link;
+use crate::{spawn, ProxyWindow, RUNTIME};
 
 mod imp {
     use super::*;
@@ -19,8 +24,19 @@ mod imp {
     pub struct ChatHistory {
         pub compact: Cell<bool>,
         pub chat: RefCell<Option<Chat>>,
+        /// The id of the newest message of the chat that has been marked as viewed
+        pub last_viewed_message_id: Cell<i64>,
+        /// The id of the message to scroll to when the next chat is opened, if any
+        pub initial_message_id: Cell<Option<i64>>,
+        pub outgoing_message_handler_id: RefCell<Option<glib::SignalHandlerId>>,
+        #[template_child]
+        pub search_bar: TemplateChild<ChatSearchBar>,
         #[template_child]
         pub list_view: TemplateChild<gtk::ListView>,
+        #[template_child]
+        pub scroll_to_bottom_revealer: TemplateChild<gtk::Revealer>,
+        #[template_child]
+        pub chat_action_bar: TemplateChild<ChatActionBar>,
     }
 
     #[glib::object_subclass]
@@ -32,11 +48,97 @@ mod imp {
         fn class_init(klass: &mut Self::Class) {
             ItemRow::static_type();
             ChatActionBar::static_type();
+            ChatSearchBar::static_type();
             Self::bind_template(klass);
 
             klass.install_action("chat-history.view-info", None, move |widget, _, _| {
                 widget.open_info_dialog();
             });
+            klass.install_action(
+                "chat-history.reply",
+                Some("x"),
+                move |widget, _, variant| {
+                    let message_id = variant.and_then(|v| v.get()).unwrap();
+                    widget.reply_to_message(message_id);
+                },
+            );
+            klass.install_action(
+                "chat-history.forward",
+                Some("x"),
+                move |widget, _, variant| {
+                    let message_id = variant.and_then(|v| v.get()).unwrap();
+                    widget.open_forward_dialog(message_id);
+                },
+            );
+            klass.install_action("chat-history.edit", Some("x"), move |widget, _, variant| {
+                let message_id = variant.and_then(|v| v.get()).unwrap();
+                widget.edit_message(message_id);
+            });
+            klass.install_action(
+                "chat-history.delete",
+                Some("x"),
+                move |widget, _, variant| {
+                    let message_id = variant.and_then(|v| v.get()).unwrap();
+                    widget.show_delete_message_dialog(message_id);
+                },
+            );
+            klass.install_action(
+                "chat-history.activate-link",
+                Some("s"),
+                move |widget, _, variant| {
+                    let uri: String = variant.and_then(|v| v.get()).unwrap();
+                    widget.activate_internal_link(&uri);
+                },
+            );
+            klass.install_action(
+                "chat-history.open-telegram-link",
+                Some("s"),
+                move |widget, _, variant| {
+                    let uri: String = variant.and_then(|v| v.get()).unwrap();
+                    widget.open_telegram_link(uri);
+                },
+            );
+            klass.install_action(
+                "chat-history.view-media",
+                Some("x"),
+                move |widget, _, variant| {
+                    let message_id = variant.and_then(|v| v.get()).unwrap();
+                    widget.view_media(message_id);
+                },
+            );
+            klass.install_action(
+                "chat-history.open-sticker-set",
+                Some("x"),
+                move |widget, _, variant| {
+                    let set_id = variant.and_then(|v| v.get()).unwrap();
+                    widget.open_sticker_set(set_id);
+                },
+            );
+            klass.install_action(
+                "chat-history.jump-to-message",
+                Some("x"),
+                move |widget, _, variant| {
+                    let message_id = variant.and_then(|v| v.get()).unwrap();
+                    widget.jump_to_message(message_id);
+                },
+            );
+            klass.install_action(
+                "chat-history.scroll-to-bottom",
+                None,
+                move |widget, _, _| {
+                    widget.scroll_to_bottom();
+                },
+            );
+            klass.install_action("chat-history.search", None, move |widget, _, _| {
+                widget.imp().search_bar.set_search_mode_enabled(true);
+            });
+
+            klass.add_binding_action(
+                gdk::Key::f,
+                gdk::ModifierType::CONTROL_MASK,
+                "chat-history.search",
+                None,
+            );
         }
 
         fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
@@ -98,9 +200,34 @@ mod imp {
         fn constructed(&self, obj: &Self::Type) {
             self.parent_constructed(obj);
 
+            // Attach the files dropped on the chat history
+            let drop_target = gtk::DropTarget::new(gio::File::static_type(), gdk::DragAction::COPY);
+            drop_target.connect_drop(
+                clone!(@weak obj => @default-return false, move |_, value, _, _| {
+                    if obj.chat().is_none() {
+                        return false;
+                    }
+
+                    match value.get::<gio::File>() {
+                        Ok(file) => {
+                            obj.imp().chat_action_bar.attach_file(&file);
+                            true
+                        }
+                        Err(_) => false,
+                    }
+                }),
+            );
+            obj.add_controller(&drop_target);
+
             let adj = self.list_view.vadjustment().unwrap();
             adj.connect_value_changed(clone!(@weak obj => move |adj| {
-                obj.load_older_messages(adj);
+                obj.load_messages_if_needed(adj);
+                obj.update_scroll_to_bottom_button(adj);
+                obj.queue_view_visible_messages();
+            }));
+            adj.connect_changed(clone!(@weak obj => move |adj| {
+                obj.update_scroll_to_bottom_button(adj);
+                obj.queue_view_visible_messages();
             }));
         }
     }
@@ -125,14 +252,203 @@ impl ChatHistory {
         glib::Object::new(&[]).expect("Failed to create ChatHistory")
     }
 
-    fn load_older_messages(&self, adj: &gtk::Adjustment) {
-        if adj.value() < adj.page_size() * 2.0 || adj.upper() <= adj.page_size() * 2.0 {
-            if let Some(chat) = self.chat() {
+    fn load_messages_if_needed(&self, adj: &gtk::Adjustment) {
+        if let Some(chat) = self.chat() {
+            if adj.value() < adj.page_size() * 2.0 || adj.upper() <= adj.page_size() * 2.0 {
                 chat.history().load_older_messages();
+            } else if adj.value() + adj.page_size() * 3.0 >= adj.upper() {
+                chat.history().load_newer_messages();
+            }
+        }
+    }
+
+    /// Loads the messages to show when the chat is opened, starting from the initial message
+    /// or the first unread message, if any.
+    fn load_initial_messages(&self) {
+        let chat = match self.chat() {
+            Some(chat) => chat,
+            None => return,
+        };
+        let history = chat.history();
+
+        if let Some(message_id) = self.imp().initial_message_id.take() {
+            history.remove_unread_divider();
+            self.jump_to_message(message_id);
+        } else if chat.unread_count() > 0 {
+            spawn!(clone!(@weak self as obj, @weak chat => async move {
+                let position = chat.history().load_unread_messages().await;
+
+                // Check that the chat hasn't been changed in the meantime
+                if obj.chat().as_ref() == Some(&chat) {
+                    match position {
+                        Some(position) => obj.scroll_to_position(position),
+                        None => obj.scroll_to_last_item(),
+                    }
+                }
+            }));
+        } else {
+            history.remove_unread_divider();
+
+            if history.has_newer_messages() {
+                spawn!(clone!(@weak self as obj, @weak chat => async move {
+                    chat.history().load_latest_messages().await;
+
+                    if obj.chat().as_ref() == Some(&chat) {
+                        obj.scroll_to_last_item();
+                    }
+                }));
+            } else {
+                let adj = self.imp().list_view.vadjustment().unwrap();
+                self.load_messages_if_needed(&adj);
+            }
+        }
+    }
+
+    /// Sets the message to scroll to when the next chat is opened, instead of the first
+    /// unread message or the last one.
+    pub fn set_initial_message_id(&self, message_id: i64) {
+        self.imp().initial_message_id.set(Some(message_id));
+    }
+
+    /// Scrolls the history to the message, loading the messages around it if it hasn't been
+    /// loaded yet.
+    pub fn jump_to_message(&self, message_id: i64) {
+        let chat = match self.chat() {
+            Some(chat) => chat,
+            None => return,
+        };
+
+        if let Some(position) = chat.history().message_position(message_id) {
+            self.scroll_to_position(position);
+            return;
+        }
+
+        spawn!(clone!(@weak self as obj, @weak chat => async move {
+            chat.history().load_messages_around(message_id).await;
+
+            if obj.chat().as_ref() == Some(&chat) {
+                if let Some(position) = chat.history().message_position(message_id) {
+                    obj.scroll_to_position(position);
+                }
             }
+        }));
+    }
+
+    fn scroll_to_bottom(&self) {
+        let chat = match self.chat() {
+            Some(chat) => chat,
+            None => return,
+        };
+
+        if chat.history().has_newer_messages() {
+            spawn!(clone!(@weak self as obj, @weak chat => async move {
+                chat.history().load_latest_messages().await;
+
+                if obj.chat().as_ref() == Some(&chat) {
+                    obj.scroll_to_last_item();
+                }
+            }));
+        } else {
+            self.scroll_to_last_item();
+        }
+    }
+
+    fn scroll_to_position(&self, position: u32) {
+        self.imp()
+            .list_view
+            .activate_action("list.scroll-to-item", Some(&position.to_variant()))
+            .unwrap();
+    }
+
+    fn scroll_to_last_item(&self) {
+        let n_items = self
+            .imp()
+            .list_view
+            .model()
+            .map(|model| model.n_items())
+            .unwrap_or_default();
+
+        if n_items > 0 {
+            self.scroll_to_position(n_items - 1);
+        }
+    }
+
+    /// Marks the visible messages as viewed once the list view has been allocated again.
+    fn queue_view_visible_messages(&self) {
+        glib::idle_add_local_once(clone!(@weak self as obj => move || {
+            obj.view_visible_messages();
+        }));
+    }
+
+    /// Marks the unread messages that are currently visible as viewed, so that TDLib can mark
+    /// them as read.
+    fn view_visible_messages(&self) {
+        let chat = match self.chat() {
+            Some(chat) => chat,
+            None => return,
+        };
+        let imp = self.imp();
+        let list_view = &*imp.list_view;
+        let last_viewed_message_id = imp
+            .last_viewed_message_id
+            .get()
+            .max(chat.last_read_inbox_message_id());
+        let mut message_ids = vec![];
+
+        let mut child = list_view.first_child();
+        while let Some(widget) = child {
+            let is_visible = widget.compute_bounds(list_view).map_or(false, |bounds| {
+                bounds.y() + bounds.height() > 0.0 && bounds.y() < list_view.height() as f32
+            });
+
+            if is_visible {
+                let message = widget
+                    .first_child()
+                    .and_then(|row| row.downcast::<ItemRow>().ok())
+                    .and_then(|row| row.item())
+                    .and_then(|item| item.downcast::<Item>().ok())
+                    .and_then(|item| item.message().cloned());
+
+                if let Some(message) = message {
+                    if !message.is_outgoing() && message.id() > last_viewed_message_id {
+                        message_ids.push(message.id());
+                    }
+                }
+            }
+
+            child = widget.next_sibling();
+        }
+
+        if let Some(newest_message_id) = message_ids.iter().max() {
+            imp.last_viewed_message_id.set(*newest_message_id);
+
+            let client_id = chat.session().client_id();
+            let chat_id = chat.id();
+            RUNTIME.spawn(async move {
+                if let Err(e) = functions::ViewMessages::new()
+                    .chat_id(chat_id)
+                    .message_ids(message_ids)
+                    .send(client_id)
+                    .await
+                {
+                    log::warn!("Failed to view the messages: {:?}", e);
+                }
+            });
         }
     }
 
+    /// Shows the scroll to bottom button when the last messages aren't visible.
+    fn update_scroll_to_bottom_button(&self, adj: &gtk::Adjustment) {
+        let has_newer_messages = self
+            .chat()
+            .map_or(false, |chat| chat.history().has_newer_messages());
+        let is_near_bottom = adj.value() + adj.page_size() * 1.5 >= adj.upper();
+
+        self.imp()
+            .scroll_to_bottom_revealer
+            .set_reveal_child(has_newer_messages || !is_near_bottom);
+    }
+
     fn open_info_dialog(&self) {
         if let Some(chat) = self.chat() {
             if let ChatType::Private(user) = chat.type_() {
@@ -142,6 +458,212 @@ impl ChatHistory {
         }
     }
 
+    fn reply_to_message(&self, message_id: i64) {
+        if let Some(chat) = self.chat() {
+            if let Some(message) = chat.history().message_by_id(message_id) {
+                self.imp()
+                    .chat_action_bar
+                    .set_reply_to_message(Some(message));
+            }
+        }
+    }
+
+    fn open_forward_dialog(&self, message_id: i64) {
+        if let Some(chat) = self.chat() {
+            if let Some(message) = chat.history().message_by_id(message_id) {
+                let dialog = ForwardDialog::new(&self.parent_window(), &message);
+                dialog.show();
+            }
+        }
+    }
+
+    fn edit_message(&self, message_id: i64) {
+        if let Some(chat) = self.chat() {
+            if let Some(message) = chat.history().message_by_id(message_id) {
+                self.imp()
+                    .chat_action_bar
+                    .set_editing_message(Some(message));
+            }
+        }
+    }
+
+    fn show_delete_message_dialog(&self, message_id: i64) {
+        let message = match self
+            .chat()
+            .and_then(|chat| chat.history().message_by_id(message_id))
+        {
+            Some(message) => message,
+            None => return,
+        };
+
+        let dialog = gtk::MessageDialog::builder()
+            .text(&gettext("Delete Message?"))
+            .buttons(gtk::ButtonsType::Cancel)
+            .modal(true)
+            .transient_for(&self.parent_window().unwrap())
+            .build();
+
+        if message.can_be_deleted_only_for_self() {
+            dialog.add_action_widget(
+                &gtk::Button::builder()
+                    .use_underline(true)
+                    .label(&gettext("Delete for _Me"))
+                    .css_classes(vec!["destructive-action".to_string()])
+                    .build(),
+                gtk::ResponseType::Reject,
+            );
+        }
+
+        if message.can_be_deleted_for_all_users() {
+            dialog.add_action_widget(
+                &gtk::Button::builder()
+                    .use_underline(true)
+                    .label(&gettext("Delete for _Everyone"))
+                    .css_classes(vec!["destructive-action".to_string()])
+                    .build(),
+                gtk::ResponseType::Accept,
+            );
+        }
+
+        dialog.run_async(clone!(@weak message => move |dialog, response_id| {
+            dialog.close();
+
+            let revoke = match response_id {
+                gtk::ResponseType::Reject => false,
+                gtk::ResponseType::Accept => true,
+                _ => return,
+            };
+
+            let chat = message.chat();
+            let client_id = chat.session().client_id();
+            let chat_id = chat.id();
+            let message_id = message.id();
+
+            RUNTIME.spawn(async move {
+                if let Err(e) = functions::DeleteMessages::new()
+                    .chat_id(chat_id)
+                    .message_ids(vec![message_id])
+                    .revoke(revoke)
+                    .send(client_id)
+                    .await
+                {
+                    log::warn!("Failed to delete the message: {:?}", e);
+                }
+            });
+        }));
+    }
+
+    /// Handles a link created for an entity of a formatted text, like a mention or a hashtag.
+    fn activate_internal_link(&self, uri: &str) {
+        let (action, value) = match parse_internal_link(uri) {
+            Some(link) => link,
+            None => {
+                log::warn!("Unexpected internal link: {}", uri);
+                return;
+            }
+        };
+        let session = match self.chat() {
+            Some(chat) => chat.session(),
+            None => return,
+        };
+
+        match action {
+            "mention" => {
+                let client_id = session.client_id();
+                spawn!(clone!(@weak session => async move {
+                    let result = functions::SearchPublicChat::new()
+                        .username(value)
+                        .send(client_id)
+                        .await;
+
+                    match result {
+                        Ok(enums::Chat::Chat(chat)) => {
+                            session.set_selected_chat(Some(session.chat_list().get(chat.id)));
+                        }
+                        Err(e) => log::warn!("Failed to search the public chat: {:?}", e),
+                    }
+                }));
+            }
+            "user" => {
+                let user_id = match value.parse() {
+                    Ok(user_id) => user_id,
+                    Err(_) => return,
+                };
+                let client_id = session.client_id();
+                spawn!(clone!(@weak session => async move {
+                    let result = functions::CreatePrivateChat::new()
+                        .user_id(user_id)
+                        .send(client_id)
+                        .await;
+
+                    match result {
+                        Ok(enums::Chat::Chat(chat)) => {
+                            session.set_selected_chat(Some(session.chat_list().get(chat.id)));
+                        }
+                        Err(e) => log::warn!("Failed to open the private chat: {:?}", e),
+                    }
+                }));
+            }
+            "search" => self.imp().search_bar.search_messages(&value),
+            "bot-command" => self
+                .imp()
+                .chat_action_bar
+                .insert_text(&format!("{} ", value)),
+            "bank-card" => self.clipboard().set_text(&value),
+            _ => log::warn!("Unexpected internal link: {}", uri),
+        }
+    }
+
+    /// Opens the proxy settings of the session with the proxy of the link filled in, if it's a
+    /// proxy link, or lets the desktop open the link otherwise.
+    fn open_telegram_link(&self, uri: String) {
+        let client_id = match self.chat() {
+            Some(chat) => chat.session().client_id(),
+            None => return,
+        };
+
+        spawn!(clone!(@weak self as obj => async move {
+            let result = functions::GetInternalLinkType::new()
+                .link(uri.clone())
+                .send(client_id)
+                .await;
+
+            match result {
+                Ok(enums::InternalLinkType::Proxy(data)) => {
+                    let proxy_window = ProxyWindow::new(&obj.parent_window(), client_id);
+                    proxy_window.set_new_proxy(&data.server, data.port, &data.r#type);
+                    proxy_window.present();
+                }
+                result => {
+                    if let Err(e) = result {
+                        log::warn!("Failed to get the type of the link {}: {:?}", uri, e);
+                    }
+                    gtk::show_uri(obj.parent_window().as_ref(), &uri, gdk::CURRENT_TIME);
+                }
+            }
+        }));
+    }
+
+    fn view_media(&self, message_id: i64) {
+        if let Some(message) = self
+            .chat()
+            .and_then(|chat| chat.history().message_by_id(message_id))
+        {
+            let viewer = MediaViewer::new(&self.parent_window(), &message);
+            viewer.show();
+        }
+    }
+
+    fn open_sticker_set(&self, set_id: i64) {
+        if let Some(chat) = self.chat() {
+            let dialog = StickerSetDialog::new(&self.parent_window(), &chat.session(), set_id);
+            dialog.connect_sticker_selected(clone!(@weak self as obj => move |_, sticker| {
+                obj.imp().chat_action_bar.send_sticker_message(sticker);
+            }));
+            dialog.show();
+        }
+    }
+
     fn parent_window(&self) -> Option<gtk::Window> {
         self.root()?.downcast().ok()
     }
@@ -165,7 +687,22 @@ impl ChatHistory {
         }
 
         let imp = self.imp();
+
+        if let Some(handler_id) = imp.outgoing_message_handler_id.take() {
+            if let Some(old_chat) = self.chat() {
+                old_chat.history().disconnect(handler_id);
+            }
+        }
+
         if let Some(ref chat) = chat {
+            // Show the messages sent by the user, even when they're reading older messages
+            let handler_id = chat.history().connect_outgoing_message_added(
+                clone!(@weak self as obj => move |_| {
+                    obj.scroll_to_last_item();
+                }),
+            );
+            imp.outgoing_message_handler_id.replace(Some(handler_id));
+
             match chat.type_() {
                 ChatType::Private(_) => self.action_set_enabled("chat-history.view-info", true),
                 _ => self.action_set_enabled("chat-history.view-info", false),
@@ -193,9 +730,12 @@ impl ChatHistory {
         }
 
         imp.chat.replace(chat);
+        imp.last_viewed_message_id.set(0);
         self.notify("chat");
 
+        self.load_initial_messages();
+
         let adj = imp.list_view.vadjustment().unwrap();
-        self.load_older_messages(&adj);
+        self.update_scroll_to_bottom_button(&adj);
     }
 }
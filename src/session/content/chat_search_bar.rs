@@ -0,0 +1,694 @@
+use gettextrs::gettext;
+use glib::clone;
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+use gtk::{gdk, glib, CompositeTemplate};
+use tdgrand::enums::{self, MessageContent, SearchMessagesFilter};
+use tdgrand::functions;
+use tdgrand::types::{MessageSenderChat, MessageSenderUser};
+
+use crate::session::chat::{Message, MessageSender};
+use crate::session::content::item_row::sender_name;
+use crate::session::{Chat, ChatType};
+use crate::spawn;
+use crate::utils::escape;
+
+/// The number of results requested at once
+const RESULTS_LIMIT: i32 = 50;
+
+/// The maximum number of members of a group listed in the sender drop down
+const MEMBERS_LIMIT: i32 = 200;
+
+/// The filters of the message types, in the same order as the items of the filter drop down
+const FILTERS: [SearchMessagesFilter; 8] = [
+    SearchMessagesFilter::Empty,
+    SearchMessagesFilter::Photo,
+    SearchMessagesFilter::Video,
+    SearchMessagesFilter::Animation,
+    SearchMessagesFilter::Document,
+    SearchMessagesFilter::Audio,
+    SearchMessagesFilter::VoiceNote,
+    SearchMessagesFilter::Url,
+];
+
+mod imp {
+    use super::*;
+    use adw::subclass::prelude::BinImpl;
+    use once_cell::sync::{Lazy, OnceCell};
+    use std::cell::{Cell, RefCell};
+
+    #[derive(Debug, Default, CompositeTemplate)]
+    #[template(resource = "/com/github/melix99/telegrand/ui/content-chat-search-bar.ui")]
+    pub struct ChatSearchBar {
+        pub chat: RefCell<Option<Chat>>,
+        /// The senders that can be chosen after the first item of the sender drop down
+        pub senders: RefCell<Vec<MessageSender>>,
+        /// The results loaded so far, ordered from the newest
+        pub results: RefCell<Vec<Message>>,
+        pub total_count: Cell<i32>,
+        /// Increased at every new search, used to ignore the results of the previous ones
+        pub search_id: Cell<u32>,
+        pub is_loading: Cell<bool>,
+        /// Whether the next result should be selected once the next page has been loaded
+        pub select_after_loading: Cell<bool>,
+        pub sender_handler_id: OnceCell<glib::SignalHandlerId>,
+        #[template_child]
+        pub search_bar: TemplateChild<gtk::SearchBar>,
+        #[template_child]
+        pub search_entry: TemplateChild<gtk::SearchEntry>,
+        #[template_child]
+        pub sender_drop_down: TemplateChild<gtk::DropDown>,
+        #[template_child]
+        pub sender_list: TemplateChild<gtk::StringList>,
+        #[template_child]
+        pub filter_drop_down: TemplateChild<gtk::DropDown>,
+        #[template_child]
+        pub counter_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub results_scrolled_window: TemplateChild<gtk::ScrolledWindow>,
+        #[template_child]
+        pub results_list: TemplateChild<gtk::ListBox>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for ChatSearchBar {
+        const NAME: &'static str = "ContentChatSearchBar";
+        type Type = super::ChatSearchBar;
+        type ParentType = adw::Bin;
+
+        fn class_init(klass: &mut Self::Class) {
+            Self::bind_template(klass);
+
+            klass.install_action("chat-search-bar.previous", None, move |widget, _, _| {
+                widget.select_older_result();
+            });
+            klass.install_action("chat-search-bar.next", None, move |widget, _, _| {
+                widget.select_newer_result();
+            });
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for ChatSearchBar {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                vec![
+                    glib::ParamSpecObject::new(
+                        "chat",
+                        "Chat",
+                        "The chat to search messages in",
+                        Chat::static_type(),
+                        glib::ParamFlags::READWRITE | glib::ParamFlags::EXPLICIT_NOTIFY,
+                    ),
+                    glib::ParamSpecBoolean::new(
+                        "search-mode-enabled",
+                        "Search Mode Enabled",
+                        "Whether the search bar is shown or not",
+                        false,
+                        glib::ParamFlags::READWRITE | glib::ParamFlags::EXPLICIT_NOTIFY,
+                    ),
+                ]
+            });
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(
+            &self,
+            obj: &Self::Type,
+            _id: usize,
+            value: &glib::Value,
+            pspec: &glib::ParamSpec,
+        ) {
+            match pspec.name() {
+                "chat" => obj.set_chat(value.get().unwrap()),
+                "search-mode-enabled" => obj.set_search_mode_enabled(value.get().unwrap()),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn property(&self, obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            match pspec.name() {
+                "chat" => obj.chat().to_value(),
+                "search-mode-enabled" => obj.search_mode_enabled().to_value(),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn constructed(&self, obj: &Self::Type) {
+            self.parent_constructed(obj);
+
+            self.search_bar.connect_entry(&*self.search_entry);
+            self.search_bar.connect_search_mode_enabled_notify(
+                clone!(@weak obj => move |search_bar| {
+                    if search_bar.is_search_mode() {
+                        obj.update_senders();
+                    } else {
+                        obj.reset();
+                    }
+                    obj.notify("search-mode-enabled");
+                }),
+            );
+
+            self.search_entry
+                .connect_search_changed(clone!(@weak obj => move |_| obj.search()));
+            self.search_entry
+                .connect_activate(clone!(@weak obj => move |_| obj.select_older_result()));
+
+            // Handle the up and down keys to move between the results, like in the chat history
+            // where the older messages are shown above the newer ones. This is done in the
+            // capture phase, before the entry handles the keys itself.
+            let key_events = gtk::EventControllerKey::new();
+            key_events.set_propagation_phase(gtk::PropagationPhase::Capture);
+            self.search_entry.add_controller(&key_events);
+            key_events.connect_key_pressed(
+                clone!(@weak obj => @default-return gtk::Inhibit(false), move |_, key, _, _| {
+                    match key {
+                        gdk::Key::Up => obj.select_older_result(),
+                        gdk::Key::Down => obj.select_newer_result(),
+                        _ => return gtk::Inhibit(false),
+                    }
+                    gtk::Inhibit(true)
+                }),
+            );
+            let sender_handler_id = self
+                .sender_drop_down
+                .connect_selected_notify(clone!(@weak obj => move |_| obj.search()));
+            self.sender_handler_id.set(sender_handler_id).unwrap();
+            self.filter_drop_down
+                .connect_selected_notify(clone!(@weak obj => move |_| obj.search()));
+
+            self.results_list
+                .connect_row_selected(clone!(@weak obj => move |_, row| {
+                    if let Some(row) = row {
+                        obj.jump_to_result(row.index() as usize);
+                    }
+                    obj.update_counter();
+                }));
+            self.results_scrolled_window.connect_edge_reached(
+                clone!(@weak obj => move |_, position| {
+                    if position == gtk::PositionType::Bottom {
+                        obj.load_results();
+                    }
+                }),
+            );
+
+            obj.update_counter();
+        }
+    }
+
+    impl WidgetImpl for ChatSearchBar {}
+    impl BinImpl for ChatSearchBar {}
+}
+
+glib::wrapper! {
+    pub struct ChatSearchBar(ObjectSubclass<imp::ChatSearchBar>)
+        @extends gtk::Widget, adw::Bin;
+}
+
+impl Default for ChatSearchBar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChatSearchBar {
+    pub fn new() -> Self {
+        glib::Object::new(&[]).expect("Failed to create ChatSearchBar")
+    }
+
+    /// Fills the sender drop down with the current user and the other members of the chat.
+    fn update_senders(&self) {
+        let chat = match self.chat() {
+            Some(chat) => chat,
+            None => return,
+        };
+        let me = chat.session().me();
+
+        match chat.type_() {
+            ChatType::Private(_) | ChatType::Secret(_) => {
+                let mut senders = vec![MessageSender::User(me.clone())];
+                if let Some(user) = chat.type_().user().filter(|user| *user != &me) {
+                    senders.push(MessageSender::User(user.clone()));
+                }
+                self.set_senders(senders);
+            }
+            ChatType::Supergroup(supergroup) if supergroup.is_channel() => {
+                self.set_senders(vec![]);
+            }
+            ChatType::BasicGroup(_) | ChatType::Supergroup(_) => {
+                self.set_senders(vec![MessageSender::User(me)]);
+                self.load_members(&chat);
+            }
+        }
+    }
+
+    /// Adds the members of the group to the senders, once they're received from TDLib.
+    fn load_members(&self, chat: &Chat) {
+        let client_id = chat.session().client_id();
+        let chat_id = chat.id();
+
+        spawn!(clone!(@weak self as obj, @weak chat => async move {
+            let result = functions::SearchChatMembers::new()
+                .chat_id(chat_id)
+                .limit(MEMBERS_LIMIT)
+                .send(client_id)
+                .await;
+
+            // Ignore the members of a chat that isn't shown anymore
+            if obj.chat().as_ref() != Some(&chat) {
+                return;
+            }
+
+            match result {
+                Ok(enums::ChatMembers::ChatMembers(data)) => {
+                    let session = chat.session();
+                    let mut senders = obj.imp().senders.borrow().clone();
+
+                    for member in data.members {
+                        let sender = MessageSender::from_td_object(&member.member_id, &session);
+                        if !senders.iter().any(|s| s.id() == sender.id()) {
+                            senders.push(sender);
+                        }
+                    }
+
+                    obj.set_senders(senders);
+                }
+                Err(e) => log::warn!("Failed to search the chat members: {:?}", e),
+            }
+        }));
+    }
+
+    /// Replaces the senders of the sender drop down, keeping the selected one, if any.
+    fn set_senders(&self, senders: Vec<MessageSender>) {
+        let imp = self.imp();
+        let me = match self.chat() {
+            Some(chat) => chat.session().me(),
+            None => return,
+        };
+
+        let selected_sender_id = (imp.sender_drop_down.selected() as usize)
+            .checked_sub(1)
+            .and_then(|index| imp.senders.borrow().get(index).map(MessageSender::id));
+        let selected = selected_sender_id
+            .and_then(|id| senders.iter().position(|sender| sender.id() == id))
+            .map_or(0, |index| index as u32 + 1);
+
+        let mut labels = vec![gettext("Anyone")];
+        labels.extend(senders.iter().map(|sender| match sender {
+            MessageSender::User(user) if user == &me => gettext("You"),
+            MessageSender::User(user) => sender_name(user),
+            MessageSender::Chat(chat) => chat.title(),
+        }));
+        let labels: Vec<&str> = labels.iter().map(String::as_str).collect();
+
+        // Changing the items of the list also changes the selected item, so don't restart the
+        // search unless the selected sender isn't available anymore
+        let sender_handler_id = imp.sender_handler_id.get().unwrap();
+        imp.sender_drop_down.block_signal(sender_handler_id);
+        imp.senders.replace(senders);
+        imp.sender_list
+            .splice(0, imp.sender_list.n_items(), &labels);
+        imp.sender_drop_down.set_selected(selected);
+        imp.sender_drop_down.unblock_signal(sender_handler_id);
+
+        imp.sender_drop_down
+            .set_visible(imp.sender_list.n_items() > 1);
+
+        if selected_sender_id.is_some() && selected == 0 {
+            self.search();
+        }
+    }
+
+    /// Starts a new search with the current query and filters.
+    fn search(&self) {
+        let imp = self.imp();
+
+        imp.search_id.set(imp.search_id.get().wrapping_add(1));
+        imp.is_loading.set(false);
+        imp.select_after_loading.set(false);
+        imp.results.borrow_mut().clear();
+        imp.total_count.set(0);
+
+        while let Some(row) = imp.results_list.first_child() {
+            imp.results_list.remove(&row);
+        }
+        imp.results_scrolled_window.set_visible(false);
+
+        let has_filters =
+            imp.sender_drop_down.selected() > 0 || imp.filter_drop_down.selected() > 0;
+        if !imp.search_entry.text().trim().is_empty() || has_filters {
+            self.load_results();
+        }
+
+        self.update_counter();
+    }
+
+    /// Loads the next page of results of the current search, if any.
+    fn load_results(&self) {
+        let imp = self.imp();
+        let chat = match self.chat() {
+            Some(chat) => chat,
+            None => return,
+        };
+
+        let loaded_count = imp.results.borrow().len();
+        let is_first_page = loaded_count == 0;
+        if imp.is_loading.get()
+            || (!is_first_page && loaded_count >= imp.total_count.get() as usize)
+        {
+            return;
+        }
+
+        let client_id = chat.session().client_id();
+        let chat_id = chat.id();
+        let query = imp.search_entry.text().trim().to_string();
+        let filter = FILTERS
+            .get(imp.filter_drop_down.selected() as usize)
+            .cloned()
+            .unwrap_or(SearchMessagesFilter::Empty);
+        let sender_id = (imp.sender_drop_down.selected() as usize)
+            .checked_sub(1)
+            .and_then(|index| imp.senders.borrow().get(index).cloned())
+            .map(|sender| match sender {
+                MessageSender::User(user) => {
+                    enums::MessageSender::User(MessageSenderUser { user_id: user.id() })
+                }
+                MessageSender::Chat(chat) => {
+                    enums::MessageSender::Chat(MessageSenderChat { chat_id: chat.id() })
+                }
+            });
+        let from_message_id = imp
+            .results
+            .borrow()
+            .last()
+            .map(|message| message.id())
+            .unwrap_or_default();
+        let search_id = imp.search_id.get();
+
+        imp.is_loading.set(true);
+
+        spawn!(clone!(@weak self as obj, @weak chat => async move {
+            let mut request = functions::SearchChatMessages::new()
+                .chat_id(chat_id)
+                .query(query)
+                .from_message_id(from_message_id)
+                .limit(RESULTS_LIMIT)
+                .filter(filter);
+            if let Some(sender_id) = sender_id {
+                request = request.sender_id(sender_id);
+            }
+            let result = request.send(client_id).await;
+
+            let imp = obj.imp();
+
+            // Ignore the results of the previous searches
+            if imp.search_id.get() != search_id {
+                return;
+            }
+            imp.is_loading.set(false);
+
+            match result {
+                Ok(enums::Messages::Messages(result)) => {
+                    let messages: Vec<Message> = result
+                        .messages
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|message| Message::new(message, &chat))
+                        .collect();
+                    let added = messages.len();
+
+                    // Stop loading if TDLib doesn't return more results than it reported
+                    imp.total_count.set(if added == 0 {
+                        imp.results.borrow().len() as i32
+                    } else {
+                        result.total_count
+                    });
+
+                    obj.add_results(messages);
+
+                    if is_first_page || imp.select_after_loading.take() {
+                        obj.select_result(loaded_count);
+                    }
+                }
+                Err(e) => log::warn!("Failed to search the chat messages: {:?}", e),
+            }
+
+            obj.update_counter();
+        }));
+    }
+
+    fn add_results(&self, messages: Vec<Message>) {
+        let imp = self.imp();
+        let query = imp.search_entry.text();
+
+        for message in &messages {
+            imp.results_list.append(&result_row(message, &query));
+        }
+
+        imp.results.borrow_mut().extend(messages);
+        imp.results_scrolled_window
+            .set_visible(!imp.results.borrow().is_empty());
+    }
+
+    fn selected_index(&self) -> Option<usize> {
+        self.imp()
+            .results_list
+            .selected_row()
+            .map(|row| row.index() as usize)
+    }
+
+    fn select_result(&self, index: usize) {
+        let imp = self.imp();
+
+        if let Some(row) = imp.results_list.row_at_index(index as i32) {
+            imp.results_list.select_row(Some(&row));
+
+            // Keep the selected row visible
+            if let Some(bounds) = row.compute_bounds(&*imp.results_list) {
+                let adj = imp.results_scrolled_window.vadjustment();
+                let top = bounds.y() as f64;
+                let bottom = top + bounds.height() as f64;
+
+                if top < adj.value() {
+                    adj.set_value(top);
+                } else if bottom > adj.value() + adj.page_size() {
+                    adj.set_value(bottom - adj.page_size());
+                }
+            }
+        }
+    }
+
+    /// Selects the result following the selected one, which is an older message.
+    fn select_older_result(&self) {
+        let index = self.selected_index().map_or(0, |index| index + 1);
+
+        if index < self.imp().results.borrow().len() {
+            self.select_result(index);
+        } else {
+            self.imp().select_after_loading.set(true);
+            self.load_results();
+        }
+    }
+
+    /// Selects the result preceding the selected one, which is a newer message.
+    fn select_newer_result(&self) {
+        if let Some(index) = self.selected_index().and_then(|index| index.checked_sub(1)) {
+            self.select_result(index);
+        }
+    }
+
+    fn jump_to_result(&self, index: usize) {
+        let message_id = match self.imp().results.borrow().get(index) {
+            Some(message) => message.id(),
+            None => return,
+        };
+
+        self.activate_action(
+            "chat-history.jump-to-message",
+            Some(&message_id.to_variant()),
+        )
+        .unwrap();
+    }
+
+    fn update_counter(&self) {
+        let imp = self.imp();
+        let total_count = imp.total_count.get();
+        let selected_index = self.selected_index();
+
+        let label = match selected_index {
+            // Translators: This is the position of the selected result, like "3 of 10"
+            Some(index) => gettext!("{} of {}", index + 1, total_count),
+            None if total_count == 0 && imp.search_id.get() > 0 && !imp.is_loading.get() => {
+                if imp.search_entry.text().trim().is_empty()
+                    && imp.sender_drop_down.selected() == 0
+                    && imp.filter_drop_down.selected() == 0
+                {
+                    String::new()
+                } else {
+                    gettext("No Results")
+                }
+            }
+            None => String::new(),
+        };
+        imp.counter_label.set_label(&label);
+
+        self.action_set_enabled(
+            "chat-search-bar.previous",
+            selected_index.map_or(total_count > 0, |index| (index as i32) + 1 < total_count),
+        );
+        self.action_set_enabled(
+            "chat-search-bar.next",
+            selected_index.map_or(false, |index| index > 0),
+        );
+    }
+
+    fn reset(&self) {
+        let imp = self.imp();
+
+        imp.search_entry.set_text("");
+        imp.sender_drop_down.set_selected(0);
+        imp.filter_drop_down.set_selected(0);
+        self.search();
+    }
+
+    pub fn chat(&self) -> Option<Chat> {
+        self.imp().chat.borrow().clone()
+    }
+
+    pub fn set_chat(&self, chat: Option<Chat>) {
+        if self.chat() == chat {
+            return;
+        }
+
+        self.imp().chat.replace(chat);
+        self.set_search_mode_enabled(false);
+        self.reset();
+        self.notify("chat");
+    }
+
+    /// Shows the search bar and searches the messages of the chat matching the query.
+    pub fn search_messages(&self, query: &str) {
+        self.set_search_mode_enabled(true);
+        self.imp().search_entry.set_text(query);
+    }
+
+    pub fn search_mode_enabled(&self) -> bool {
+        self.imp().search_bar.is_search_mode()
+    }
+
+    pub fn set_search_mode_enabled(&self, search_mode_enabled: bool) {
+        self.imp().search_bar.set_search_mode(search_mode_enabled);
+    }
+}
+
+fn result_row(message: &Message, query: &str) -> gtk::ListBoxRow {
+    let sender_label = gtk::Label::builder()
+        .hexpand(true)
+        .xalign(0.0)
+        .ellipsize(gtk::pango::EllipsizeMode::End)
+        .css_classes(vec!["sender-text".to_string()])
+        .build();
+    message
+        .sender_name_expression()
+        .bind(&sender_label, "label", glib::Object::NONE);
+
+    let date_label = gtk::Label::builder()
+        .label(&format_date(message.date()))
+        .css_classes(vec!["dim-label".to_string()])
+        .build();
+
+    let header_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    header_box.append(&sender_label);
+    header_box.append(&date_label);
+
+    let text_label = gtk::Label::builder()
+        .label(&highlight_matches(
+            &message_text(message.content().0),
+            query,
+        ))
+        .use_markup(true)
+        .single_line_mode(true)
+        .xalign(0.0)
+        .ellipsize(gtk::pango::EllipsizeMode::End)
+        .build();
+
+    let content_box = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .spacing(3)
+        .margin_top(6)
+        .margin_bottom(6)
+        .margin_start(12)
+        .margin_end(12)
+        .build();
+    content_box.append(&header_box);
+    content_box.append(&text_label);
+
+    gtk::ListBoxRow::builder().child(&content_box).build()
+}
+
+fn format_date(date: i32) -> String {
+    let datetime = glib::DateTime::from_unix_utc(date as i64)
+        .and_then(|t| t.to_local())
+        .unwrap();
+    let now = glib::DateTime::now_local().unwrap();
+
+    if datetime.ymd() == now.ymd() {
+        // Translators: This is a time format for the search results without seconds
+        datetime.format(&gettext("%l:%M %p")).unwrap().to_string()
+    } else {
+        datetime.format("%x").unwrap().to_string()
+    }
+}
+
+/// Returns the text of the message, or a description of it if it has no text.
+fn message_text(content: MessageContent) -> String {
+    let (text, description) = match content {
+        MessageContent::MessageText(data) => (data.text.text, String::new()),
+        MessageContent::MessagePhoto(data) => (data.caption.text, gettext("Photo")),
+        MessageContent::MessageVideo(data) => (data.caption.text, gettext("Video")),
+        MessageContent::MessageAnimation(data) => (data.caption.text, gettext("GIF")),
+        MessageContent::MessageDocument(data) => (data.caption.text, data.document.file_name),
+        MessageContent::MessageAudio(data) => (
+            data.caption.text,
+            format!("{} – {}", data.audio.performer, data.audio.title),
+        ),
+        MessageContent::MessageVoiceNote(data) => (data.caption.text, gettext("Voice Message")),
+        MessageContent::MessageSticker(data) => (String::new(), data.sticker.emoji),
+        _ => (String::new(), gettext("Unsupported Message")),
+    };
+
+    let text = if text.is_empty() { description } else { text };
+    text.replace('\n', " ")
+}
+
+/// Escapes the text and makes the occurrences of the query bold, ignoring the case.
+fn highlight_matches(text: &str, query: &str) -> String {
+    let query = query.trim().to_lowercase();
+    let lowercase_text = text.to_lowercase();
+
+    // Lowercasing can change the length of some characters, in which case the positions of
+    // the matches don't correspond to the original text
+    if query.is_empty() || lowercase_text.len() != text.len() {
+        return escape(text);
+    }
+
+    let mut markup = String::new();
+    let mut end = 0;
+
+    for (start, part) in lowercase_text.match_indices(&query) {
+        let (before, matched) = match (text.get(end..start), text.get(start..start + part.len())) {
+            (Some(before), Some(matched)) => (before, matched),
+            _ => return escape(text),
+        };
+
+        markup.push_str(&escape(before));
+        markup.push_str(&format!("<b>{}</b>", escape(matched)));
+        end = start + part.len();
+    }
+    markup.push_str(&escape(&text[end..]));
+
+    markup
+}
@@ -0,0 +1,246 @@
+use glib::clone;
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+use gtk::{glib, CompositeTemplate};
+use tdgrand::functions;
+
+use crate::session::chat::Message;
+use crate::session::{Chat, ChatListType};
+use crate::{spawn, RUNTIME};
+
+mod imp {
+    use super::*;
+    use adw::subclass::prelude::AdwWindowImpl;
+    use once_cell::sync::{Lazy, OnceCell};
+    use std::cell::{Cell, RefCell};
+
+    #[derive(Debug, Default, CompositeTemplate)]
+    #[template(resource = "/com/github/melix99/telegrand/ui/content-forward-dialog.ui")]
+    pub struct ForwardDialog {
+        pub message: OnceCell<Message>,
+        pub filter: OnceCell<gtk::CustomFilter>,
+        pub sorter: OnceCell<gtk::CustomSorter>,
+        /// The ids of the chats found by the search, in order of relevance
+        pub searched_chats: RefCell<Vec<i64>>,
+        /// Increased at every new search, used to ignore the results of the previous ones
+        pub search_id: Cell<u32>,
+        #[template_child]
+        pub search_entry: TemplateChild<gtk::SearchEntry>,
+        #[template_child]
+        pub list_view: TemplateChild<gtk::ListView>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for ForwardDialog {
+        const NAME: &'static str = "ContentForwardDialog";
+        type Type = super::ForwardDialog;
+        type ParentType = adw::Window;
+
+        fn class_init(klass: &mut Self::Class) {
+            Self::bind_template(klass);
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for ForwardDialog {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                vec![glib::ParamSpecObject::new(
+                    "message",
+                    "Message",
+                    "The message to forward",
+                    Message::static_type(),
+                    glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
+                )]
+            });
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(
+            &self,
+            _obj: &Self::Type,
+            _id: usize,
+            value: &glib::Value,
+            pspec: &glib::ParamSpec,
+        ) {
+            match pspec.name() {
+                "message" => self.message.set(value.get().unwrap()).unwrap(),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn property(&self, obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            match pspec.name() {
+                "message" => obj.message().to_value(),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn constructed(&self, obj: &Self::Type) {
+            self.parent_constructed(obj);
+
+            obj.setup_chat_list();
+
+            self.search_entry
+                .connect_search_changed(clone!(@weak obj => move |entry| {
+                    let query = entry.text().to_string();
+                    obj.search(query);
+                }));
+
+            self.list_view
+                .connect_activate(clone!(@weak obj => move |list_view, position| {
+                    if let Some(chat) = list_view
+                        .model()
+                        .and_then(|model| model.item(position))
+                        .and_then(|item| item.downcast::<Chat>().ok())
+                    {
+                        obj.forward_to(&chat);
+                    }
+                }));
+        }
+    }
+
+    impl WidgetImpl for ForwardDialog {}
+    impl WindowImpl for ForwardDialog {}
+    impl AdwWindowImpl for ForwardDialog {}
+}
+
+glib::wrapper! {
+    pub struct ForwardDialog(ObjectSubclass<imp::ForwardDialog>)
+        @extends gtk::Widget, gtk::Window, adw::Window;
+}
+
+impl ForwardDialog {
+    pub fn new(parent_window: &Option<gtk::Window>, message: &Message) -> Self {
+        glib::Object::new(&[("transient-for", parent_window), ("message", message)])
+            .expect("Failed to create ForwardDialog")
+    }
+
+    fn setup_chat_list(&self) {
+        let imp = self.imp();
+        let chat_list = self.message().chat().session().chat_list().to_owned();
+
+        let filter = gtk::CustomFilter::new(
+            clone!(@weak self as obj => @default-return false, move |item| {
+                let imp = obj.imp();
+                let chat = item.downcast_ref::<Chat>().unwrap();
+
+                if imp.search_entry.text().is_empty() {
+                    chat.order(ChatListType::Main) > 0
+                } else {
+                    imp.searched_chats.borrow().contains(&chat.id())
+                }
+            }),
+        );
+        let sorter = gtk::CustomSorter::new(
+            clone!(@weak self as obj => @default-return gtk::Ordering::Equal, move |obj1, obj2| {
+                let imp = obj.imp();
+                let chat1 = obj1.downcast_ref::<Chat>().unwrap();
+                let chat2 = obj2.downcast_ref::<Chat>().unwrap();
+
+                if imp.search_entry.text().is_empty() {
+                    chat2
+                        .order(ChatListType::Main)
+                        .cmp(&chat1.order(ChatListType::Main))
+                        .into()
+                } else {
+                    // Keep the order of relevance of the search results
+                    let searched_chats = imp.searched_chats.borrow();
+                    let position = |chat: &Chat| {
+                        searched_chats
+                            .iter()
+                            .position(|chat_id| *chat_id == chat.id())
+                    };
+                    position(chat1).cmp(&position(chat2)).into()
+                }
+            }),
+        );
+
+        chat_list.connect_positions_changed(clone!(@weak filter, @weak sorter => move |_| {
+            filter.changed(gtk::FilterChange::Different);
+            sorter.changed(gtk::SorterChange::Different);
+        }));
+
+        let filter_model = gtk::FilterListModel::new(Some(&chat_list), Some(&filter));
+        let sort_model = gtk::SortListModel::new(Some(&filter_model), Some(&sorter));
+        let selection = gtk::NoSelection::new(Some(&sort_model));
+
+        imp.list_view.set_model(Some(&selection));
+        imp.filter.set(filter).unwrap();
+        imp.sorter.set(sorter).unwrap();
+    }
+
+    fn update_search_results(&self) {
+        let imp = self.imp();
+        imp.filter
+            .get()
+            .unwrap()
+            .changed(gtk::FilterChange::Different);
+        imp.sorter
+            .get()
+            .unwrap()
+            .changed(gtk::SorterChange::Different);
+    }
+
+    fn search(&self, query: String) {
+        let imp = self.imp();
+        imp.searched_chats.borrow_mut().clear();
+        imp.search_id.set(imp.search_id.get().wrapping_add(1));
+
+        if query.is_empty() {
+            self.update_search_results();
+        } else {
+            let chat_list = self.message().chat().session().chat_list().to_owned();
+            let search_id = imp.search_id.get();
+
+            spawn!(clone!(@weak self as obj, @weak chat_list => async move {
+                let result = chat_list.search(query).await;
+
+                // Ignore the results of the previous searches
+                let imp = obj.imp();
+                if imp.search_id.get() != search_id {
+                    return;
+                }
+
+                match result {
+                    Ok(chats) => {
+                        imp.searched_chats
+                            .borrow_mut()
+                            .extend(chats.iter().map(|chat| chat.id()));
+                        obj.update_search_results();
+                    }
+                    Err(e) => log::warn!("Failed to search chats: {:?}", e),
+                }
+            }));
+        }
+    }
+
+    fn forward_to(&self, chat: &Chat) {
+        let message = self.message();
+        let client_id = chat.session().client_id();
+        let chat_id = chat.id();
+        let from_chat_id = message.chat().id();
+        let message_id = message.id();
+
+        RUNTIME.spawn(async move {
+            if let Err(e) = functions::ForwardMessages::new()
+                .chat_id(chat_id)
+                .from_chat_id(from_chat_id)
+                .message_ids(vec![message_id])
+                .send(client_id)
+                .await
+            {
+                log::warn!("Failed to forward the message: {:?}", e);
+            }
+        });
+
+        self.close();
+    }
+
+    pub fn message(&self) -> &Message {
+        self.imp().message.get().unwrap()
+    }
+}
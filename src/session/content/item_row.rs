@@ -8,7 +8,9 @@ use std::borrow::Cow;
 use tdgrand::enums::{MessageContent, UserType};
 
 use crate::session::chat::{ChatType, Item, ItemType, Message, MessageSender, SponsoredMessage};
-use crate::session::content::message_row::{MessagePhoto, MessageSticker, MessageText};
+use crate::session::content::message_row::{
+    MessageAudio, MessageDocument, MessagePhoto, MessageSticker, MessageText, MessageVideo,
+};
 use crate::session::content::{EventRow, MessageRow, MessageRowExt};
 use crate::session::User;
 use crate::utils::MESSAGE_TRUNCATED_LENGTH;
@@ -105,7 +107,18 @@ impl ItemRow {
                             MessageContent::MessagePhoto(_) => {
                                 self.set_child_row::<MessagePhoto>(message.to_owned().upcast())
                             }
-                            MessageContent::MessageSticker(data) if !data.sticker.is_animated => {
+                            MessageContent::MessageVideo(_)
+                            | MessageContent::MessageAnimation(_) => {
+                                self.set_child_row::<MessageVideo>(message.to_owned().upcast())
+                            }
+                            MessageContent::MessageVoiceNote(_)
+                            | MessageContent::MessageAudio(_) => {
+                                self.set_child_row::<MessageAudio>(message.to_owned().upcast())
+                            }
+                            MessageContent::MessageDocument(_) => {
+                                self.set_child_row::<MessageDocument>(message.to_owned().upcast())
+                            }
+                            MessageContent::MessageSticker(_) => {
                                 self.set_child_row::<MessageSticker>(message.to_owned().upcast())
                             }
                             MessageContent::MessageChatChangeTitle(data) => {
@@ -325,6 +338,10 @@ impl ItemRow {
                         let child = self.get_or_create_event_row();
                         child.set_label(&date);
                     }
+                    ItemType::UnreadDivider(_) => {
+                        let child = self.get_or_create_event_row();
+                        child.set_label(&format!("<b>{}</b>", gettext("Unread messages")));
+                    }
                 }
             } else if let Some(sponsored_message) = item.downcast_ref::<SponsoredMessage>() {
                 let content = &sponsored_message.content().0;
@@ -336,6 +353,9 @@ impl ItemRow {
             } else {
                 unreachable!("Unexpected item type: {:?}", item);
             }
+        } else if let Some(child) = self.child().and_then(|w| w.downcast::<MessageVideo>().ok()) {
+            // Stop the video of the unbound row
+            child.set_message(None);
         }
 
         self.imp().item.replace(item);
@@ -0,0 +1,601 @@
+use gettextrs::gettext;
+use glib::clone;
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+use gtk::{gdk, gio, glib, CompositeTemplate};
+use std::path::Path;
+use tdgrand::enums::{self, MessageContent, SearchMessagesFilter};
+use tdgrand::functions;
+use tdgrand::types::{File, Message as TelegramMessage};
+
+use crate::session::chat::Message;
+use crate::session::Chat;
+use crate::spawn;
+
+const MAX_ZOOM: f64 = 8.0;
+const ZOOM_STEP: f64 = 1.5;
+const SEARCH_LIMIT: i32 = 20;
+
+/// A photo or a video of the chat shown by the media viewer
+#[derive(Clone, Debug)]
+struct MediaItem {
+    message_id: i64,
+    date: i32,
+    content: MessageContent,
+}
+
+impl MediaItem {
+    fn new(message_id: i64, date: i32, content: MessageContent) -> Option<Self> {
+        match content {
+            MessageContent::MessagePhoto(_) | MessageContent::MessageVideo(_) => Some(Self {
+                message_id,
+                date,
+                content,
+            }),
+            _ => None,
+        }
+    }
+
+    fn file(&self) -> Option<File> {
+        match self.content {
+            MessageContent::MessagePhoto(ref data) => {
+                data.photo.sizes.last().map(|size| size.photo.clone())
+            }
+            MessageContent::MessageVideo(ref data) => Some(data.video.video.clone()),
+            _ => None,
+        }
+    }
+
+    fn is_video(&self) -> bool {
+        matches!(self.content, MessageContent::MessageVideo(_))
+    }
+}
+
+mod imp {
+    use super::*;
+    use adw::subclass::prelude::AdwWindowImpl;
+    use once_cell::sync::{Lazy, OnceCell};
+    use std::cell::{Cell, RefCell};
+
+    #[derive(Debug, Default, CompositeTemplate)]
+    #[template(resource = "/com/github/melix99/telegrand/ui/content-media-viewer.ui")]
+    pub struct MediaViewer {
+        pub chat: OnceCell<Chat>,
+        /// The media of the chat loaded so far, sorted from the oldest to the newest
+        pub items: RefCell<Vec<MediaItem>>,
+        pub index: Cell<usize>,
+        pub has_older_items: Cell<bool>,
+        pub has_newer_items: Cell<bool>,
+        pub is_loading_older: Cell<bool>,
+        pub is_loading_newer: Cell<bool>,
+        /// The id of the file currently shown, used to ignore the updates of the files of
+        /// the previous items
+        pub file_id: Cell<i32>,
+        pub local_path: RefCell<Option<String>>,
+        pub texture: RefCell<Option<gdk::Texture>>,
+        pub zoom: Cell<f64>,
+        pub drag_start: Cell<(f64, f64)>,
+        pub file_chooser: RefCell<Option<gtk::FileChooserNative>>,
+        #[template_child]
+        pub window_title: TemplateChild<adw::WindowTitle>,
+        #[template_child]
+        pub stack: TemplateChild<gtk::Stack>,
+        #[template_child]
+        pub scrolled_window: TemplateChild<gtk::ScrolledWindow>,
+        #[template_child]
+        pub picture: TemplateChild<gtk::Picture>,
+        #[template_child]
+        pub video: TemplateChild<gtk::Video>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for MediaViewer {
+        const NAME: &'static str = "ContentMediaViewer";
+        type Type = super::MediaViewer;
+        type ParentType = adw::Window;
+
+        fn class_init(klass: &mut Self::Class) {
+            Self::bind_template(klass);
+
+            klass.install_action("media-viewer.previous", None, move |widget, _, _| {
+                widget.show_previous();
+            });
+            klass.install_action("media-viewer.next", None, move |widget, _, _| {
+                widget.show_next();
+            });
+            klass.install_action("media-viewer.zoom-in", None, move |widget, _, _| {
+                widget.set_zoom(widget.imp().zoom.get() * ZOOM_STEP);
+            });
+            klass.install_action("media-viewer.zoom-out", None, move |widget, _, _| {
+                widget.set_zoom(widget.imp().zoom.get() / ZOOM_STEP);
+            });
+            klass.install_action("media-viewer.save", None, move |widget, _, _| {
+                widget.save_media();
+            });
+            klass.install_action("media-viewer.copy", None, move |widget, _, _| {
+                widget.copy_media();
+            });
+
+            klass.add_binding_action(
+                gdk::Key::Escape,
+                gdk::ModifierType::empty(),
+                "window.close",
+                None,
+            );
+            for key in [gdk::Key::Left, gdk::Key::Up] {
+                klass.add_binding_action(
+                    key,
+                    gdk::ModifierType::empty(),
+                    "media-viewer.previous",
+                    None,
+                );
+            }
+            for key in [gdk::Key::Right, gdk::Key::Down] {
+                klass.add_binding_action(
+                    key,
+                    gdk::ModifierType::empty(),
+                    "media-viewer.next",
+                    None,
+                );
+            }
+            for key in [gdk::Key::plus, gdk::Key::equal, gdk::Key::KP_Add] {
+                klass.add_binding_action(
+                    key,
+                    gdk::ModifierType::empty(),
+                    "media-viewer.zoom-in",
+                    None,
+                );
+            }
+            for key in [gdk::Key::minus, gdk::Key::KP_Subtract] {
+                klass.add_binding_action(
+                    key,
+                    gdk::ModifierType::empty(),
+                    "media-viewer.zoom-out",
+                    None,
+                );
+            }
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for MediaViewer {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                vec![glib::ParamSpecObject::new(
+                    "chat",
+                    "Chat",
+                    "The chat of the media shown by this viewer",
+                    Chat::static_type(),
+                    glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
+                )]
+            });
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(
+            &self,
+            _obj: &Self::Type,
+            _id: usize,
+            value: &glib::Value,
+            pspec: &glib::ParamSpec,
+        ) {
+            match pspec.name() {
+                "chat" => self.chat.set(value.get().unwrap()).unwrap(),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn property(&self, obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            match pspec.name() {
+                "chat" => obj.chat().to_value(),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn constructed(&self, obj: &Self::Type) {
+            self.parent_constructed(obj);
+
+            self.zoom.set(1.0);
+            self.has_older_items.set(true);
+            self.has_newer_items.set(true);
+
+            // Pan the zoomed photos by dragging them
+            let drag_gesture = gtk::GestureDrag::new();
+            drag_gesture.connect_drag_begin(clone!(@weak obj => move |_, _, _| {
+                let imp = obj.imp();
+                imp.drag_start.set((
+                    imp.scrolled_window.hadjustment().value(),
+                    imp.scrolled_window.vadjustment().value(),
+                ));
+            }));
+            drag_gesture.connect_drag_update(clone!(@weak obj => move |_, x, y| {
+                let imp = obj.imp();
+                let (start_x, start_y) = imp.drag_start.get();
+                imp.scrolled_window.hadjustment().set_value(start_x - x);
+                imp.scrolled_window.vadjustment().set_value(start_y - y);
+            }));
+            self.scrolled_window.add_controller(&drag_gesture);
+        }
+    }
+
+    impl WidgetImpl for MediaViewer {}
+    impl WindowImpl for MediaViewer {}
+    impl AdwWindowImpl for MediaViewer {}
+}
+
+glib::wrapper! {
+    pub struct MediaViewer(ObjectSubclass<imp::MediaViewer>)
+        @extends gtk::Widget, gtk::Window, adw::Window;
+}
+
+impl MediaViewer {
+    pub fn new(parent_window: &Option<gtk::Window>, message: &Message) -> Self {
+        let viewer: Self =
+            glib::Object::new(&[("transient-for", parent_window), ("chat", &message.chat())])
+                .expect("Failed to create MediaViewer");
+
+        if let Some(item) = MediaItem::new(message.id(), message.date(), message.content().0) {
+            viewer.imp().items.replace(vec![item]);
+            viewer.show_item(0);
+        }
+
+        viewer
+    }
+
+    fn show_previous(&self) {
+        let index = self.imp().index.get();
+        if index > 0 {
+            self.show_item(index - 1);
+        }
+    }
+
+    fn show_next(&self) {
+        let imp = self.imp();
+        let index = imp.index.get();
+        if index + 1 < imp.items.borrow().len() {
+            self.show_item(index + 1);
+        }
+    }
+
+    fn show_item(&self, index: usize) {
+        let imp = self.imp();
+        let item = match imp.items.borrow().get(index) {
+            Some(item) => item.clone(),
+            None => return,
+        };
+
+        imp.index.set(index);
+
+        let datetime = glib::DateTime::from_unix_local(item.date as i64).unwrap();
+        imp.window_title.set_title(&self.chat().title());
+        imp.window_title
+            .set_subtitle(&datetime.format("%x %X").unwrap());
+
+        // Reset the shown media
+        imp.local_path.replace(None);
+        imp.texture.replace(None);
+        imp.picture.set_paintable(gdk::Paintable::NONE);
+        imp.video.set_media_stream(gtk::MediaStream::NONE);
+        imp.stack.set_visible_child_name("loading");
+        self.set_zoom(1.0);
+
+        if let Some(file) = item.file() {
+            imp.file_id.set(file.id);
+
+            if file.local.is_downloading_completed {
+                self.load_media(&file, item.is_video());
+            } else {
+                self.download_media(file.id, item.is_video());
+            }
+        }
+
+        self.update_actions();
+        self.load_items_if_needed();
+    }
+
+    /// Loads more media of the chat if the shown one is at one of the ends of the loaded ones.
+    fn load_items_if_needed(&self) {
+        let imp = self.imp();
+        let index = imp.index.get();
+        let len = imp.items.borrow().len();
+
+        if index == 0 && imp.has_older_items.get() {
+            self.load_items(false);
+        }
+        if index + 1 == len && imp.has_newer_items.get() {
+            self.load_items(true);
+        }
+    }
+
+    fn download_media(&self, file_id: i32, is_video: bool) {
+        let (sender, receiver) = glib::MainContext::sync_channel::<File>(Default::default(), 5);
+
+        receiver.attach(
+            None,
+            clone!(@weak self as obj => @default-return glib::Continue(false), move |file| {
+                // Check that another media hasn't been shown in the meantime
+                if obj.imp().file_id.get() != file.id {
+                    return glib::Continue(false);
+                }
+
+                if file.local.is_downloading_completed {
+                    obj.load_media(&file, is_video);
+                }
+
+                glib::Continue(!file.local.is_downloading_completed)
+            }),
+        );
+
+        self.chat().session().download_file(file_id, sender);
+    }
+
+    fn load_media(&self, file: &File, is_video: bool) {
+        let imp = self.imp();
+        let path = file.local.path.clone();
+
+        imp.local_path.replace(Some(path.clone()));
+        self.update_actions();
+
+        if is_video {
+            let media_file = gtk::MediaFile::for_filename(&path);
+
+            // Pause the other media of the session, so that only one is played at a time
+            media_file.connect_playing_notify(clone!(@weak self as obj => move |media_file| {
+                if media_file.is_playing() {
+                    obj.chat()
+                        .session()
+                        .set_playing_media(media_file.upcast_ref());
+                }
+            }));
+
+            imp.video.set_media_stream(Some(&media_file));
+            imp.stack.set_visible_child_name("video");
+            return;
+        }
+
+        let file_id = file.id;
+        let session = self.chat().session();
+
+        spawn!(clone!(@weak self as obj => async move {
+            let result = session.load_image(file_id, &path, None).await;
+
+            // Check that another media hasn't been shown in the meantime
+            let imp = obj.imp();
+            if imp.file_id.get() != file_id {
+                return;
+            }
+
+            match result {
+                Ok(texture) => {
+                    imp.picture.set_paintable(Some(&texture));
+                    imp.texture.replace(Some(texture));
+                    imp.stack.set_visible_child_name("picture");
+                }
+                Err(e) => {
+                    log::warn!("Failed to load a photo: {}", e);
+                    imp.stack.set_visible_child_name("error");
+                }
+            }
+
+            obj.update_actions();
+        }));
+    }
+
+    /// Loads the media of the chat older or newer than the ones already loaded.
+    fn load_items(&self, newer: bool) {
+        let imp = self.imp();
+        let is_loading = if newer {
+            &imp.is_loading_newer
+        } else {
+            &imp.is_loading_older
+        };
+        if is_loading.get() {
+            return;
+        }
+
+        let from_message_id = {
+            let items = imp.items.borrow();
+            let item = if newer { items.last() } else { items.first() };
+            match item {
+                Some(item) => item.message_id,
+                None => return,
+            }
+        };
+        // A negative offset is needed to get the messages newer than the given one
+        let (offset, limit) = if newer {
+            (-SEARCH_LIMIT, SEARCH_LIMIT + 1)
+        } else {
+            (0, SEARCH_LIMIT)
+        };
+        let chat = self.chat();
+        let client_id = chat.session().client_id();
+        let chat_id = chat.id();
+
+        is_loading.set(true);
+
+        spawn!(clone!(@weak self as obj => async move {
+            let result = functions::SearchChatMessages::new()
+                .chat_id(chat_id)
+                .from_message_id(from_message_id)
+                .offset(offset)
+                .limit(limit)
+                .filter(SearchMessagesFilter::PhotoAndVideo)
+                .send(client_id)
+                .await;
+
+            let imp = obj.imp();
+            if newer {
+                imp.is_loading_newer.set(false);
+            } else {
+                imp.is_loading_older.set(false);
+            }
+
+            match result {
+                Ok(enums::Messages::Messages(result)) => {
+                    let added = obj.add_items(result.messages.unwrap_or_default());
+                    if added == 0 {
+                        if newer {
+                            imp.has_newer_items.set(false);
+                        } else {
+                            imp.has_older_items.set(false);
+                        }
+                    }
+
+                    obj.load_items_if_needed();
+                }
+                Err(e) => log::warn!("Failed to search the media of the chat: {:?}", e),
+            }
+        }));
+    }
+
+    /// Adds the media of the messages that aren't loaded yet, returning how many were added.
+    fn add_items(&self, messages: Vec<TelegramMessage>) -> usize {
+        let imp = self.imp();
+        let mut items = imp.items.borrow_mut();
+        let current_message_id = items[imp.index.get()].message_id;
+        let len = items.len();
+
+        for message in messages {
+            if items.iter().any(|item| item.message_id == message.id) {
+                continue;
+            }
+
+            if let Some(item) = MediaItem::new(message.id, message.date, message.content) {
+                items.push(item);
+            }
+        }
+
+        items.sort_by_key(|item| item.message_id);
+
+        let index = items
+            .iter()
+            .position(|item| item.message_id == current_message_id)
+            .unwrap();
+        imp.index.set(index);
+
+        let added = items.len() - len;
+        drop(items);
+
+        self.update_actions();
+        added
+    }
+
+    fn update_actions(&self) {
+        let imp = self.imp();
+        let index = imp.index.get();
+        let len = imp.items.borrow().len();
+        let zoom = imp.zoom.get();
+        let has_texture = imp.texture.borrow().is_some();
+
+        self.action_set_enabled("media-viewer.previous", index > 0);
+        self.action_set_enabled("media-viewer.next", index + 1 < len);
+        self.action_set_enabled("media-viewer.zoom-in", has_texture && zoom < MAX_ZOOM);
+        self.action_set_enabled("media-viewer.zoom-out", has_texture && zoom > 1.0);
+        self.action_set_enabled("media-viewer.save", imp.local_path.borrow().is_some());
+        self.action_set_enabled("media-viewer.copy", has_texture);
+    }
+
+    /// Sets the zoom of the photo, where 1 means that the photo fits the window.
+    fn set_zoom(&self, zoom: f64) {
+        let imp = self.imp();
+        let zoom = zoom.clamp(1.0, MAX_ZOOM);
+        let scrolled_window = &*imp.scrolled_window;
+
+        if zoom <= 1.0 {
+            scrolled_window.set_policy(gtk::PolicyType::Never, gtk::PolicyType::Never);
+            imp.picture.set_size_request(-1, -1);
+        } else {
+            // Keep the same area of the photo at the center of the window
+            let hadjustment = scrolled_window.hadjustment();
+            let vadjustment = scrolled_window.vadjustment();
+            let center_x = relative_center(&hadjustment);
+            let center_y = relative_center(&vadjustment);
+
+            scrolled_window.set_policy(gtk::PolicyType::Automatic, gtk::PolicyType::Automatic);
+            imp.picture.set_size_request(
+                (scrolled_window.width() as f64 * zoom) as i32,
+                (scrolled_window.height() as f64 * zoom) as i32,
+            );
+
+            glib::idle_add_local_once(move || {
+                hadjustment
+                    .set_value(center_x * hadjustment.upper() - hadjustment.page_size() / 2.0);
+                vadjustment
+                    .set_value(center_y * vadjustment.upper() - vadjustment.page_size() / 2.0);
+            });
+        }
+
+        imp.zoom.set(zoom);
+        self.update_actions();
+    }
+
+    fn save_media(&self) {
+        let imp = self.imp();
+        let path = match imp.local_path.borrow().clone() {
+            Some(path) => path,
+            None => return,
+        };
+
+        let file_chooser = gtk::FileChooserNative::new(
+            Some(&gettext("Save File")),
+            Some(self),
+            gtk::FileChooserAction::Save,
+            Some(&gettext("_Save")),
+            Some(&gettext("_Cancel")),
+        );
+        file_chooser.set_modal(true);
+        if let Some(name) = Path::new(&path).file_name() {
+            file_chooser.set_current_name(&name.to_string_lossy());
+        }
+
+        file_chooser.connect_response(clone!(@weak self as obj => move |file_chooser, response| {
+            if response == gtk::ResponseType::Accept {
+                if let Some(destination) = file_chooser.file() {
+                    // Copy the file out of the TDLib cache
+                    gio::File::for_path(&path).copy_async(
+                        &destination,
+                        gio::FileCopyFlags::OVERWRITE,
+                        glib::PRIORITY_DEFAULT,
+                        gio::Cancellable::NONE,
+                        None,
+                        |result| {
+                            if let Err(e) = result {
+                                log::warn!("Failed to save the media: {:?}", e);
+                            }
+                        },
+                    );
+                }
+            }
+
+            obj.imp().file_chooser.replace(None);
+        }));
+
+        file_chooser.show();
+
+        // Keep the file chooser alive until the user responds
+        imp.file_chooser.replace(Some(file_chooser));
+    }
+
+    fn copy_media(&self) {
+        if let Some(texture) = self.imp().texture.borrow().as_ref() {
+            self.clipboard().set_texture(texture);
+        }
+    }
+
+    pub fn chat(&self) -> &Chat {
+        self.imp().chat.get().unwrap()
+    }
+}
+
+/// Returns the position of the center of the visible area of the adjustment, relative to
+/// its whole range.
+fn relative_center(adjustment: &gtk::Adjustment) -> f64 {
+    if adjustment.upper() > 0.0 {
+        (adjustment.value() + adjustment.page_size() / 2.0) / adjustment.upper()
+    } else {
+        0.5
+    }
+}
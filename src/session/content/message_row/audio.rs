@@ -0,0 +1,318 @@
+use gettextrs::gettext;
+use glib::{clone, closure};
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+use gtk::{glib, CompositeTemplate};
+use tdgrand::enums::MessageContent;
+use tdgrand::types::File;
+
+use crate::session::chat::{BoxedMessageContent, Message};
+use crate::session::content::message_row::{bind_forward_label, MessageReply, Waveform};
+use crate::session::content::{MessageRow, MessageRowExt};
+use crate::utils::parse_formatted_text;
+use crate::Session;
+
+mod imp {
+    use super::*;
+    use glib::WeakRef;
+    use std::cell::{Cell, RefCell};
+
+    #[derive(Debug, Default, CompositeTemplate)]
+    #[template(resource = "/com/github/melix99/telegrand/ui/content-message-audio.ui")]
+    pub struct MessageAudio {
+        pub bindings: RefCell<Vec<gtk::ExpressionWatch>>,
+        pub handler_id: RefCell<Option<glib::SignalHandlerId>>,
+        pub old_message: WeakRef<glib::Object>,
+        /// The id of the audio file currently shown, used to ignore the updates of the
+        /// files of the previous messages
+        pub file_id: Cell<i32>,
+        /// The duration of the audio, in seconds
+        pub duration: Cell<i32>,
+        pub media_file: RefCell<Option<gtk::MediaFile>>,
+        #[template_child]
+        pub forward_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub reply: TemplateChild<MessageReply>,
+        #[template_child]
+        pub play_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub title_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub waveform: TemplateChild<Waveform>,
+        #[template_child]
+        pub seek_bar: TemplateChild<gtk::Scale>,
+        #[template_child]
+        pub duration_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub caption_label: TemplateChild<gtk::Label>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for MessageAudio {
+        const NAME: &'static str = "ContentMessageAudio";
+        type Type = super::MessageAudio;
+        type ParentType = MessageRow;
+
+        fn class_init(klass: &mut Self::Class) {
+            Waveform::static_type();
+            Self::bind_template(klass);
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for MessageAudio {
+        fn constructed(&self, obj: &Self::Type) {
+            self.parent_constructed(obj);
+            obj.connect_message_notify(|obj, _| obj.update_widget());
+
+            self.caption_label.connect_activate_link(
+                clone!(@weak obj => @default-return gtk::Inhibit(false), move |label, uri| {
+                    obj.upcast_ref::<MessageRow>().activate_link(label, uri)
+                }),
+            );
+
+            self.play_button
+                .connect_clicked(clone!(@weak obj => move |_| obj.toggle_playback()));
+
+            self.seek_bar.connect_change_value(
+                clone!(@weak obj => @default-return gtk::Inhibit(false), move |_, _, value| {
+                    if let Some(media_file) = obj.imp().media_file.borrow().as_ref() {
+                        media_file.seek((value * 1_000_000.0) as i64);
+                    }
+                    gtk::Inhibit(false)
+                }),
+            );
+        }
+    }
+
+    impl WidgetImpl for MessageAudio {}
+}
+
+glib::wrapper! {
+    pub struct MessageAudio(ObjectSubclass<imp::MessageAudio>)
+        @extends gtk::Widget, MessageRow;
+}
+
+impl MessageAudio {
+    fn update_widget(&self) {
+        let imp = self.imp();
+        let mut bindings = imp.bindings.borrow_mut();
+
+        while let Some(binding) = bindings.pop() {
+            binding.unwatch();
+        }
+
+        if let Some(old_message) = imp.old_message.upgrade() {
+            if let Some(id) = imp.handler_id.take() {
+                old_message.disconnect(id);
+            }
+        }
+
+        if let Some(message) = self.message() {
+            let message = message.downcast_ref::<Message>().unwrap();
+
+            // Setup caption expression
+            let caption_binding = Message::this_expression("content")
+                .chain_closure::<String>(closure!(|_: Message, content: BoxedMessageContent| {
+                    match content.0 {
+                        MessageContent::MessageVoiceNote(data) => {
+                            parse_formatted_text(data.caption)
+                        }
+                        MessageContent::MessageAudio(data) => parse_formatted_text(data.caption),
+                        _ => unreachable!(),
+                    }
+                }))
+                .bind(&*imp.caption_label, "label", Some(message));
+            bindings.push(caption_binding);
+
+            let caption_visible_binding = imp
+                .caption_label
+                .property_expression("label")
+                .chain_closure::<bool>(closure!(|_: Option<glib::Object>, label: String| {
+                    !label.is_empty()
+                }))
+                .bind(&*imp.caption_label, "visible", glib::Object::NONE);
+            bindings.push(caption_visible_binding);
+
+            if let Some(forward_binding) = bind_forward_label(&imp.forward_label, message) {
+                bindings.push(forward_binding);
+            }
+
+            imp.reply.update_from_message(message);
+
+            // Load audio
+            let handler_id =
+                message.connect_content_notify(clone!(@weak self as obj => move |message, _| {
+                    obj.update_audio(message);
+                }));
+            imp.handler_id.replace(Some(handler_id));
+            self.update_audio(message);
+        }
+
+        imp.old_message.set(self.message().as_ref());
+    }
+
+    fn update_audio(&self, message: &Message) {
+        let imp = self.imp();
+
+        let (file, duration) = match message.content().0 {
+            MessageContent::MessageVoiceNote(data) => {
+                imp.title_label.set_visible(false);
+                imp.waveform.set_visible(true);
+                imp.waveform.set_waveform(&data.voice_note.waveform);
+
+                (data.voice_note.voice, data.voice_note.duration)
+            }
+            MessageContent::MessageAudio(data) => {
+                let audio = data.audio;
+                let title = if audio.title.is_empty() {
+                    audio.file_name
+                } else if audio.performer.is_empty() {
+                    audio.title
+                } else {
+                    format!("{} - {}", audio.performer, audio.title)
+                };
+
+                imp.title_label.set_label(&title);
+                imp.title_label.set_visible(true);
+                imp.waveform.set_visible(false);
+
+                (audio.audio, audio.duration)
+            }
+            _ => return,
+        };
+
+        // Don't reset the player if only other parts of the content, like the caption,
+        // have been changed
+        if imp.file_id.get() == file.id && imp.media_file.borrow().is_some() {
+            return;
+        }
+
+        if let Some(media_file) = imp.media_file.take() {
+            media_file.set_playing(false);
+        }
+
+        imp.file_id.set(file.id);
+        imp.duration.set(duration);
+        imp.play_button.set_sensitive(true);
+        imp.seek_bar.set_range(0.0, duration.max(1) as f64);
+        self.update_playback_state(false);
+        self.update_position(0);
+    }
+
+    fn toggle_playback(&self) {
+        let imp = self.imp();
+
+        if let Some(media_file) = imp.media_file.borrow().as_ref() {
+            if media_file.is_ended() {
+                media_file.seek(0);
+            }
+            media_file.set_playing(!media_file.is_playing());
+            return;
+        }
+
+        if let Some(message) = self.message().and_then(|m| m.downcast::<Message>().ok()) {
+            let file = match message.content().0 {
+                MessageContent::MessageVoiceNote(data) => data.voice_note.voice,
+                MessageContent::MessageAudio(data) => data.audio.audio,
+                _ => return,
+            };
+
+            if file.local.is_downloading_completed {
+                self.load_audio_from_path(&file.local.path);
+            } else {
+                self.download_audio(file.id, &message.chat().session());
+            }
+        }
+    }
+
+    fn download_audio(&self, file_id: i32, session: &Session) {
+        let (sender, receiver) = glib::MainContext::sync_channel::<File>(Default::default(), 5);
+
+        receiver.attach(
+            None,
+            clone!(@weak self as obj => @default-return glib::Continue(false), move |file| {
+                // Check that the widget hasn't been recycled for another message
+                if obj.imp().file_id.get() != file.id {
+                    return glib::Continue(false);
+                }
+
+                if file.local.is_downloading_completed {
+                    obj.imp().play_button.set_sensitive(true);
+                    obj.load_audio_from_path(&file.local.path);
+                }
+
+                glib::Continue(true)
+            }),
+        );
+
+        // Disable the button until the audio is downloaded
+        self.imp().play_button.set_sensitive(false);
+
+        session.download_file(file_id, sender);
+    }
+
+    fn load_audio_from_path(&self, path: &str) {
+        let media_file = gtk::MediaFile::for_filename(path);
+
+        media_file.connect_playing_notify(clone!(@weak self as obj => move |media_file| {
+            let is_playing = media_file.is_playing();
+            obj.update_playback_state(is_playing);
+
+            // Pause the other media of the session, so that only one is played at a time
+            if is_playing {
+                if let Some(message) = obj.message().and_then(|m| m.downcast::<Message>().ok()) {
+                    message
+                        .chat()
+                        .session()
+                        .set_playing_media(media_file.upcast_ref());
+                }
+            }
+        }));
+        media_file.connect_timestamp_notify(clone!(@weak self as obj => move |media_file| {
+            obj.update_position(media_file.timestamp());
+        }));
+
+        media_file.play();
+
+        self.imp().media_file.replace(Some(media_file));
+    }
+
+    fn update_playback_state(&self, is_playing: bool) {
+        let play_button = &*self.imp().play_button;
+
+        if is_playing {
+            play_button.set_icon_name("media-playback-pause-symbolic");
+            play_button.set_tooltip_text(Some(&gettext("Pause")));
+        } else {
+            play_button.set_icon_name("media-playback-start-symbolic");
+            play_button.set_tooltip_text(Some(&gettext("Play")));
+        }
+    }
+
+    /// Updates the widgets showing the playback position, which is in microseconds.
+    fn update_position(&self, timestamp: i64) {
+        let imp = self.imp();
+        let duration = imp.duration.get();
+        let position = (timestamp / 1_000_000) as i32;
+
+        imp.seek_bar.set_value(timestamp as f64 / 1_000_000.0);
+        imp.waveform.set_progress(if duration > 0 {
+            (timestamp as f64 / 1_000_000.0 / duration as f64).min(1.0)
+        } else {
+            0.0
+        });
+        imp.duration_label.set_label(&format!(
+            "{} / {}",
+            format_duration(position),
+            format_duration(duration)
+        ));
+    }
+}
+
+fn format_duration(seconds: i32) -> String {
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
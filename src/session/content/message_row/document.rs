@@ -0,0 +1,380 @@
+use gettextrs::gettext;
+use glib::{clone, closure};
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+use gtk::{gdk, gio, glib, CompositeTemplate};
+use tdgrand::enums::MessageContent;
+use tdgrand::types::File;
+
+use crate::session::chat::{BoxedMessageContent, Message};
+use crate::session::content::message_row::{bind_forward_label, MessageReply};
+use crate::session::content::{MessageRow, MessageRowExt};
+use crate::utils::parse_formatted_text;
+use crate::{Session, Window};
+
+mod imp {
+    use super::*;
+    use glib::WeakRef;
+    use std::cell::{Cell, RefCell};
+
+    #[derive(Debug, Default, CompositeTemplate)]
+    #[template(resource = "/com/github/melix99/telegrand/ui/content-message-document.ui")]
+    pub struct MessageDocument {
+        pub bindings: RefCell<Vec<gtk::ExpressionWatch>>,
+        pub handler_id: RefCell<Option<glib::SignalHandlerId>>,
+        pub old_message: WeakRef<glib::Object>,
+        /// The id of the file currently shown, used to ignore the updates of the files of
+        /// the previous messages
+        pub file_id: Cell<i32>,
+        /// The id of the download of the file started by this widget, if any, used to cancel it
+        pub download_id: Cell<Option<u32>>,
+        /// The local path of the file, if it has been downloaded
+        pub local_path: RefCell<Option<String>>,
+        pub file_chooser: RefCell<Option<gtk::FileChooserNative>>,
+        #[template_child]
+        pub forward_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub reply: TemplateChild<MessageReply>,
+        #[template_child]
+        pub icon: TemplateChild<gtk::Image>,
+        #[template_child]
+        pub name_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub size_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub progress_bar: TemplateChild<gtk::ProgressBar>,
+        #[template_child]
+        pub action_stack: TemplateChild<gtk::Stack>,
+        #[template_child]
+        pub download_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub cancel_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub open_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub save_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub caption_label: TemplateChild<gtk::Label>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for MessageDocument {
+        const NAME: &'static str = "ContentMessageDocument";
+        type Type = super::MessageDocument;
+        type ParentType = MessageRow;
+
+        fn class_init(klass: &mut Self::Class) {
+            Self::bind_template(klass);
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for MessageDocument {
+        fn constructed(&self, obj: &Self::Type) {
+            self.parent_constructed(obj);
+            obj.connect_message_notify(|obj, _| obj.update_widget());
+
+            self.caption_label.connect_activate_link(
+                clone!(@weak obj => @default-return gtk::Inhibit(false), move |label, uri| {
+                    obj.upcast_ref::<MessageRow>().activate_link(label, uri)
+                }),
+            );
+
+            self.download_button
+                .connect_clicked(clone!(@weak obj => move |_| obj.download_document()));
+            self.cancel_button
+                .connect_clicked(clone!(@weak obj => move |_| obj.cancel_download()));
+            self.open_button
+                .connect_clicked(clone!(@weak obj => move |_| obj.open_document()));
+            self.save_button
+                .connect_clicked(clone!(@weak obj => move |_| obj.save_document()));
+        }
+    }
+
+    impl WidgetImpl for MessageDocument {}
+}
+
+glib::wrapper! {
+    pub struct MessageDocument(ObjectSubclass<imp::MessageDocument>)
+        @extends gtk::Widget, MessageRow;
+}
+
+impl MessageDocument {
+    fn update_widget(&self) {
+        let imp = self.imp();
+        let mut bindings = imp.bindings.borrow_mut();
+
+        while let Some(binding) = bindings.pop() {
+            binding.unwatch();
+        }
+
+        if let Some(old_message) = imp.old_message.upgrade() {
+            if let Some(id) = imp.handler_id.take() {
+                old_message.disconnect(id);
+            }
+        }
+
+        if let Some(message) = self.message() {
+            let message = message.downcast_ref::<Message>().unwrap();
+
+            // Setup caption expression
+            let caption_binding = Message::this_expression("content")
+                .chain_closure::<String>(closure!(|_: Message, content: BoxedMessageContent| {
+                    if let MessageContent::MessageDocument(data) = content.0 {
+                        parse_formatted_text(data.caption)
+                    } else {
+                        unreachable!();
+                    }
+                }))
+                .bind(&*imp.caption_label, "label", Some(message));
+            bindings.push(caption_binding);
+
+            let caption_visible_binding = imp
+                .caption_label
+                .property_expression("label")
+                .chain_closure::<bool>(closure!(|_: Option<glib::Object>, label: String| {
+                    !label.is_empty()
+                }))
+                .bind(&*imp.caption_label, "visible", glib::Object::NONE);
+            bindings.push(caption_visible_binding);
+
+            if let Some(forward_binding) = bind_forward_label(&imp.forward_label, message) {
+                bindings.push(forward_binding);
+            }
+
+            imp.reply.update_from_message(message);
+
+            // Load document
+            let handler_id =
+                message.connect_content_notify(clone!(@weak self as obj => move |message, _| {
+                    obj.update_document(message);
+                }));
+            imp.handler_id.replace(Some(handler_id));
+            self.update_document(message);
+        }
+
+        imp.old_message.set(self.message().as_ref());
+    }
+
+    fn update_document(&self, message: &Message) {
+        if let MessageContent::MessageDocument(data) = message.content().0 {
+            let imp = self.imp();
+            let document = data.document;
+            let file = document.document;
+
+            let content_type = gio::content_type_from_mime_type(&document.mime_type);
+            let icon = gio::content_type_get_icon(
+                content_type
+                    .as_deref()
+                    .unwrap_or("application/octet-stream"),
+            );
+            imp.icon.set_from_gicon(&icon);
+            imp.name_label.set_label(&document.file_name);
+
+            imp.file_id.set(file.id);
+            imp.download_id.set(None);
+            self.update_file_state(&file);
+
+            // Keep following the download if it was started before the widget was bound to
+            // this message
+            if file.local.is_downloading_active {
+                self.download_document();
+            }
+
+            // Show the upload progress of the documents being sent
+            if file.remote.is_uploading_active {
+                self.watch_document_upload(&file, &message.chat().session());
+            }
+        }
+    }
+
+    fn update_file_state(&self, file: &File) {
+        let imp = self.imp();
+        let size = file.size.max(file.expected_size) as u64;
+
+        if file.local.is_downloading_completed {
+            imp.local_path.replace(Some(file.local.path.clone()));
+            imp.action_stack.set_visible_child_name("downloaded");
+            imp.progress_bar.set_visible(false);
+            imp.size_label.set_label(&glib::format_size(size));
+        } else if file.local.is_downloading_active {
+            imp.local_path.replace(None);
+            imp.action_stack.set_visible_child_name("downloading");
+            imp.progress_bar.set_visible(true);
+            imp.progress_bar.set_fraction(if size > 0 {
+                file.local.downloaded_size as f64 / size as f64
+            } else {
+                0.0
+            });
+            imp.size_label.set_label(&format!(
+                "{} / {}",
+                glib::format_size(file.local.downloaded_size as u64),
+                glib::format_size(size)
+            ));
+        } else {
+            imp.local_path.replace(None);
+            imp.action_stack.set_visible_child_name("download");
+            imp.progress_bar.set_visible(false);
+            imp.size_label.set_label(&glib::format_size(size));
+        }
+    }
+
+    fn download_document(&self) {
+        let message = match self.message().and_then(|m| m.downcast::<Message>().ok()) {
+            Some(message) => message,
+            None => return,
+        };
+
+        if let MessageContent::MessageDocument(data) = message.content().0 {
+            let (sender, receiver) = glib::MainContext::sync_channel::<File>(Default::default(), 5);
+
+            receiver.attach(
+                None,
+                clone!(@weak self as obj => @default-return glib::Continue(false), move |file| {
+                    // Check that the widget hasn't been recycled for another message
+                    if obj.imp().file_id.get() != file.id {
+                        return glib::Continue(false);
+                    }
+
+                    obj.update_file_state(&file);
+                    glib::Continue(!file.local.is_downloading_completed)
+                }),
+            );
+
+            let imp = self.imp();
+            imp.action_stack.set_visible_child_name("downloading");
+
+            let download_id = message
+                .chat()
+                .session()
+                .download_file(data.document.document.id, sender);
+            imp.download_id.set(Some(download_id));
+        }
+    }
+
+    fn cancel_download(&self) {
+        let message = match self.message().and_then(|m| m.downcast::<Message>().ok()) {
+            Some(message) => message,
+            None => return,
+        };
+
+        let imp = self.imp();
+        if let Some(download_id) = imp.download_id.take() {
+            message
+                .chat()
+                .session()
+                .cancel_download_file(imp.file_id.get(), download_id);
+        }
+
+        imp.action_stack.set_visible_child_name("download");
+        imp.progress_bar.set_visible(false);
+    }
+
+    fn watch_document_upload(&self, file: &File, session: &Session) {
+        let (sender, receiver) = glib::MainContext::sync_channel::<File>(Default::default(), 5);
+
+        receiver.attach(
+            None,
+            clone!(@weak self as obj => @default-return glib::Continue(false), move |file| {
+                // Check that the widget hasn't been recycled for another message
+                if obj.imp().file_id.get() != file.id {
+                    return glib::Continue(false);
+                }
+
+                obj.update_upload_progress(&file);
+                glib::Continue(!file.remote.is_uploading_completed)
+            }),
+        );
+
+        self.update_upload_progress(file);
+        session.watch_file_upload(file.id, sender);
+    }
+
+    fn update_upload_progress(&self, file: &File) {
+        let imp = self.imp();
+
+        if file.remote.is_uploading_completed || file.expected_size == 0 {
+            imp.progress_bar.set_visible(false);
+            imp.size_label
+                .set_label(&glib::format_size(file.expected_size as u64));
+        } else {
+            imp.progress_bar.set_visible(true);
+            imp.progress_bar
+                .set_fraction(file.remote.uploaded_size as f64 / file.expected_size as f64);
+            imp.size_label.set_label(&format!(
+                "{} / {}",
+                glib::format_size(file.remote.uploaded_size as u64),
+                glib::format_size(file.expected_size as u64)
+            ));
+        }
+    }
+
+    fn parent_window(&self) -> Option<gtk::Window> {
+        self.root()?.downcast().ok()
+    }
+
+    fn show_toast(&self, title: &str) {
+        if let Some(window) = self.root().and_then(|root| root.downcast::<Window>().ok()) {
+            window.add_toast(&adw::Toast::new(title));
+        }
+    }
+
+    fn open_document(&self) {
+        if let Some(path) = self.imp().local_path.borrow().as_ref() {
+            gtk::show_uri(
+                self.parent_window().as_ref(),
+                &gio::File::for_path(path).uri(),
+                gdk::CURRENT_TIME,
+            );
+        }
+    }
+
+    fn save_document(&self) {
+        let imp = self.imp();
+        let path = match imp.local_path.borrow().clone() {
+            Some(path) => path,
+            None => return,
+        };
+
+        let file_chooser = gtk::FileChooserNative::new(
+            Some(&gettext("Save File")),
+            self.parent_window().as_ref(),
+            gtk::FileChooserAction::Save,
+            Some(&gettext("_Save")),
+            Some(&gettext("_Cancel")),
+        );
+        file_chooser.set_modal(true);
+        file_chooser.set_current_name(&imp.name_label.label());
+
+        file_chooser.connect_response(clone!(@weak self as obj => move |file_chooser, response| {
+            if response == gtk::ResponseType::Accept {
+                if let Some(destination) = file_chooser.file() {
+                    // Copy the file out of the TDLib cache
+                    gio::File::for_path(&path).copy_async(
+                        &destination,
+                        gio::FileCopyFlags::OVERWRITE,
+                        glib::PRIORITY_DEFAULT,
+                        gio::Cancellable::NONE,
+                        None,
+                        clone!(@weak obj => move |result| {
+                            if let Err(e) = result {
+                                log::warn!("Failed to save the document: {:?}", e);
+                                obj.show_toast(&gettext("Failed to save the file"));
+                            }
+                        }),
+                    );
+                }
+            }
+
+            obj.imp().file_chooser.replace(None);
+        }));
+
+        file_chooser.show();
+
+        // Keep the file chooser alive until the user responds
+        imp.file_chooser.replace(Some(file_chooser));
+    }
+}
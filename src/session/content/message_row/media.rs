@@ -2,7 +2,7 @@ use gtk::prelude::*;
 use gtk::subclass::prelude::*;
 use gtk::{gdk, glib, CompositeTemplate};
 
-use crate::session::content::message_row::MediaPicture;
+use crate::session::content::message_row::{MediaPicture, MessageReply};
 
 mod imp {
     use super::*;
@@ -14,11 +14,19 @@ mod imp {
         #[template_child]
         pub content: TemplateChild<gtk::Box>,
         #[template_child]
+        pub forward_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub reply: TemplateChild<MessageReply>,
+        #[template_child]
         pub picture: TemplateChild<MediaPicture>,
         #[template_child]
         pub caption_label: TemplateChild<gtk::Label>,
         #[template_child]
         pub progress_bar: TemplateChild<gtk::ProgressBar>,
+        #[template_child]
+        pub play_icon: TemplateChild<gtk::Image>,
+        #[template_child]
+        pub error_icon: TemplateChild<gtk::Image>,
     }
 
     #[glib::object_subclass]
@@ -146,6 +154,31 @@ impl Media {
         glib::Object::new(&[]).expect("Failed to create Media")
     }
 
+    pub fn forward_label(&self) -> &gtk::Label {
+        &*self.imp().forward_label
+    }
+
+    pub fn caption_label(&self) -> &gtk::Label {
+        &*self.imp().caption_label
+    }
+
+    pub fn reply(&self) -> &MessageReply {
+        &*self.imp().reply
+    }
+
+    pub fn picture(&self) -> &MediaPicture {
+        &*self.imp().picture
+    }
+
+    pub fn set_play_icon_visible(&self, visible: bool) {
+        self.imp().play_icon.set_visible(visible);
+    }
+
+    /// Shows an icon telling that the media couldn't be loaded.
+    pub fn set_error_icon_visible(&self, visible: bool) {
+        self.imp().error_icon.set_visible(visible);
+    }
+
     pub fn set_aspect_ratio(&self, aspect_ratio: f64) {
         self.imp().picture.set_aspect_ratio(aspect_ratio);
     }
@@ -154,6 +187,10 @@ impl Media {
         self.imp().picture.set_paintable(paintable);
     }
 
+    pub fn set_blurred(&self, is_blurred: bool) {
+        self.imp().picture.set_blurred(is_blurred);
+    }
+
     pub fn caption(&self) -> String {
         self.imp().caption_label.label().into()
     }
@@ -1,3 +1,4 @@
+use glib::clone;
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
 use gtk::{gdk, glib, graphene};
@@ -5,6 +6,7 @@ use gtk::{gdk, glib, graphene};
 const MIN_WIDTH: i32 = 100;
 const MIN_HEIGHT: i32 = 100;
 const MAX_HEIGHT: i32 = 400;
+const BLUR_RADIUS: f64 = 10.0;
 
 mod imp {
     use super::*;
@@ -14,7 +16,9 @@ mod imp {
     #[derive(Debug, Default)]
     pub struct MediaPicture {
         pub paintable: RefCell<Option<gdk::Paintable>>,
+        pub invalidate_handler_id: RefCell<Option<glib::SignalHandlerId>>,
         pub aspect_ratio: Cell<f64>,
+        pub is_blurred: Cell<bool>,
     }
 
     #[glib::object_subclass]
@@ -44,6 +48,13 @@ mod imp {
                         0.0,
                         glib::ParamFlags::READWRITE | glib::ParamFlags::EXPLICIT_NOTIFY,
                     ),
+                    glib::ParamSpecBoolean::new(
+                        "is-blurred",
+                        "Is Blurred",
+                        "Whether the media is shown blurred",
+                        false,
+                        glib::ParamFlags::READWRITE | glib::ParamFlags::EXPLICIT_NOTIFY,
+                    ),
                 ]
             });
             PROPERTIES.as_ref()
@@ -59,6 +70,7 @@ mod imp {
             match pspec.name() {
                 "paintable" => obj.set_paintable(value.get().unwrap()),
                 "aspect-ratio" => obj.set_aspect_ratio(value.get().unwrap()),
+                "is-blurred" => obj.set_blurred(value.get().unwrap()),
                 _ => unimplemented!(),
             }
         }
@@ -67,6 +79,7 @@ mod imp {
             match pspec.name() {
                 "paintable" => obj.paintable().to_value(),
                 "aspect-ratio" => obj.aspect_ratio().to_value(),
+                "is-blurred" => obj.is_blurred().to_value(),
                 _ => unimplemented!(),
             }
         }
@@ -130,8 +143,25 @@ mod imp {
                     )
                 };
 
+                // Keep the blur inside the bounds of the widget
+                let is_blurred = self.is_blurred.get();
+                if is_blurred {
+                    snapshot.push_clip(&graphene::Rect::new(
+                        0.0,
+                        0.0,
+                        widget_width as f32,
+                        widget_height as f32,
+                    ));
+                    snapshot.push_blur(BLUR_RADIUS);
+                }
+
                 snapshot.translate(&graphene::Point::new(x as f32, y as f32));
                 paintable.snapshot(snapshot.upcast_ref(), width, height);
+
+                if is_blurred {
+                    snapshot.pop();
+                    snapshot.pop();
+                }
             }
         }
     }
@@ -162,7 +192,23 @@ impl MediaPicture {
             return;
         }
 
-        self.imp().paintable.replace(paintable);
+        let imp = self.imp();
+        if let Some(old_paintable) = imp.paintable.borrow().as_ref() {
+            if let Some(handler_id) = imp.invalidate_handler_id.take() {
+                old_paintable.disconnect(handler_id);
+            }
+        }
+
+        // Redraw the animated paintables, like videos, on each new frame
+        if let Some(ref paintable) = paintable {
+            let handler_id =
+                paintable.connect_invalidate_contents(clone!(@weak self as obj => move |_| {
+                    obj.queue_draw();
+                }));
+            imp.invalidate_handler_id.replace(Some(handler_id));
+        }
+
+        imp.paintable.replace(paintable);
         self.queue_draw();
 
         self.notify("paintable");
@@ -182,4 +228,19 @@ impl MediaPicture {
 
         self.notify("aspect-ratio");
     }
+
+    pub fn is_blurred(&self) -> bool {
+        self.imp().is_blurred.get()
+    }
+
+    pub fn set_blurred(&self, is_blurred: bool) {
+        if self.is_blurred() == is_blurred {
+            return;
+        }
+
+        self.imp().is_blurred.set(is_blurred);
+        self.queue_draw();
+
+        self.notify("is-blurred");
+    }
 }
@@ -1,24 +1,41 @@
+mod audio;
+mod document;
 mod media;
 mod media_picture;
 mod photo;
+mod reply;
 mod sticker;
 mod sticker_paintable;
 mod text;
+mod video;
+mod waveform;
 
+pub use self::audio::MessageAudio;
+pub use self::document::MessageDocument;
 use self::media::Media;
 use self::media_picture::MediaPicture;
 pub use self::photo::MessagePhoto;
+pub use self::reply::MessageReply;
 pub use self::sticker::MessageSticker;
 use self::sticker_paintable::StickerPaintable;
 pub use self::text::MessageText;
+pub use self::video::MessageVideo;
+use self::waveform::Waveform;
 
+use gettextrs::gettext;
+use glib::{clone, closure};
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
-use gtk::{gdk, glib};
+use gtk::{gdk, gio, glib};
+use tdgrand::enums::MessageContent;
+use tdgrand::types::FormattedText;
 
 use crate::session::chat::{Message, MessageSender, SponsoredMessage};
 use crate::session::components::Avatar;
 use crate::session::ChatType;
+use crate::utils::{
+    is_telegram_link, parse_formatted_text_with_revealed_spoilers, parse_internal_link,
+};
 
 const AVATAR_SIZE: i32 = 32;
 const SPACING: i32 = 6;
@@ -26,6 +43,7 @@ const SPACING: i32 = 6;
 mod imp {
     use super::*;
     use once_cell::sync::Lazy;
+    use once_cell::unsync::OnceCell;
     use std::cell::{Cell, RefCell};
 
     #[derive(Debug, Default)]
@@ -34,7 +52,11 @@ mod imp {
         pub message: RefCell<Option<glib::Object>>,
         pub content: RefCell<Option<gtk::Widget>>,
         pub avatar: RefCell<Option<Avatar>>,
+        /// The icon showing whether an outgoing message has been sent or read
+        pub status_icon: RefCell<Option<gtk::Image>>,
+        pub status_handler_id: RefCell<Option<glib::SignalHandlerId>>,
         pub is_outgoing: Cell<bool>,
+        pub menu: OnceCell<gtk::PopoverMenu>,
     }
 
     #[glib::object_subclass]
@@ -42,6 +64,24 @@ mod imp {
         const NAME: &'static str = "ContentMessageRow";
         type Type = super::MessageRow;
         type ParentType = gtk::Widget;
+
+        fn class_init(klass: &mut Self::Class) {
+            klass.install_action("message-row.reply", None, move |widget, _, _| {
+                widget.reply();
+            });
+            klass.install_action("message-row.forward", None, move |widget, _, _| {
+                widget.forward();
+            });
+            klass.install_action("message-row.edit", None, move |widget, _, _| {
+                widget.edit();
+            });
+            klass.install_action("message-row.delete", None, move |widget, _, _| {
+                widget.delete();
+            });
+            klass.install_action("message-row.view-media", None, move |widget, _, _| {
+                widget.view_media();
+            });
+        }
     }
 
     impl ObjectImpl for MessageRow {
@@ -89,11 +129,58 @@ mod imp {
             }
         }
 
+        fn constructed(&self, obj: &Self::Type) {
+            self.parent_constructed(obj);
+
+            let menu_model = gio::Menu::new();
+            menu_model.append(Some(&gettext("_Reply")), Some("message-row.reply"));
+            for (label, action) in [
+                (gettext("_Forward"), "message-row.forward"),
+                (gettext("_Edit"), "message-row.edit"),
+                (gettext("_Delete"), "message-row.delete"),
+                (gettext("_View"), "message-row.view-media"),
+            ] {
+                let item = gio::MenuItem::new(Some(&label), Some(action));
+                item.set_attribute_value("hidden-when", Some(&"action-disabled".to_variant()));
+                menu_model.append_item(&item);
+            }
+
+            let menu = gtk::PopoverMenu::from_model(Some(&menu_model));
+            menu.set_parent(obj);
+            menu.set_has_arrow(false);
+            self.menu.set(menu).unwrap();
+
+            // Show the context menu on right click and on long press
+            let click_gesture = gtk::GestureClick::new();
+            click_gesture.set_button(gdk::BUTTON_SECONDARY);
+            click_gesture.connect_pressed(clone!(@weak obj => move |gesture, _, x, y| {
+                gesture.set_state(gtk::EventSequenceState::Claimed);
+                obj.popup_menu(x, y);
+            }));
+            obj.add_controller(&click_gesture);
+
+            let long_press_gesture = gtk::GestureLongPress::new();
+            long_press_gesture.set_touch_only(true);
+            long_press_gesture.connect_pressed(clone!(@weak obj => move |gesture, x, y| {
+                gesture.set_state(gtk::EventSequenceState::Claimed);
+                obj.popup_menu(x, y);
+            }));
+            obj.add_controller(&long_press_gesture);
+        }
+
         fn dispose(&self, _obj: &Self::Type) {
+            if let Some(menu) = self.menu.get() {
+                menu.unparent();
+            }
+
             if let Some(avatar) = self.avatar.borrow().as_ref() {
                 avatar.unparent();
             }
 
+            if let Some(status_icon) = self.status_icon.borrow().as_ref() {
+                status_icon.unparent();
+            }
+
             if let Some(content) = self.content.borrow().as_ref() {
                 content.unparent();
             }
@@ -187,6 +274,22 @@ mod imp {
 
                 let allocation = gdk::Rectangle::new(x, 0, actual_width, height);
                 content.size_allocate(&allocation, baseline);
+
+                // Place the status icon in the start margin of the outgoing messages
+                if let Some(status_icon) = self.status_icon.borrow().as_ref() {
+                    let (_, natural_size) = status_icon.preferred_size();
+                    let allocation = gdk::Rectangle::new(
+                        x + content.margin_start() - SPACING - natural_size.width(),
+                        height - natural_size.height(),
+                        natural_size.width(),
+                        natural_size.height(),
+                    );
+                    status_icon.size_allocate(&allocation, -1);
+                }
+            }
+
+            if let Some(menu) = self.menu.get() {
+                menu.present();
             }
         }
 
@@ -201,6 +304,234 @@ glib::wrapper! {
         @extends gtk::Widget;
 }
 
+impl MessageRow {
+    fn popup_menu(&self, x: f64, y: f64) {
+        // Sponsored messages don't support any action
+        if self
+            .message()
+            .and_then(|m| m.downcast::<Message>().ok())
+            .is_none()
+        {
+            return;
+        }
+
+        let menu = self.imp().menu.get().unwrap();
+        menu.set_pointing_to(Some(&gdk::Rectangle::new(x as i32, y as i32, 0, 0)));
+        menu.popup();
+    }
+
+    fn reply(&self) {
+        if let Some(message) = self.message().and_then(|m| m.downcast::<Message>().ok()) {
+            self.activate_action("chat-history.reply", Some(&message.id().to_variant()))
+                .unwrap();
+        }
+    }
+
+    fn forward(&self) {
+        if let Some(message) = self.message().and_then(|m| m.downcast::<Message>().ok()) {
+            self.activate_action("chat-history.forward", Some(&message.id().to_variant()))
+                .unwrap();
+        }
+    }
+
+    fn edit(&self) {
+        if let Some(message) = self.message().and_then(|m| m.downcast::<Message>().ok()) {
+            self.activate_action("chat-history.edit", Some(&message.id().to_variant()))
+                .unwrap();
+        }
+    }
+
+    fn delete(&self) {
+        if let Some(message) = self.message().and_then(|m| m.downcast::<Message>().ok()) {
+            self.activate_action("chat-history.delete", Some(&message.id().to_variant()))
+                .unwrap();
+        }
+    }
+
+    fn view_media(&self) {
+        if let Some(message) = self.message().and_then(|m| m.downcast::<Message>().ok()) {
+            self.activate_action("chat-history.view-media", Some(&message.id().to_variant()))
+                .unwrap();
+        }
+    }
+
+    /// Handles the activation of a link in the text of the message. Spoilers are revealed in
+    /// place, telegram links and the other internal links are handled by the chat history and
+    /// the remaining ones are opened by the label itself.
+    fn activate_link(&self, label: &gtk::Label, uri: &str) -> gtk::Inhibit {
+        if is_telegram_link(uri) {
+            self.activate_action("chat-history.open-telegram-link", Some(&uri.to_variant()))
+                .unwrap();
+            return gtk::Inhibit(true);
+        }
+
+        let action = match parse_internal_link(uri) {
+            Some((action, _)) => action,
+            None => return gtk::Inhibit(false),
+        };
+
+        if action == "spoiler" {
+            let formatted_text = self
+                .message()
+                .and_then(|m| m.downcast::<Message>().ok())
+                .and_then(|message| message_formatted_text(message.content().0));
+
+            if let Some(formatted_text) = formatted_text {
+                label.set_label(&parse_formatted_text_with_revealed_spoilers(formatted_text));
+            }
+        } else {
+            self.activate_action("chat-history.activate-link", Some(&uri.to_variant()))
+                .unwrap();
+        }
+
+        gtk::Inhibit(true)
+    }
+
+    fn update_status_icon(&self, message: Option<&Message>) {
+        let imp = self.imp();
+
+        if let Some(handler_id) = imp.status_handler_id.take() {
+            if let Some(old_message) = imp
+                .message
+                .borrow()
+                .as_ref()
+                .and_then(|m| m.downcast_ref::<Message>())
+            {
+                old_message.chat().disconnect(handler_id);
+            }
+        }
+
+        // The read state isn't available for the posts of the channels
+        let message = message.filter(|message| {
+            let is_channel = matches!(
+                message.chat().type_(),
+                ChatType::Supergroup(supergroup) if supergroup.is_channel()
+            );
+            message.is_outgoing() && !is_channel
+        });
+
+        match message {
+            Some(message) => {
+                if imp.status_icon.borrow().is_none() {
+                    let status_icon = gtk::Image::new();
+                    status_icon.set_parent(self);
+                    imp.status_icon.replace(Some(status_icon));
+                }
+
+                let handler_id = message.chat().connect_last_read_outbox_message_id_notify(
+                    clone!(@weak self as obj, @weak message => move |_, _| {
+                        obj.update_status_icon_state(&message);
+                    }),
+                );
+                imp.status_handler_id.replace(Some(handler_id));
+
+                self.update_status_icon_state(message);
+            }
+            None => {
+                if let Some(status_icon) = imp.status_icon.take() {
+                    status_icon.unparent();
+                }
+            }
+        }
+    }
+
+    fn update_status_icon_state(&self, message: &Message) {
+        let (icon_name, tooltip_text) = if message.is_pending() {
+            ("document-open-recent-symbolic", gettext("Sending"))
+        } else if message.is_failed() {
+            ("dialog-error-symbolic", gettext("Failed to Send"))
+        } else if message.id() <= message.chat().last_read_outbox_message_id() {
+            ("message-read-symbolic", gettext("Read"))
+        } else {
+            ("message-sent-symbolic", gettext("Sent"))
+        };
+
+        if let Some(status_icon) = self.imp().status_icon.borrow().as_ref() {
+            status_icon.set_icon_name(Some(icon_name));
+            status_icon.set_tooltip_text(Some(&tooltip_text));
+
+            if message.is_failed() {
+                status_icon.remove_css_class("dim-label");
+                status_icon.add_css_class("error");
+            } else {
+                status_icon.remove_css_class("error");
+                status_icon.add_css_class("dim-label");
+            }
+        }
+    }
+
+    fn update_actions(&self, message: Option<&Message>) {
+        // Only text messages can be edited for now
+        let can_be_edited = message
+            .map(|message| {
+                message.can_be_edited()
+                    && matches!(message.content().0, MessageContent::MessageText(_))
+            })
+            .unwrap_or_default();
+        let can_be_deleted = message
+            .map(|message| {
+                message.can_be_deleted_only_for_self() || message.can_be_deleted_for_all_users()
+            })
+            .unwrap_or_default();
+
+        self.action_set_enabled(
+            "message-row.forward",
+            message
+                .map(|message| message.can_be_forwarded())
+                .unwrap_or_default(),
+        );
+        self.action_set_enabled("message-row.edit", can_be_edited);
+        self.action_set_enabled("message-row.delete", can_be_deleted);
+        self.action_set_enabled(
+            "message-row.view-media",
+            message
+                .map(|message| {
+                    matches!(
+                        message.content().0,
+                        MessageContent::MessagePhoto(_) | MessageContent::MessageVideo(_)
+                    )
+                })
+                .unwrap_or_default(),
+        );
+    }
+}
+
+/// Binds the "Forwarded from …" header of the message to the label, hiding the label if the
+/// message isn't forwarded.
+fn bind_forward_label(label: &gtk::Label, message: &Message) -> Option<gtk::ExpressionWatch> {
+    match message.forward_origin() {
+        Some(forward_origin) => {
+            let binding = forward_origin
+                .name_expression()
+                .chain_closure::<String>(closure!(|_: Option<glib::Object>, name: String| {
+                    gettext!("Forwarded from {}", name)
+                }))
+                .bind(label, "label", glib::Object::NONE);
+
+            label.set_visible(true);
+            Some(binding)
+        }
+        None => {
+            label.set_visible(false);
+            None
+        }
+    }
+}
+
+/// Returns the text of a text message or the caption of a media message.
+fn message_formatted_text(content: MessageContent) -> Option<FormattedText> {
+    match content {
+        MessageContent::MessageText(data) => Some(data.text),
+        MessageContent::MessagePhoto(data) => Some(data.caption),
+        MessageContent::MessageVideo(data) => Some(data.caption),
+        MessageContent::MessageAnimation(data) => Some(data.caption),
+        MessageContent::MessageAudio(data) => Some(data.caption),
+        MessageContent::MessageDocument(data) => Some(data.caption),
+        MessageContent::MessageVoiceNote(data) => Some(data.caption),
+        _ => None,
+    }
+}
+
 pub trait MessageRowExt: IsA<MessageRow> {
     fn new(message: &glib::Object) -> Self;
 
@@ -214,6 +545,12 @@ pub trait MessageRowExt: IsA<MessageRow> {
         }
 
         let imp = self.upcast_ref().imp();
+
+        self.upcast_ref()
+            .update_actions(message.as_ref().and_then(|m| m.downcast_ref::<Message>()));
+        self.upcast_ref()
+            .update_status_icon(message.as_ref().and_then(|m| m.downcast_ref::<Message>()));
+
         if let Some(ref message) = message {
             if let Some(message) = message.downcast_ref::<Message>() {
                 imp.is_outgoing.set(message.is_outgoing());
@@ -1,27 +1,33 @@
 use glib::{clone, closure};
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
-use gtk::{gdk, gio, glib, CompositeTemplate};
+use gtk::{gdk, gdk_pixbuf, glib, CompositeTemplate};
 use tdgrand::enums::MessageContent;
-use tdgrand::types::File;
+use tdgrand::types::{File, Minithumbnail, PhotoSize};
 
 use crate::session::chat::{BoxedMessageContent, Message};
-use crate::session::content::message_row::Media;
+use crate::session::content::message_row::{bind_forward_label, Media};
 use crate::session::content::{MessageRow, MessageRowExt};
 use crate::utils::parse_formatted_text;
-use crate::Session;
+use crate::{spawn, Session};
 
 mod imp {
     use super::*;
     use glib::WeakRef;
-    use std::cell::RefCell;
+    use std::cell::{Cell, RefCell};
 
     #[derive(Debug, Default, CompositeTemplate)]
     #[template(resource = "/com/github/melix99/telegrand/ui/content-message-photo.ui")]
     pub struct MessagePhoto {
         pub binding: RefCell<Option<gtk::ExpressionWatch>>,
+        pub forward_binding: RefCell<Option<gtk::ExpressionWatch>>,
         pub handler_id: RefCell<Option<glib::SignalHandlerId>>,
         pub old_message: WeakRef<glib::Object>,
+        /// The id of the photo file currently shown, used to ignore the updates of the
+        /// files of the previous messages
+        pub file_id: Cell<i32>,
+        /// The ids of the photo file being downloaded and of its download request, if any
+        pub download: Cell<Option<(i32, u32)>>,
         #[template_child]
         pub media: TemplateChild<Media>,
     }
@@ -45,6 +51,20 @@ mod imp {
         fn constructed(&self, obj: &Self::Type) {
             self.parent_constructed(obj);
             obj.connect_message_notify(|obj, _| obj.update_widget());
+
+            self.media.caption_label().connect_activate_link(
+                clone!(@weak obj => @default-return gtk::Inhibit(false), move |label, uri| {
+                    obj.upcast_ref::<MessageRow>().activate_link(label, uri)
+                }),
+            );
+
+            // Open the photo in the media viewer when it's clicked
+            let click_gesture = gtk::GestureClick::new();
+            click_gesture.connect_released(clone!(@weak obj => move |gesture, _, _, _| {
+                gesture.set_state(gtk::EventSequenceState::Claimed);
+                obj.activate_action("message-row.view-media", None).unwrap();
+            }));
+            self.media.picture().add_controller(&click_gesture);
         }
     }
 
@@ -64,6 +84,10 @@ impl MessagePhoto {
             binding.unwatch();
         }
 
+        if let Some(binding) = imp.forward_binding.take() {
+            binding.unwatch();
+        }
+
         if let Some(old_message) = imp.old_message.upgrade() {
             if let Some(id) = imp.handler_id.take() {
                 old_message.disconnect(id);
@@ -85,6 +109,10 @@ impl MessagePhoto {
                 .bind(&*imp.media, "caption", Some(message));
             imp.binding.replace(Some(caption_binding));
 
+            imp.forward_binding
+                .replace(bind_forward_label(imp.media.forward_label(), message));
+            imp.media.reply().update_from_message(message);
+
             // Load photo
             let handler_id =
                 message.connect_content_notify(clone!(@weak self as obj => move |message, _| {
@@ -99,52 +127,180 @@ impl MessagePhoto {
 
     fn update_photo(&self, message: &Message) {
         if let MessageContent::MessagePhoto(data) = message.content().0 {
-            if let Some(photo_size) = data.photo.sizes.last() {
-                let imp = self.imp();
+            let imp = self.imp();
+            let session = message.chat().session();
 
-                // Reset media widget
-                imp.media.set_paintable(None);
-                imp.media
-                    .set_aspect_ratio(photo_size.width as f64 / photo_size.height as f64);
+            // The largest size is the one with the most accurate aspect ratio
+            let largest_size = match data.photo.sizes.last() {
+                Some(size) => size,
+                None => return,
+            };
+            imp.media
+                .set_aspect_ratio(largest_size.width as f64 / largest_size.height as f64);
 
-                if photo_size.photo.local.is_downloading_completed {
-                    imp.media.set_download_progress(1.0);
-                    self.load_photo_from_path(&photo_size.photo.local.path);
-                } else {
-                    imp.media.set_download_progress(0.0);
-                    self.download_photo(photo_size.photo.id, &message.chat().session());
+            let photo_size = self.photo_size_for_widget(&data.photo.sizes);
+
+            // Don't reload the photo if only other parts of the content, like the caption,
+            // have been changed
+            if imp.file_id.get() == photo_size.photo.id {
+                return;
+            }
+
+            // Stop downloading the photo of the previous message
+            if let Some((file_id, download_id)) = imp.download.take() {
+                session.cancel_download_file(file_id, download_id);
+            }
+
+            imp.file_id.set(photo_size.photo.id);
+            imp.media.set_error_icon_visible(false);
+
+            // Show the blurred minithumbnail until the photo is loaded
+            let minithumbnail = data
+                .photo
+                .minithumbnail
+                .as_ref()
+                .and_then(minithumbnail_texture);
+            imp.media.set_blurred(minithumbnail.is_some());
+            imp.media
+                .set_paintable(minithumbnail.map(|texture| texture.upcast()));
+
+            if photo_size.photo.local.is_downloading_completed {
+                imp.media.set_download_progress(1.0);
+                self.load_photo(photo_size.photo.id, &photo_size.photo.local.path, &session);
+
+                // Show the upload progress of the photos being sent
+                if photo_size.photo.remote.is_uploading_active {
+                    self.watch_photo_upload(&photo_size.photo, &session);
                 }
+            } else {
+                imp.media.set_download_progress(0.0);
+                self.download_photo(photo_size.photo.id, &session);
             }
         }
     }
 
+    /// Returns the size in device pixels of the longest side of the photo, as it's shown.
+    fn target_size(&self) -> i32 {
+        let picture = self.imp().media.picture();
+        let width = if picture.width() > 0 {
+            picture.width()
+        } else {
+            let (_, natural_width, _, _) = picture.measure(gtk::Orientation::Horizontal, -1);
+            natural_width
+        };
+        let (_, height, _, _) = picture.measure(gtk::Orientation::Vertical, width);
+
+        width.max(height) * self.scale_factor()
+    }
+
+    /// Returns the smallest size of the photo that is at least as big as the photo is shown,
+    /// or the largest one if there isn't any. The full size is only downloaded when the photo
+    /// is opened in the media viewer.
+    fn photo_size_for_widget<'a>(&self, sizes: &'a [PhotoSize]) -> &'a PhotoSize {
+        let target_size = self.target_size();
+
+        sizes
+            .iter()
+            .find(|size| size.width.max(size.height) >= target_size)
+            .unwrap_or_else(|| sizes.last().unwrap())
+    }
+
     fn download_photo(&self, file_id: i32, session: &Session) {
         let (sender, receiver) = glib::MainContext::sync_channel::<File>(Default::default(), 5);
 
         receiver.attach(
             None,
             clone!(@weak self as obj => @default-return glib::Continue(false), move |file| {
+                let imp = obj.imp();
+
+                // Check that the widget hasn't been recycled for another message
+                if imp.file_id.get() != file.id {
+                    return glib::Continue(false);
+                }
+
                 if file.local.is_downloading_completed {
-                    obj.imp().media.set_download_progress(1.0);
-                    obj.load_photo_from_path(&file.local.path);
+                    imp.download.set(None);
+                    imp.media.set_download_progress(1.0);
+
+                    if let Some(message) =
+                        obj.message().and_then(|m| m.downcast::<Message>().ok())
+                    {
+                        obj.load_photo(file.id, &file.local.path, &message.chat().session());
+                    }
                 } else {
                     let progress = file.local.downloaded_size as f64 / file.expected_size as f64;
-                    obj.imp().media.set_download_progress(progress);
+                    imp.media.set_download_progress(progress);
                 }
 
-                glib::Continue(true)
+                glib::Continue(!file.local.is_downloading_completed)
+            }),
+        );
+
+        let download_id = session.download_file(file_id, sender);
+        self.imp().download.set(Some((file_id, download_id)));
+    }
+
+    fn watch_photo_upload(&self, file: &File, session: &Session) {
+        let (sender, receiver) = glib::MainContext::sync_channel::<File>(Default::default(), 5);
+
+        receiver.attach(
+            None,
+            clone!(@weak self as obj => @default-return glib::Continue(false), move |file| {
+                obj.update_upload_progress(&file);
+                glib::Continue(!file.remote.is_uploading_completed)
             }),
         );
 
-        session.download_file(file_id, sender);
+        self.update_upload_progress(file);
+        session.watch_file_upload(file.id, sender);
     }
 
-    fn load_photo_from_path(&self, path: &str) {
-        // TODO: Consider changing this to use an async api when
-        // https://github.com/gtk-rs/gtk4-rs/pull/777 is merged
-        let file = gio::File::for_path(path);
-        self.imp()
-            .media
-            .set_paintable(Some(gdk::Texture::from_file(&file).unwrap().upcast()));
+    fn update_upload_progress(&self, file: &File) {
+        // The progress bar of the media is used for both downloads and uploads
+        let progress = if file.remote.is_uploading_completed || file.expected_size == 0 {
+            1.0
+        } else {
+            file.remote.uploaded_size as f64 / file.expected_size as f64
+        };
+        self.imp().media.set_download_progress(progress);
     }
+
+    fn load_photo(&self, file_id: i32, path: &str, session: &Session) {
+        let size = self.target_size();
+        let path = path.to_owned();
+
+        spawn!(clone!(@weak self as obj, @weak session => async move {
+            let result = session.load_image(file_id, &path, Some(size)).await;
+
+            // Check that the widget hasn't been recycled for another message in the meantime
+            let imp = obj.imp();
+            if imp.file_id.get() != file_id {
+                return;
+            }
+
+            match result {
+                Ok(texture) => {
+                    imp.media.set_blurred(false);
+                    imp.media.set_paintable(Some(texture.upcast()));
+                }
+                Err(e) => {
+                    log::warn!("Failed to load a photo: {}", e);
+                    imp.media.set_error_icon_visible(true);
+                }
+            }
+        }));
+    }
+}
+
+/// Decodes the tiny, blurry preview of the photo that TDLib sends along with the message.
+fn minithumbnail_texture(minithumbnail: &Minithumbnail) -> Option<gdk::Texture> {
+    let loader = gdk_pixbuf::PixbufLoader::new();
+    loader
+        .write(&glib::base64_decode(&minithumbnail.data))
+        .and_then(|_| loader.close())
+        .ok()?;
+
+    loader
+        .pixbuf()
+        .map(|pixbuf| gdk::Texture::for_pixbuf(&pixbuf))
 }
@@ -0,0 +1,241 @@
+use gettextrs::gettext;
+use glib::clone;
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+use gtk::{glib, CompositeTemplate};
+use tdgrand::enums::MessageContent;
+
+use crate::session::chat::Message;
+use crate::spawn;
+
+/// The seconds to wait before trying to load the replied message again after an error
+const RETRY_INTERVAL: u32 = 5;
+
+mod imp {
+    use super::*;
+    use once_cell::sync::Lazy;
+    use std::cell::{Cell, RefCell};
+
+    #[derive(Debug, Default, CompositeTemplate)]
+    #[template(resource = "/com/github/melix99/telegrand/ui/content-message-reply.ui")]
+    pub struct MessageReply {
+        pub message: RefCell<Option<Message>>,
+        /// The id of the message whose reply is being loaded
+        pub loading_message_id: Cell<i64>,
+        pub binding: RefCell<Option<gtk::ExpressionWatch>>,
+        #[template_child]
+        pub sender_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub text_label: TemplateChild<gtk::Label>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for MessageReply {
+        const NAME: &'static str = "ContentMessageReply";
+        type Type = super::MessageReply;
+        type ParentType = gtk::Widget;
+
+        fn class_init(klass: &mut Self::Class) {
+            Self::bind_template(klass);
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for MessageReply {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                vec![glib::ParamSpecObject::new(
+                    "message",
+                    "Message",
+                    "The message being replied to",
+                    Message::static_type(),
+                    glib::ParamFlags::READWRITE | glib::ParamFlags::EXPLICIT_NOTIFY,
+                )]
+            });
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(
+            &self,
+            obj: &Self::Type,
+            _id: usize,
+            value: &glib::Value,
+            pspec: &glib::ParamSpec,
+        ) {
+            match pspec.name() {
+                "message" => obj.set_message(value.get().unwrap()),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn property(&self, obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            match pspec.name() {
+                "message" => obj.message().to_value(),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn constructed(&self, obj: &Self::Type) {
+            self.parent_constructed(obj);
+
+            let click_gesture = gtk::GestureClick::new();
+            click_gesture.connect_released(clone!(@weak obj => move |gesture, _, _, _| {
+                if let Some(message) = obj.message() {
+                    gesture.set_state(gtk::EventSequenceState::Claimed);
+
+                    // The replied message can be in another chat, so let the session jump to it
+                    // in the current chat or open its chat
+                    let chat = message.chat();
+                    chat.session().select_chat_message(chat, message.id());
+                }
+            }));
+            obj.add_controller(&click_gesture);
+        }
+
+        fn dispose(&self, _obj: &Self::Type) {
+            self.sender_label.unparent();
+            self.text_label.unparent();
+        }
+    }
+
+    impl WidgetImpl for MessageReply {}
+}
+
+glib::wrapper! {
+    pub struct MessageReply(ObjectSubclass<imp::MessageReply>)
+        @extends gtk::Widget;
+}
+
+impl Default for MessageReply {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MessageReply {
+    pub fn new() -> Self {
+        glib::Object::new(&[]).expect("Failed to create MessageReply")
+    }
+
+    /// Shows the message which the specified message is replying to, or hides this widget
+    /// if the message isn't a reply.
+    pub fn update_from_message(&self, message: &Message) {
+        let imp = self.imp();
+        let reply_to_message_id = message.reply_to_message_id();
+
+        imp.loading_message_id.set(message.id());
+
+        if reply_to_message_id == 0 {
+            self.set_visible(false);
+            self.set_message(None);
+            return;
+        }
+
+        self.set_visible(true);
+
+        match message.chat().history().message_by_id(reply_to_message_id) {
+            Some(reply_to_message) => self.set_message(Some(reply_to_message)),
+            None => {
+                self.set_message(None);
+                imp.text_label.set_label(&gettext("Loading…"));
+
+                spawn!(clone!(@weak self as obj, @weak message => async move {
+                    let result = message.reply_to_message().await;
+
+                    // Check that the widget hasn't been recycled for another message
+                    // in the meantime
+                    if obj.imp().loading_message_id.get() != message.id() {
+                        return;
+                    }
+
+                    match result {
+                        Ok(reply_to_message) => obj.set_message(reply_to_message),
+                        // The replied message has been deleted
+                        Err(e) if e.code == 404 => obj.set_message(None),
+                        Err(e) => {
+                            log::warn!("Failed to get the replied message: {:?}", e);
+
+                            // Keep showing that the message is loading and try again later
+                            glib::timeout_add_seconds_local_once(
+                                RETRY_INTERVAL,
+                                clone!(@weak obj, @weak message => move || {
+                                    if obj.imp().loading_message_id.get() == message.id() {
+                                        obj.update_from_message(&message);
+                                    }
+                                }),
+                            );
+                        }
+                    }
+                }));
+            }
+        }
+    }
+
+    pub fn message(&self) -> Option<Message> {
+        self.imp().message.borrow().to_owned()
+    }
+
+    pub fn set_message(&self, message: Option<Message>) {
+        let imp = self.imp();
+
+        if let Some(binding) = imp.binding.take() {
+            binding.unwatch();
+        }
+
+        match message {
+            Some(ref message) => {
+                let sender_binding = message.sender_name_expression().bind(
+                    &*imp.sender_label,
+                    "label",
+                    glib::Object::NONE,
+                );
+                imp.binding.replace(Some(sender_binding));
+
+                imp.sender_label.set_visible(true);
+                imp.text_label
+                    .set_label(&stringify_message_content(message.content().0));
+            }
+            None => {
+                imp.sender_label.set_visible(false);
+                imp.text_label.set_label(&gettext("Deleted message"));
+            }
+        }
+
+        imp.message.replace(message);
+        self.notify("message");
+    }
+}
+
+fn stringify_message_content(content: MessageContent) -> String {
+    let with_caption = |label: String, caption: String| {
+        if caption.is_empty() {
+            label
+        } else {
+            format!("{}, {}", label, caption)
+        }
+    };
+
+    match content {
+        MessageContent::MessageText(data) => data.text.text,
+        MessageContent::MessagePhoto(data) => with_caption(gettext("Photo"), data.caption.text),
+        MessageContent::MessageVideo(data) => with_caption(gettext("Video"), data.caption.text),
+        MessageContent::MessageAnimation(data) => with_caption(gettext("GIF"), data.caption.text),
+        MessageContent::MessageVoiceNote(data) => {
+            with_caption(gettext("Voice message"), data.caption.text)
+        }
+        MessageContent::MessageAudio(data) => with_caption(
+            format!("{} - {}", data.audio.performer, data.audio.title),
+            data.caption.text,
+        ),
+        MessageContent::MessageDocument(data) => {
+            with_caption(data.document.file_name, data.caption.text)
+        }
+        MessageContent::MessageSticker(data) => {
+            format!("{} {}", data.sticker.emoji, gettext("Sticker"))
+        }
+        _ => gettext("Unsupported message"),
+    }
+}
@@ -1,21 +1,32 @@
 use glib::clone;
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
-use gtk::{gdk, gio, glib, CompositeTemplate};
+use gtk::{gio, glib, CompositeTemplate};
+use std::cell::Cell;
+use std::io::Read;
 use tdgrand::enums::MessageContent;
 use tdgrand::types::File;
 
 use crate::session::chat::Message;
 use crate::session::content::message_row::StickerPaintable;
 use crate::session::content::{MessageRow, MessageRowExt};
+use crate::utils::decode_webp;
+
+/// The magic number at the start of the WebM files, used by the video stickers
+const WEBM_MAGIC_NUMBER: &[u8] = &[0x1a, 0x45, 0xdf, 0xa3];
 
 mod imp {
     use super::*;
+    use std::cell::RefCell;
 
     #[derive(Debug, Default, CompositeTemplate)]
     #[template(resource = "/com/github/melix99/telegrand/ui/content-message-sticker.ui")]
     pub struct MessageSticker {
         pub paintable: StickerPaintable,
+        /// The id of the sticker file currently shown, used to ignore the files of the
+        /// previous messages
+        pub file_id: Cell<i32>,
+        pub tick_callback_id: RefCell<Option<gtk::TickCallbackId>>,
         #[template_child]
         pub picture: TemplateChild<gtk::Picture>,
     }
@@ -40,6 +51,19 @@ mod imp {
             self.parent_constructed(obj);
             self.picture.set_paintable(Some(&self.paintable));
             obj.connect_message_notify(|obj, _| obj.update_widget());
+
+            // Open the sticker set of the sticker when it's clicked
+            let click_gesture = gtk::GestureClick::new();
+            click_gesture.connect_released(clone!(@weak obj => move |gesture, _, _, _| {
+                gesture.set_state(gtk::EventSequenceState::Claimed);
+                obj.open_sticker_set();
+            }));
+            self.picture.add_controller(&click_gesture);
+
+            // Don't play the animated stickers while they aren't shown
+            self.picture.connect_unmap(
+                clone!(@weak obj => move |_| obj.imp().paintable.set_playing(false)),
+            );
         }
     }
 
@@ -57,12 +81,20 @@ impl MessageSticker {
             let message = message.downcast_ref::<Message>().unwrap();
 
             if let MessageContent::MessageSticker(data) = message.content().0 {
-                self.imp()
-                    .paintable
+                let imp = self.imp();
+                let is_animated = data.sticker.is_animated;
+
+                if let Some(tick_callback_id) = imp.tick_callback_id.take() {
+                    tick_callback_id.remove();
+                }
+
+                imp.file_id.set(data.sticker.sticker.id);
+                imp.paintable.reset();
+                imp.paintable
                     .set_aspect_ratio(data.sticker.width as f64 / data.sticker.height as f64);
 
                 if data.sticker.sticker.local.is_downloading_completed {
-                    self.load_sticker(&data.sticker.sticker.local.path);
+                    self.load_sticker(&data.sticker.sticker.local.path, is_animated);
                 } else {
                     let (sender, receiver) =
                         glib::MainContext::sync_channel::<File>(Default::default(), 5);
@@ -70,8 +102,13 @@ impl MessageSticker {
                     receiver.attach(
                         None,
                         clone!(@weak self as obj => @default-return glib::Continue(false), move |file| {
+                            // Check that the widget hasn't been recycled for another message
+                            if obj.imp().file_id.get() != file.id {
+                                return glib::Continue(false);
+                            }
+
                             if file.local.is_downloading_completed {
-                                obj.load_sticker(&file.local.path);
+                                obj.load_sticker(&file.local.path, is_animated);
                             }
 
                             glib::Continue(true)
@@ -87,33 +124,58 @@ impl MessageSticker {
         }
     }
 
-    fn load_sticker(&self, path: &str) {
-        let paintable = &self.imp().paintable;
-        let file = gio::File::for_path(path);
-        let future = clone!(@weak paintable => async move {
-            match file.load_bytes_future().await {
-                Ok((bytes, _)) => {
-                    let image = webp::Decoder::new(&bytes)
-                        .decode()
-                        .unwrap()
-                        .to_image()
-                        .into_rgba8();
-
-                    let flat_samples = image.into_flat_samples();
-
-                    let (stride, width, height) = flat_samples.extents();
-                    let gtk_stride = stride * width;
-
-                    let bytes = glib::Bytes::from_owned(flat_samples.samples);
-                    let texture = gdk::MemoryTexture::new(
-                        width as i32,
-                        height as i32,
-                        gdk::MemoryFormat::R8g8b8a8,
-                        &bytes,
-                        gtk_stride,
-                    );
-                    paintable.set_texture(Some(texture.upcast()));
+    fn open_sticker_set(&self) {
+        if let Some(message) = self.message().and_then(|m| m.downcast::<Message>().ok()) {
+            if let MessageContent::MessageSticker(data) = message.content().0 {
+                // Stickers that don't belong to a set have a set id of 0
+                if data.sticker.set_id != 0 {
+                    self.activate_action(
+                        "chat-history.open-sticker-set",
+                        Some(&data.sticker.set_id.to_variant()),
+                    )
+                    .unwrap();
+                }
+            }
+        }
+    }
+
+    fn load_sticker(&self, path: &str, is_animated: bool) {
+        let file_id = self.imp().file_id.get();
+        let path = path.to_owned();
+        let file = gio::File::for_path(&path);
+        let future = clone!(@weak self as obj => async move {
+            let result = file.load_bytes_future().await;
+
+            // Check that the widget hasn't been recycled for another message in the meantime
+            let imp = obj.imp();
+            if imp.file_id.get() != file_id {
+                return;
+            }
+
+            let paintable = &imp.paintable;
+            match result {
+                Ok((bytes, _)) if is_animated => {
+                    match load_lottie_animation(&bytes, &path) {
+                        Some(animation) => {
+                            paintable.set_lottie_animation(animation);
+                            obj.start_animation();
+                        }
+                        None => log::warn!("Failed to load an animated sticker"),
+                    }
+                }
+                Ok((bytes, _)) if bytes.starts_with(WEBM_MAGIC_NUMBER) => {
+                    let media_file = gtk::MediaFile::for_filename(&path);
+                    media_file.set_loop(true);
+                    media_file.set_muted(true);
+                    media_file.play();
+
+                    paintable.set_media_file(Some(media_file));
+                    obj.start_animation();
                 }
+                Ok((bytes, _)) => match decode_webp(&bytes) {
+                    Some(texture) => paintable.set_texture(Some(texture)),
+                    None => log::warn!("Failed to decode a sticker"),
+                },
                 Err(e) => {
                     log::warn!("Failed to load a sticker: {}", e);
                 }
@@ -122,4 +184,56 @@ impl MessageSticker {
 
         glib::MainContext::default().spawn_local(future);
     }
+
+    /// Advances the animation of the sticker on each frame, pausing it while the sticker
+    /// is scrolled off screen.
+    fn start_animation(&self) {
+        let imp = self.imp();
+        let paintable = &imp.paintable;
+        let last_frame_time = Cell::new(None);
+
+        let tick_callback_id = imp.picture.add_tick_callback(
+            clone!(@weak paintable => @default-return glib::Continue(false), move |widget, clock| {
+                let frame_time = clock.frame_time();
+                let is_on_screen = is_on_screen(widget.upcast_ref());
+
+                paintable.set_playing(is_on_screen);
+                if is_on_screen {
+                    if let Some(last_frame_time) = last_frame_time.get() {
+                        paintable.advance(frame_time - last_frame_time);
+                    }
+                }
+
+                last_frame_time.set(Some(frame_time));
+                glib::Continue(paintable.is_animated())
+            }),
+        );
+        imp.tick_callback_id.replace(Some(tick_callback_id));
+    }
+}
+
+/// Decodes a TGS sticker, which is a gzip compressed Lottie animation.
+fn load_lottie_animation(bytes: &[u8], cache_key: &str) -> Option<rlottie::Animation> {
+    let mut json_data = Vec::new();
+    if let Err(e) = flate2::read::GzDecoder::new(bytes).read_to_end(&mut json_data) {
+        log::warn!("Failed to decompress an animated sticker: {}", e);
+        return None;
+    }
+
+    rlottie::Animation::from_data(json_data, cache_key, "")
+}
+
+/// Whether the widget is inside the visible area of its scrolled window, if any.
+fn is_on_screen(widget: &gtk::Widget) -> bool {
+    let scrolled_window = match widget.ancestor(gtk::ScrolledWindow::static_type()) {
+        Some(scrolled_window) => scrolled_window,
+        None => return widget.is_mapped(),
+    };
+
+    match widget.compute_bounds(&scrolled_window) {
+        Some(bounds) => {
+            bounds.y() + bounds.height() > 0.0 && bounds.y() < scrolled_window.height() as f32
+        }
+        None => false,
+    }
 }
@@ -1,9 +1,26 @@
+use glib::clone;
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
 use gtk::{gdk, glib};
+use std::fmt;
 
 const MAX_SIZE: i32 = 200;
 
+/// A Lottie animation, together with the surface on which its frames are rendered
+pub struct LottieAnimation {
+    animation: rlottie::Animation,
+    surface: rlottie::Surface,
+    frame: Option<usize>,
+}
+
+impl fmt::Debug for LottieAnimation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LottieAnimation")
+            .field("frame", &self.frame)
+            .finish()
+    }
+}
+
 mod imp {
     use super::*;
     use once_cell::sync::Lazy;
@@ -14,6 +31,11 @@ mod imp {
         pub texture: RefCell<Option<gdk::Texture>>,
         pub width: Cell<i32>,
         pub height: Cell<i32>,
+        pub lottie_animation: RefCell<Option<LottieAnimation>>,
+        /// The time elapsed since the start of the Lottie animation, in microseconds
+        pub elapsed_time: Cell<i64>,
+        pub media_file: RefCell<Option<gtk::MediaFile>>,
+        pub media_file_handler_id: RefCell<Option<glib::SignalHandlerId>>,
     }
 
     #[glib::object_subclass]
@@ -74,7 +96,9 @@ mod imp {
             width: f64,
             height: f64,
         ) {
-            if let Some(texture) = self.texture.borrow().as_ref() {
+            if let Some(media_file) = self.media_file.borrow().as_ref() {
+                media_file.snapshot(snapshot, width, height);
+            } else if let Some(texture) = self.texture.borrow().as_ref() {
                 texture.snapshot(snapshot, width, height);
             }
         }
@@ -124,4 +148,103 @@ impl StickerPaintable {
 
         self.notify("texture");
     }
+
+    /// Removes the content of the sticker, so that it can be reused for another one.
+    pub fn reset(&self) {
+        let imp = self.imp();
+
+        imp.lottie_animation.replace(None);
+        self.set_media_file(None);
+        self.set_texture(None);
+    }
+
+    /// Whether the sticker is a Lottie animation or a video that needs to be played.
+    pub fn is_animated(&self) -> bool {
+        let imp = self.imp();
+        imp.lottie_animation.borrow().is_some() || imp.media_file.borrow().is_some()
+    }
+
+    pub fn set_lottie_animation(&self, animation: rlottie::Animation) {
+        let imp = self.imp();
+        let surface = rlottie::Surface::new(rlottie::Size::new(
+            imp.width.get() as usize,
+            imp.height.get() as usize,
+        ));
+
+        imp.lottie_animation.replace(Some(LottieAnimation {
+            animation,
+            surface,
+            frame: None,
+        }));
+        imp.elapsed_time.set(0);
+
+        self.advance(0);
+    }
+
+    pub fn set_media_file(&self, media_file: Option<gtk::MediaFile>) {
+        let imp = self.imp();
+
+        if let Some(old_media_file) = imp.media_file.borrow().as_ref() {
+            if let Some(handler_id) = imp.media_file_handler_id.take() {
+                old_media_file.disconnect(handler_id);
+            }
+        }
+
+        if let Some(ref media_file) = media_file {
+            let handler_id = media_file.connect_invalidate_contents(
+                clone!(@weak self as obj => move |_| obj.invalidate_contents()),
+            );
+            imp.media_file_handler_id.replace(Some(handler_id));
+        }
+
+        imp.media_file.replace(media_file);
+        self.invalidate_contents();
+    }
+
+    /// Plays or pauses the video of the sticker, if any.
+    pub fn set_playing(&self, playing: bool) {
+        if let Some(media_file) = self.imp().media_file.borrow().as_ref() {
+            media_file.set_playing(playing);
+        }
+    }
+
+    /// Advances the Lottie animation, if any, by the specified time in microseconds.
+    pub fn advance(&self, delta_time: i64) {
+        let imp = self.imp();
+        let mut lottie_animation = imp.lottie_animation.borrow_mut();
+        let lottie_animation = match lottie_animation.as_mut() {
+            Some(lottie_animation) => lottie_animation,
+            None => return,
+        };
+
+        let elapsed_time = imp.elapsed_time.get() + delta_time;
+        imp.elapsed_time.set(elapsed_time);
+
+        let total_frames = lottie_animation.animation.totalframe().max(1);
+        let frame = (elapsed_time as f64 / 1_000_000.0 * lottie_animation.animation.framerate())
+            as usize
+            % total_frames;
+
+        if lottie_animation.frame == Some(frame) {
+            return;
+        }
+
+        lottie_animation
+            .animation
+            .render(frame, &mut lottie_animation.surface);
+        lottie_animation.frame = Some(frame);
+
+        let width = lottie_animation.surface.width();
+        let height = lottie_animation.surface.height();
+        let bytes = glib::Bytes::from(lottie_animation.surface.data_as_bytes());
+        let texture = gdk::MemoryTexture::new(
+            width as i32,
+            height as i32,
+            gdk::MemoryFormat::B8g8r8a8Premultiplied,
+            &bytes,
+            width * 4,
+        );
+
+        self.set_texture(Some(texture.upcast()));
+    }
 }
@@ -1,11 +1,12 @@
 use gettextrs::gettext;
-use glib::closure;
+use glib::{clone, closure};
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
 use gtk::{glib, CompositeTemplate};
 use tdgrand::enums::MessageContent;
 
 use crate::session::chat::{BoxedMessageContent, Message, MessageSender, SponsoredMessage};
+use crate::session::content::message_row::{bind_forward_label, MessageReply};
 use crate::session::content::{MessageRow, MessageRowExt};
 use crate::session::{Chat, ChatType};
 use crate::utils::parse_formatted_text;
@@ -22,7 +23,13 @@ mod imp {
         #[template_child]
         pub sender_label: TemplateChild<gtk::Label>,
         #[template_child]
+        pub forward_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub reply: TemplateChild<MessageReply>,
+        #[template_child]
         pub content_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub edited_label: TemplateChild<gtk::Label>,
     }
 
     #[glib::object_subclass]
@@ -44,6 +51,12 @@ mod imp {
         fn constructed(&self, obj: &Self::Type) {
             self.parent_constructed(obj);
             obj.connect_message_notify(|obj, _| obj.update_widget());
+
+            self.content_label.connect_activate_link(
+                clone!(@weak obj => @default-return gtk::Inhibit(false), move |label, uri| {
+                    obj.upcast_ref::<MessageRow>().activate_link(label, uri)
+                }),
+            );
         }
     }
 
@@ -116,6 +129,12 @@ impl MessageText {
                     imp.sender_label.set_visible(false);
                 }
 
+                if let Some(forward_binding) = bind_forward_label(&imp.forward_label, message) {
+                    bindings.push(forward_binding);
+                }
+
+                imp.reply.update_from_message(message);
+
                 // Set content label expression
                 let text_binding = Message::this_expression("content")
                     .chain_closure::<String>(closure!(
@@ -125,8 +144,17 @@ impl MessageText {
                     ))
                     .bind(&*imp.content_label, "label", Some(message));
                 bindings.push(text_binding);
+
+                // Show the edited label if the message has been edited
+                let edited_binding = Message::this_expression("edit-date")
+                    .chain_closure::<bool>(closure!(|_: Message, edit_date: i32| edit_date > 0))
+                    .bind(&*imp.edited_label, "visible", Some(message));
+                bindings.push(edited_binding);
             } else if let Some(sponsored_message) = message.downcast_ref::<SponsoredMessage>() {
                 imp.sender_label.set_visible(true);
+                imp.forward_label.set_visible(false);
+                imp.reply.set_visible(false);
+                imp.edited_label.set_visible(false);
 
                 let sender_binding = Chat::this_expression("title").bind(
                     &*imp.sender_label,
@@ -0,0 +1,379 @@
+use glib::{clone, closure};
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+use gtk::{glib, CompositeTemplate};
+use tdgrand::enums::{MessageContent, ThumbnailFormat};
+use tdgrand::types::{File, Thumbnail};
+
+use crate::session::chat::{BoxedMessageContent, Message};
+use crate::session::content::message_row::{bind_forward_label, Media};
+use crate::session::content::{MessageRow, MessageRowExt};
+use crate::utils::parse_formatted_text;
+use crate::{spawn, Session};
+
+mod imp {
+    use super::*;
+    use glib::WeakRef;
+    use std::cell::{Cell, RefCell};
+
+    #[derive(Debug, Default, CompositeTemplate)]
+    #[template(resource = "/com/github/melix99/telegrand/ui/content-message-video.ui")]
+    pub struct MessageVideo {
+        pub binding: RefCell<Option<gtk::ExpressionWatch>>,
+        pub forward_binding: RefCell<Option<gtk::ExpressionWatch>>,
+        pub handler_id: RefCell<Option<glib::SignalHandlerId>>,
+        pub old_message: WeakRef<glib::Object>,
+        /// The id of the video file currently shown, used to ignore the updates of the
+        /// files of the previous messages
+        pub file_id: Cell<i32>,
+        pub is_downloading: Cell<bool>,
+        pub media_file: RefCell<Option<gtk::MediaFile>>,
+        #[template_child]
+        pub media: TemplateChild<Media>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for MessageVideo {
+        const NAME: &'static str = "ContentMessageVideo";
+        type Type = super::MessageVideo;
+        type ParentType = MessageRow;
+
+        fn class_init(klass: &mut Self::Class) {
+            Self::bind_template(klass);
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for MessageVideo {
+        fn constructed(&self, obj: &Self::Type) {
+            self.parent_constructed(obj);
+            obj.connect_message_notify(|obj, _| obj.update_widget());
+
+            self.media.caption_label().connect_activate_link(
+                clone!(@weak obj => @default-return gtk::Inhibit(false), move |label, uri| {
+                    obj.upcast_ref::<MessageRow>().activate_link(label, uri)
+                }),
+            );
+
+            let click_gesture = gtk::GestureClick::new();
+            click_gesture.connect_released(clone!(@weak obj => move |gesture, _, _, _| {
+                gesture.set_state(gtk::EventSequenceState::Claimed);
+                obj.toggle_playback();
+            }));
+            self.media.picture().add_controller(&click_gesture);
+        }
+    }
+
+    impl WidgetImpl for MessageVideo {
+        fn map(&self, widget: &Self::Type) {
+            self.parent_map(widget);
+
+            // Resume the animations paused when the row was hidden
+            if let Some(media_file) = self.media_file.borrow().as_ref() {
+                if media_file.is_loop() {
+                    media_file.play();
+                }
+            }
+        }
+
+        fn unmap(&self, widget: &Self::Type) {
+            // Don't keep playing the media of the rows that aren't shown
+            if let Some(media_file) = self.media_file.borrow().as_ref() {
+                media_file.pause();
+            }
+
+            self.parent_unmap(widget);
+        }
+    }
+}
+
+glib::wrapper! {
+    pub struct MessageVideo(ObjectSubclass<imp::MessageVideo>)
+        @extends gtk::Widget, MessageRow;
+}
+
+/// The common data of the video and animation messages
+struct VideoData {
+    file: File,
+    thumbnail: Option<Thumbnail>,
+    width: i32,
+    height: i32,
+    is_animation: bool,
+}
+
+impl VideoData {
+    fn from_message_content(content: MessageContent) -> Option<Self> {
+        match content {
+            MessageContent::MessageVideo(data) => Some(Self {
+                file: data.video.video,
+                thumbnail: data.video.thumbnail,
+                width: data.video.width,
+                height: data.video.height,
+                is_animation: false,
+            }),
+            MessageContent::MessageAnimation(data) => Some(Self {
+                file: data.animation.animation,
+                thumbnail: data.animation.thumbnail,
+                width: data.animation.width,
+                height: data.animation.height,
+                is_animation: true,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl MessageVideo {
+    fn update_widget(&self) {
+        let imp = self.imp();
+
+        if let Some(binding) = imp.binding.take() {
+            binding.unwatch();
+        }
+
+        if let Some(binding) = imp.forward_binding.take() {
+            binding.unwatch();
+        }
+
+        if let Some(old_message) = imp.old_message.upgrade() {
+            if let Some(id) = imp.handler_id.take() {
+                old_message.disconnect(id);
+            }
+        }
+
+        if let Some(message) = self.message() {
+            let message = message.downcast_ref::<Message>().unwrap();
+
+            // Setup caption expression
+            let caption_binding = Message::this_expression("content")
+                .chain_closure::<String>(closure!(|_: Message, content: BoxedMessageContent| {
+                    match content.0 {
+                        MessageContent::MessageVideo(data) => parse_formatted_text(data.caption),
+                        MessageContent::MessageAnimation(data) => {
+                            parse_formatted_text(data.caption)
+                        }
+                        _ => unreachable!(),
+                    }
+                }))
+                .bind(&*imp.media, "caption", Some(message));
+            imp.binding.replace(Some(caption_binding));
+
+            imp.forward_binding
+                .replace(bind_forward_label(imp.media.forward_label(), message));
+            imp.media.reply().update_from_message(message);
+
+            // Load video
+            let handler_id =
+                message.connect_content_notify(clone!(@weak self as obj => move |message, _| {
+                    obj.update_video(message);
+                }));
+            imp.handler_id.replace(Some(handler_id));
+            self.update_video(message);
+        } else if let Some(media_file) = imp.media_file.take() {
+            // Stop the media of the unbound message
+            media_file.set_playing(false);
+        }
+
+        imp.old_message.set(self.message().as_ref());
+    }
+
+    fn update_video(&self, message: &Message) {
+        let data = match VideoData::from_message_content(message.content().0) {
+            Some(data) => data,
+            None => return,
+        };
+        let imp = self.imp();
+
+        // Don't reload the video if only other parts of the content, like the caption,
+        // have been changed
+        if imp.file_id.get() == data.file.id && imp.media_file.borrow().is_some() {
+            return;
+        }
+
+        // Reset media widget
+        if let Some(media_file) = imp.media_file.take() {
+            media_file.set_playing(false);
+        }
+        imp.file_id.set(data.file.id);
+        imp.is_downloading.set(false);
+        imp.media.set_paintable(None);
+        imp.media.set_download_progress(1.0);
+        imp.media.set_play_icon_visible(!data.is_animation);
+        if data.width > 0 && data.height > 0 {
+            imp.media
+                .set_aspect_ratio(data.width as f64 / data.height as f64);
+        }
+
+        let session = message.chat().session();
+
+        if let Some(thumbnail) = data.thumbnail {
+            self.load_thumbnail(thumbnail, &session);
+        }
+
+        // Animations are played automatically, while videos only when clicked
+        if data.is_animation {
+            if data.file.local.is_downloading_completed {
+                self.load_video_from_path(&data.file.local.path, true);
+            } else {
+                self.download_video(data.file.id, true, &session);
+            }
+        }
+    }
+
+    fn load_thumbnail(&self, thumbnail: Thumbnail, session: &Session) {
+        // Animated thumbnails aren't supported yet
+        if !matches!(
+            thumbnail.format,
+            ThumbnailFormat::Jpeg | ThumbnailFormat::Png | ThumbnailFormat::Webp
+        ) {
+            return;
+        }
+
+        if thumbnail.file.local.is_downloading_completed {
+            self.load_thumbnail_from_file(&thumbnail.file, session);
+        } else {
+            let (sender, receiver) = glib::MainContext::sync_channel::<File>(Default::default(), 5);
+            let file_id = self.imp().file_id.get();
+
+            receiver.attach(
+                None,
+                clone!(@weak self as obj => @default-return glib::Continue(false), move |file| {
+                    // Check that the widget hasn't been recycled for another message
+                    if obj.imp().file_id.get() != file_id {
+                        return glib::Continue(false);
+                    }
+
+                    if file.local.is_downloading_completed {
+                        if let Some(message) =
+                            obj.message().and_then(|m| m.downcast::<Message>().ok())
+                        {
+                            obj.load_thumbnail_from_file(&file, &message.chat().session());
+                        }
+                    }
+
+                    glib::Continue(true)
+                }),
+            );
+
+            session.download_file(thumbnail.file.id, sender);
+        }
+    }
+
+    fn load_thumbnail_from_file(&self, file: &File, session: &Session) {
+        let video_file_id = self.imp().file_id.get();
+        let file_id = file.id;
+        let path = file.local.path.clone();
+
+        spawn!(clone!(@weak self as obj, @weak session => async move {
+            let result = session.load_image(file_id, &path, None).await;
+
+            // Check that the widget hasn't been recycled for another message and that the
+            // video hasn't been loaded in the meantime
+            let imp = obj.imp();
+            if imp.file_id.get() != video_file_id || imp.media_file.borrow().is_some() {
+                return;
+            }
+
+            match result {
+                Ok(texture) => imp.media.set_paintable(Some(texture.upcast())),
+                Err(e) => log::warn!("Failed to load the thumbnail of a video: {}", e),
+            }
+        }));
+    }
+
+    fn toggle_playback(&self) {
+        let imp = self.imp();
+
+        if let Some(media_file) = imp.media_file.borrow().as_ref() {
+            if media_file.is_ended() {
+                media_file.seek(0);
+            }
+            media_file.set_playing(!media_file.is_playing());
+            return;
+        }
+
+        if imp.is_downloading.get() {
+            return;
+        }
+
+        if let Some(message) = self.message().and_then(|m| m.downcast::<Message>().ok()) {
+            if let Some(data) = VideoData::from_message_content(message.content().0) {
+                if data.file.local.is_downloading_completed {
+                    self.load_video_from_path(&data.file.local.path, false);
+                } else {
+                    self.download_video(data.file.id, false, &message.chat().session());
+                }
+            }
+        }
+    }
+
+    fn download_video(&self, file_id: i32, is_animation: bool, session: &Session) {
+        let (sender, receiver) = glib::MainContext::sync_channel::<File>(Default::default(), 5);
+
+        receiver.attach(
+            None,
+            clone!(@weak self as obj => @default-return glib::Continue(false), move |file| {
+                let imp = obj.imp();
+
+                // Check that the widget hasn't been recycled for another message
+                if imp.file_id.get() != file.id {
+                    return glib::Continue(false);
+                }
+
+                if file.local.is_downloading_completed {
+                    imp.is_downloading.set(false);
+                    imp.media.set_download_progress(1.0);
+                    obj.load_video_from_path(&file.local.path, is_animation);
+                } else {
+                    let progress = file.local.downloaded_size as f64 / file.expected_size as f64;
+                    imp.media.set_download_progress(progress);
+                }
+
+                glib::Continue(true)
+            }),
+        );
+
+        let imp = self.imp();
+        imp.is_downloading.set(true);
+        imp.media.set_download_progress(0.0);
+        imp.media.set_play_icon_visible(false);
+
+        session.download_file(file_id, sender);
+    }
+
+    fn load_video_from_path(&self, path: &str, is_animation: bool) {
+        let imp = self.imp();
+        let media_file = gtk::MediaFile::for_filename(path);
+
+        if is_animation {
+            media_file.set_loop(true);
+            media_file.set_muted(true);
+        } else {
+            // Show the play icon while the video is paused
+            media_file.connect_playing_notify(clone!(@weak self as obj => move |media_file| {
+                let is_playing = media_file.is_playing();
+                obj.imp().media.set_play_icon_visible(!is_playing);
+
+                // Pause the other media of the session, so that only one is played at a time
+                if is_playing {
+                    if let Some(message) =
+                        obj.message().and_then(|m| m.downcast::<Message>().ok())
+                    {
+                        message
+                            .chat()
+                            .session()
+                            .set_playing_media(media_file.upcast_ref());
+                    }
+                }
+            }));
+        }
+
+        media_file.play();
+
+        imp.media.set_paintable(Some(media_file.clone().upcast()));
+        imp.media_file.replace(Some(media_file));
+    }
+}
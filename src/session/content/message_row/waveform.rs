@@ -0,0 +1,174 @@
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+use gtk::{gdk, glib, graphene};
+
+const HEIGHT: i32 = 24;
+const BAR_WIDTH: i32 = 2;
+const BAR_SPACING: i32 = 1;
+const MIN_BAR_HEIGHT: f32 = 2.0;
+
+mod imp {
+    use super::*;
+    use once_cell::sync::Lazy;
+    use std::cell::{Cell, RefCell};
+
+    #[derive(Debug, Default)]
+    pub struct Waveform {
+        /// The samples of the waveform, in the range from 0 to 1
+        pub samples: RefCell<Vec<f32>>,
+        pub progress: Cell<f64>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for Waveform {
+        const NAME: &'static str = "ContentMessageWaveform";
+        type Type = super::Waveform;
+        type ParentType = gtk::Widget;
+    }
+
+    impl ObjectImpl for Waveform {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                vec![glib::ParamSpecDouble::new(
+                    "progress",
+                    "Progress",
+                    "The playback progress of the waveform",
+                    0.0,
+                    1.0,
+                    0.0,
+                    glib::ParamFlags::READWRITE | glib::ParamFlags::EXPLICIT_NOTIFY,
+                )]
+            });
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(
+            &self,
+            obj: &Self::Type,
+            _id: usize,
+            value: &glib::Value,
+            pspec: &glib::ParamSpec,
+        ) {
+            match pspec.name() {
+                "progress" => obj.set_progress(value.get().unwrap()),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn property(&self, obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            match pspec.name() {
+                "progress" => obj.progress().to_value(),
+                _ => unimplemented!(),
+            }
+        }
+    }
+
+    impl WidgetImpl for Waveform {
+        fn measure(
+            &self,
+            _widget: &Self::Type,
+            orientation: gtk::Orientation,
+            _for_size: i32,
+        ) -> (i32, i32, i32, i32) {
+            if let gtk::Orientation::Horizontal = orientation {
+                let natural = self.samples.borrow().len() as i32 * (BAR_WIDTH + BAR_SPACING);
+                (0, natural, -1, -1)
+            } else {
+                (HEIGHT, HEIGHT, -1, -1)
+            }
+        }
+
+        fn snapshot(&self, widget: &Self::Type, snapshot: &gtk::Snapshot) {
+            let samples = self.samples.borrow();
+            if samples.is_empty() {
+                return;
+            }
+
+            let width = widget.width();
+            let height = widget.height() as f32;
+            let bars = (width / (BAR_WIDTH + BAR_SPACING)).max(1) as usize;
+            let played_bars = (bars as f64 * self.progress.get()).round() as usize;
+
+            let color = widget.style_context().color();
+            let unplayed_color = gdk::RGBA::new(
+                color.red(),
+                color.green(),
+                color.blue(),
+                color.alpha() * 0.4,
+            );
+
+            for bar in 0..bars {
+                // Take the loudest sample of the ones represented by this bar
+                let start = bar * samples.len() / bars;
+                let end = ((bar + 1) * samples.len() / bars).max(start + 1);
+                let sample = samples[start..end.min(samples.len())]
+                    .iter()
+                    .cloned()
+                    .fold(0.0, f32::max);
+
+                let bar_height = (sample * height).max(MIN_BAR_HEIGHT);
+                let rect = graphene::Rect::new(
+                    (bar as i32 * (BAR_WIDTH + BAR_SPACING)) as f32,
+                    height - bar_height,
+                    BAR_WIDTH as f32,
+                    bar_height,
+                );
+
+                if bar < played_bars {
+                    snapshot.append_color(&color, &rect);
+                } else {
+                    snapshot.append_color(&unplayed_color, &rect);
+                }
+            }
+        }
+    }
+}
+
+glib::wrapper! {
+    pub struct Waveform(ObjectSubclass<imp::Waveform>)
+        @extends gtk::Widget;
+}
+
+impl Default for Waveform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Waveform {
+    pub fn new() -> Self {
+        glib::Object::new(&[]).expect("Failed to create Waveform")
+    }
+
+    /// Sets the waveform of a voice note, as returned by TDLib: a base64 encoded sequence of
+    /// 5-bit samples.
+    pub fn set_waveform(&self, waveform: &str) {
+        let data = glib::base64_decode(waveform);
+        let samples = (0..data.len() * 8 / 5)
+            .map(|i| {
+                let bit = i * 5;
+                let byte = bit / 8;
+                let value = data[byte] as u16 | (*data.get(byte + 1).unwrap_or(&0) as u16) << 8;
+                ((value >> (bit % 8)) & 31) as f32 / 31.0
+            })
+            .collect();
+
+        self.imp().samples.replace(samples);
+        self.queue_resize();
+    }
+
+    pub fn progress(&self) -> f64 {
+        self.imp().progress.get()
+    }
+
+    pub fn set_progress(&self, progress: f64) {
+        if self.progress() == progress {
+            return;
+        }
+
+        self.imp().progress.set(progress);
+        self.queue_draw();
+
+        self.notify("progress");
+    }
+}
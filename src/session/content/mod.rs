@@ -1,15 +1,27 @@
 mod chat_action_bar;
 mod chat_history;
+mod chat_search_bar;
 mod event_row;
+mod forward_dialog;
 mod item_row;
+mod media_viewer;
 mod message_row;
+mod send_media_dialog;
+mod sticker_chooser;
+mod sticker_set_dialog;
 mod user_dialog;
 
 use self::chat_action_bar::ChatActionBar;
 use self::chat_history::ChatHistory;
+use self::chat_search_bar::ChatSearchBar;
 use self::event_row::EventRow;
+use self::forward_dialog::ForwardDialog;
 use self::item_row::ItemRow;
+use self::media_viewer::MediaViewer;
 use self::message_row::{MessageRow, MessageRowExt};
+use self::send_media_dialog::SendMediaDialog;
+use self::sticker_chooser::{sticker_button, BoxedSticker, StickerChooser};
+use self::sticker_set_dialog::StickerSetDialog;
 use self::user_dialog::UserDialog;
 
 use gtk::glib;
@@ -131,6 +143,15 @@ impl Content {
         self.imp().chat.borrow().clone()
     }
 
+    /// Makes the next opened chat start from the message instead of the first unread one.
+    pub fn set_initial_message_id(&self, message_id: i64) {
+        self.imp().chat_history.set_initial_message_id(message_id);
+    }
+
+    pub fn jump_to_message(&self, message_id: i64) {
+        self.imp().chat_history.jump_to_message(message_id);
+    }
+
     fn set_chat(&self, chat: Option<Chat>) {
         if self.chat() == chat {
             return;
@@ -0,0 +1,207 @@
+use gettextrs::gettext;
+use glib::clone;
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+use gtk::{gio, glib, CompositeTemplate};
+use tdgrand::enums::{InputFile, InputMessageContent};
+use tdgrand::types;
+
+use crate::utils::parse_markdown;
+
+mod imp {
+    use super::*;
+    use adw::subclass::prelude::AdwWindowImpl;
+    use glib::subclass::Signal;
+    use once_cell::sync::{Lazy, OnceCell};
+    use std::cell::Cell;
+
+    #[derive(Debug, Default, CompositeTemplate)]
+    #[template(resource = "/com/github/melix99/telegrand/ui/content-send-media-dialog.ui")]
+    pub struct SendMediaDialog {
+        pub file: OnceCell<gio::File>,
+        pub is_photo: Cell<bool>,
+        #[template_child]
+        pub window_title: TemplateChild<adw::WindowTitle>,
+        #[template_child]
+        pub picture: TemplateChild<gtk::Picture>,
+        #[template_child]
+        pub file_box: TemplateChild<gtk::Box>,
+        #[template_child]
+        pub file_icon: TemplateChild<gtk::Image>,
+        #[template_child]
+        pub file_name_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub caption_entry: TemplateChild<gtk::Entry>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for SendMediaDialog {
+        const NAME: &'static str = "ContentSendMediaDialog";
+        type Type = super::SendMediaDialog;
+        type ParentType = adw::Window;
+
+        fn class_init(klass: &mut Self::Class) {
+            Self::bind_template(klass);
+
+            klass.install_action("send-media-dialog.send", None, move |widget, _, _| {
+                widget.emit_by_name::<()>("send", &[]);
+                widget.close();
+            });
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for SendMediaDialog {
+        fn signals() -> &'static [Signal] {
+            static SIGNALS: Lazy<Vec<Signal>> =
+                Lazy::new(
+                    || vec![Signal::builder("send", &[], <()>::static_type().into()).build()],
+                );
+            SIGNALS.as_ref()
+        }
+
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                vec![glib::ParamSpecObject::new(
+                    "file",
+                    "File",
+                    "The file to send",
+                    gio::File::static_type(),
+                    glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
+                )]
+            });
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(
+            &self,
+            _obj: &Self::Type,
+            _id: usize,
+            value: &glib::Value,
+            pspec: &glib::ParamSpec,
+        ) {
+            match pspec.name() {
+                "file" => self.file.set(value.get().unwrap()).unwrap(),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn property(&self, obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            match pspec.name() {
+                "file" => obj.file().to_value(),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn constructed(&self, obj: &Self::Type) {
+            self.parent_constructed(obj);
+
+            obj.setup_preview();
+
+            self.caption_entry
+                .connect_activate(clone!(@weak obj => move |_| {
+                    obj.activate_action("send-media-dialog.send", None).unwrap();
+                }));
+        }
+    }
+
+    impl WidgetImpl for SendMediaDialog {}
+    impl WindowImpl for SendMediaDialog {}
+    impl AdwWindowImpl for SendMediaDialog {}
+}
+
+glib::wrapper! {
+    pub struct SendMediaDialog(ObjectSubclass<imp::SendMediaDialog>)
+        @extends gtk::Widget, gtk::Window, adw::Window;
+}
+
+impl SendMediaDialog {
+    pub fn new(parent_window: &Option<gtk::Window>, file: &gio::File) -> Self {
+        glib::Object::new(&[("transient-for", parent_window), ("file", file)])
+            .expect("Failed to create SendMediaDialog")
+    }
+
+    fn setup_preview(&self) {
+        let imp = self.imp();
+        let file = self.file();
+        let file_name = file
+            .basename()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        // Detect the type from the content of the file, as its extension may be wrong
+        let content_type = file
+            .query_info(
+                &gio::FILE_ATTRIBUTE_STANDARD_CONTENT_TYPE,
+                gio::FileQueryInfoFlags::NONE,
+                gio::Cancellable::NONE,
+            )
+            .ok()
+            .and_then(|info| info.content_type())
+            .unwrap_or_else(|| gio::content_type_guess(Some(&file_name), &[]).0);
+        let is_photo = gio::content_type_get_mime_type(&content_type)
+            .map(|mime_type| is_photo_mime_type(&mime_type))
+            .unwrap_or_default();
+
+        if is_photo {
+            imp.window_title.set_title(&gettext("Send Photo"));
+            imp.picture.set_file(Some(file));
+            imp.picture.set_visible(true);
+        } else {
+            imp.window_title.set_title(&gettext("Send File"));
+            imp.file_icon
+                .set_from_gicon(&gio::content_type_get_icon(&content_type));
+            imp.file_name_label.set_label(&file_name);
+            imp.file_box.set_visible(true);
+        }
+
+        imp.is_photo.set(is_photo);
+    }
+
+    /// Returns the content of the message to send, with the caption written by the user.
+    /// The file is sent as a photo, if possible, or as a document otherwise.
+    pub fn input_message_content(&self) -> Option<InputMessageContent> {
+        let imp = self.imp();
+        let path = self.file().path()?.to_string_lossy().into_owned();
+        let file = InputFile::Local(types::InputFileLocal { path });
+        let caption = parse_markdown(imp.caption_entry.text().trim());
+
+        let content = if imp.is_photo.get() {
+            InputMessageContent::InputMessagePhoto(types::InputMessagePhoto {
+                photo: file,
+                caption,
+                ..Default::default()
+            })
+        } else {
+            InputMessageContent::InputMessageDocument(types::InputMessageDocument {
+                document: file,
+                caption,
+                ..Default::default()
+            })
+        };
+
+        Some(content)
+    }
+
+    pub fn file(&self) -> &gio::File {
+        self.imp().file.get().unwrap()
+    }
+
+    pub fn connect_send<F: Fn(&Self) + 'static>(&self, f: F) -> glib::SignalHandlerId {
+        self.connect_local("send", true, move |values| {
+            let obj = values[0].get::<Self>().unwrap();
+            f(&obj);
+
+            None
+        })
+    }
+}
+
+fn is_photo_mime_type(mime_type: &str) -> bool {
+    matches!(
+        mime_type,
+        "image/jpeg" | "image/png" | "image/bmp" | "image/webp"
+    )
+}
@@ -0,0 +1,340 @@
+use gettextrs::gettext;
+use glib::clone;
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+use gtk::{gio, glib, CompositeTemplate};
+use tdgrand::enums::{self, ThumbnailFormat};
+use tdgrand::functions;
+use tdgrand::types::{File, Sticker, StickerSetInfo};
+
+use crate::session::Session;
+use crate::spawn;
+use crate::utils::decode_webp;
+
+const STICKER_SIZE: i32 = 56;
+
+#[derive(Clone, Debug, glib::Boxed)]
+#[boxed_type(name = "BoxedSticker")]
+pub struct BoxedSticker(pub Sticker);
+
+mod imp {
+    use super::*;
+    use glib::subclass::Signal;
+    use glib::WeakRef;
+    use once_cell::sync::Lazy;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    #[derive(Debug, Default, CompositeTemplate)]
+    #[template(resource = "/com/github/melix99/telegrand/ui/content-sticker-chooser.ui")]
+    pub struct StickerChooser {
+        pub session: WeakRef<Session>,
+        pub recent_stickers: RefCell<Vec<Sticker>>,
+        pub sticker_sets: RefCell<Vec<StickerSetInfo>>,
+        /// The stickers of the sets that have already been shown, by set id
+        pub set_stickers: RefCell<HashMap<i64, Vec<Sticker>>>,
+        #[template_child]
+        pub set_drop_down: TemplateChild<gtk::DropDown>,
+        #[template_child]
+        pub set_list: TemplateChild<gtk::StringList>,
+        #[template_child]
+        pub scrolled_window: TemplateChild<gtk::ScrolledWindow>,
+        #[template_child]
+        pub flow_box: TemplateChild<gtk::FlowBox>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for StickerChooser {
+        const NAME: &'static str = "ContentStickerChooser";
+        type Type = super::StickerChooser;
+        type ParentType = gtk::Widget;
+
+        fn class_init(klass: &mut Self::Class) {
+            Self::bind_template(klass);
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for StickerChooser {
+        fn signals() -> &'static [Signal] {
+            static SIGNALS: Lazy<Vec<Signal>> = Lazy::new(|| {
+                vec![Signal::builder(
+                    "sticker-selected",
+                    &[BoxedSticker::static_type().into()],
+                    <()>::static_type().into(),
+                )
+                .build()]
+            });
+            SIGNALS.as_ref()
+        }
+
+        fn constructed(&self, obj: &Self::Type) {
+            self.parent_constructed(obj);
+
+            self.set_drop_down
+                .connect_selected_notify(clone!(@weak obj => move |_| obj.update_stickers()));
+        }
+
+        fn dispose(&self, _obj: &Self::Type) {
+            self.set_drop_down.unparent();
+            self.scrolled_window.unparent();
+        }
+    }
+
+    impl WidgetImpl for StickerChooser {}
+}
+
+glib::wrapper! {
+    pub struct StickerChooser(ObjectSubclass<imp::StickerChooser>)
+        @extends gtk::Widget;
+}
+
+impl Default for StickerChooser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StickerChooser {
+    pub fn new() -> Self {
+        glib::Object::new(&[]).expect("Failed to create StickerChooser")
+    }
+
+    /// Loads the recently used stickers and the installed sticker sets of the session. They're
+    /// requested again at every call, as they may have changed in the meantime, while the
+    /// stickers of the sets are kept until the session changes.
+    pub fn load(&self, session: &Session) {
+        let imp = self.imp();
+        if imp.session.upgrade().as_ref() != Some(session) {
+            imp.session.set(Some(session));
+            imp.recent_stickers.borrow_mut().clear();
+            imp.sticker_sets.borrow_mut().clear();
+            imp.set_stickers.borrow_mut().clear();
+            imp.set_list.splice(0, imp.set_list.n_items(), &[]);
+        }
+
+        let client_id = session.client_id();
+        spawn!(clone!(@weak self as obj => async move {
+            let recent_stickers = functions::GetRecentStickers::new()
+                .is_attached(false)
+                .send(client_id)
+                .await;
+            let sticker_sets = functions::GetInstalledStickerSets::new()
+                .is_masks(false)
+                .send(client_id)
+                .await;
+
+            let imp = obj.imp();
+
+            // Ignore the results if the session has changed in the meantime
+            if imp.session.upgrade().map(|session| session.client_id()) != Some(client_id) {
+                return;
+            }
+
+            // Keep the selected set, if it's still installed
+            let selected_set_id = imp
+                .set_drop_down
+                .selected()
+                .checked_sub(1)
+                .and_then(|index| imp.sticker_sets.borrow().get(index as usize).map(|set| set.id));
+
+            match recent_stickers {
+                Ok(enums::Stickers::Stickers(stickers)) => {
+                    imp.recent_stickers.replace(stickers.stickers);
+                }
+                Err(e) => log::warn!("Failed to get the recent stickers: {:?}", e),
+            }
+
+            match sticker_sets {
+                Ok(enums::StickerSets::StickerSets(sticker_sets)) => {
+                    imp.sticker_sets.replace(sticker_sets.sets);
+                }
+                Err(e) => log::warn!("Failed to get the installed sticker sets: {:?}", e),
+            }
+
+            // The first item of the list is always the one of the recent stickers
+            let mut titles = vec![gettext("Recent")];
+            titles.extend(imp.sticker_sets.borrow().iter().map(|set| set.title.clone()));
+            let titles: Vec<&str> = titles.iter().map(String::as_str).collect();
+            imp.set_list.splice(0, imp.set_list.n_items(), &titles);
+
+            let selected = selected_set_id
+                .and_then(|set_id| {
+                    imp.sticker_sets
+                        .borrow()
+                        .iter()
+                        .position(|set| set.id == set_id)
+                })
+                .map_or(0, |index| index as u32 + 1);
+            imp.set_drop_down.set_selected(selected);
+            obj.update_stickers();
+        }));
+    }
+
+    /// Shows the stickers of the selected set, requesting them if needed.
+    fn update_stickers(&self) {
+        let imp = self.imp();
+        let selected = imp.set_drop_down.selected();
+
+        if selected == gtk::INVALID_LIST_POSITION {
+            return;
+        }
+
+        if selected == 0 {
+            let stickers = imp.recent_stickers.borrow().clone();
+            self.show_stickers(&stickers);
+            return;
+        }
+
+        let set_id = match imp.sticker_sets.borrow().get(selected as usize - 1) {
+            Some(set) => set.id,
+            None => return,
+        };
+
+        if let Some(stickers) = imp.set_stickers.borrow().get(&set_id) {
+            self.show_stickers(stickers);
+            return;
+        }
+
+        self.show_stickers(&[]);
+
+        let client_id = match imp.session.upgrade() {
+            Some(session) => session.client_id(),
+            None => return,
+        };
+
+        spawn!(clone!(@weak self as obj => async move {
+            let result = functions::GetStickerSet::new()
+                .set_id(set_id)
+                .send(client_id)
+                .await;
+
+            match result {
+                Ok(enums::StickerSet::StickerSet(set)) => {
+                    obj.imp().set_stickers.borrow_mut().insert(set_id, set.stickers);
+                    obj.update_stickers();
+                }
+                Err(e) => log::warn!("Failed to get a sticker set: {:?}", e),
+            }
+        }));
+    }
+
+    fn show_stickers(&self, stickers: &[Sticker]) {
+        let imp = self.imp();
+
+        while let Some(child) = imp.flow_box.first_child() {
+            imp.flow_box.remove(&child);
+        }
+
+        if let Some(session) = imp.session.upgrade() {
+            for sticker in stickers {
+                let button = sticker_button(sticker, &session);
+                let sticker = BoxedSticker(sticker.clone());
+                button.connect_clicked(clone!(@weak self as obj => move |_| {
+                    obj.emit_by_name::<()>("sticker-selected", &[&sticker]);
+                }));
+                imp.flow_box.insert(&button, -1);
+            }
+        }
+    }
+
+    pub fn connect_sticker_selected<F: Fn(&Self, &Sticker) + 'static>(
+        &self,
+        f: F,
+    ) -> glib::SignalHandlerId {
+        self.connect_local("sticker-selected", true, move |values| {
+            let obj = values[0].get::<Self>().unwrap();
+            let sticker = values[1].get::<BoxedSticker>().unwrap();
+            f(&obj, &sticker.0);
+
+            None
+        })
+    }
+}
+
+/// Creates a button showing the thumbnail of the sticker. The thumbnail is downloaded when
+/// the button is shown for the first time.
+pub fn sticker_button(sticker: &Sticker, session: &Session) -> gtk::Button {
+    let button = gtk::Button::builder()
+        .tooltip_text(&sticker.emoji)
+        .css_classes(vec!["flat".to_string()])
+        .build();
+
+    // Use the sticker itself for the static stickers without a thumbnail
+    let file = match sticker.thumbnail {
+        Some(ref thumbnail) if !matches!(thumbnail.format, ThumbnailFormat::Tgs) => {
+            Some(thumbnail.file.clone())
+        }
+        _ if !sticker.is_animated => Some(sticker.sticker.clone()),
+        _ => None,
+    };
+
+    match file {
+        Some(file) => {
+            let picture = gtk::Picture::builder()
+                .width_request(STICKER_SIZE)
+                .height_request(STICKER_SIZE)
+                .build();
+            button.set_child(Some(&picture));
+
+            button.connect_map(clone!(@weak session => move |_| {
+                if picture.paintable().is_none() {
+                    load_sticker_thumbnail(&picture, &file, &session);
+                }
+            }));
+        }
+        None => button.set_label(&sticker.emoji),
+    }
+
+    button
+}
+
+fn load_sticker_thumbnail(picture: &gtk::Picture, file: &File, session: &Session) {
+    if file.local.is_downloading_completed {
+        let file_id = file.id;
+        let path = file.local.path.clone();
+
+        spawn!(clone!(@weak picture, @weak session => async move {
+            let bytes = match gio::File::for_path(&path).load_bytes_future().await {
+                Ok((bytes, _)) => bytes,
+                Err(e) => {
+                    log::warn!("Failed to load a sticker thumbnail: {}", e);
+                    return;
+                }
+            };
+
+            // The thumbnails can also be in JPEG format, which is decoded in a worker thread
+            let texture = match decode_webp(&bytes) {
+                Some(texture) => texture,
+                None => match session.load_image(file_id, &path, None).await {
+                    Ok(texture) => texture,
+                    Err(e) => {
+                        log::warn!("Failed to decode a sticker thumbnail: {}", e);
+                        return;
+                    }
+                },
+            };
+            picture.set_paintable(Some(&texture));
+        }));
+    } else {
+        let (sender, receiver) = glib::MainContext::sync_channel::<File>(Default::default(), 5);
+
+        receiver.attach(
+            None,
+            clone!(@weak picture, @weak session => @default-return glib::Continue(false),
+                move |file| {
+                    if file.local.is_downloading_completed {
+                        load_sticker_thumbnail(&picture, &file, &session);
+                    }
+
+                    glib::Continue(!file.local.is_downloading_completed)
+                }
+            ),
+        );
+
+        session.download_file(file.id, sender);
+    }
+}
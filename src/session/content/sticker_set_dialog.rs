@@ -0,0 +1,242 @@
+use gettextrs::{gettext, ngettext};
+use glib::clone;
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+use gtk::{glib, CompositeTemplate};
+use tdgrand::enums;
+use tdgrand::functions;
+use tdgrand::types::{Sticker, StickerSet};
+
+use crate::session::content::{sticker_button, BoxedSticker};
+use crate::session::Session;
+use crate::spawn;
+
+mod imp {
+    use super::*;
+    use adw::subclass::prelude::AdwWindowImpl;
+    use glib::subclass::Signal;
+    use once_cell::sync::{Lazy, OnceCell};
+    use std::cell::Cell;
+
+    #[derive(Debug, Default, CompositeTemplate)]
+    #[template(resource = "/com/github/melix99/telegrand/ui/content-sticker-set-dialog.ui")]
+    pub struct StickerSetDialog {
+        pub session: OnceCell<Session>,
+        pub set_id: Cell<i64>,
+        pub is_installed: Cell<bool>,
+        #[template_child]
+        pub window_title: TemplateChild<adw::WindowTitle>,
+        #[template_child]
+        pub flow_box: TemplateChild<gtk::FlowBox>,
+        #[template_child]
+        pub install_button: TemplateChild<gtk::Button>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for StickerSetDialog {
+        const NAME: &'static str = "ContentStickerSetDialog";
+        type Type = super::StickerSetDialog;
+        type ParentType = adw::Window;
+
+        fn class_init(klass: &mut Self::Class) {
+            Self::bind_template(klass);
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for StickerSetDialog {
+        fn signals() -> &'static [Signal] {
+            static SIGNALS: Lazy<Vec<Signal>> = Lazy::new(|| {
+                vec![Signal::builder(
+                    "sticker-selected",
+                    &[BoxedSticker::static_type().into()],
+                    <()>::static_type().into(),
+                )
+                .build()]
+            });
+            SIGNALS.as_ref()
+        }
+
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                vec![
+                    glib::ParamSpecObject::new(
+                        "session",
+                        "Session",
+                        "The session of the sticker set",
+                        Session::static_type(),
+                        glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
+                    ),
+                    glib::ParamSpecInt64::new(
+                        "set-id",
+                        "Set Id",
+                        "The id of the sticker set",
+                        std::i64::MIN,
+                        std::i64::MAX,
+                        0,
+                        glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
+                    ),
+                ]
+            });
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(
+            &self,
+            _obj: &Self::Type,
+            _id: usize,
+            value: &glib::Value,
+            pspec: &glib::ParamSpec,
+        ) {
+            match pspec.name() {
+                "session" => self.session.set(value.get().unwrap()).unwrap(),
+                "set-id" => self.set_id.set(value.get().unwrap()),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn property(&self, obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            match pspec.name() {
+                "session" => obj.session().to_value(),
+                "set-id" => obj.set_id().to_value(),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn constructed(&self, obj: &Self::Type) {
+            self.parent_constructed(obj);
+
+            self.install_button
+                .connect_clicked(clone!(@weak obj => move |_| obj.toggle_installed()));
+
+            obj.load_sticker_set();
+        }
+    }
+
+    impl WidgetImpl for StickerSetDialog {}
+    impl WindowImpl for StickerSetDialog {}
+    impl AdwWindowImpl for StickerSetDialog {}
+}
+
+glib::wrapper! {
+    pub struct StickerSetDialog(ObjectSubclass<imp::StickerSetDialog>)
+        @extends gtk::Widget, gtk::Window, adw::Window;
+}
+
+impl StickerSetDialog {
+    pub fn new(parent_window: &Option<gtk::Window>, session: &Session, set_id: i64) -> Self {
+        glib::Object::new(&[
+            ("transient-for", parent_window),
+            ("session", session),
+            ("set-id", &set_id),
+        ])
+        .expect("Failed to create StickerSetDialog")
+    }
+
+    fn load_sticker_set(&self) {
+        let client_id = self.session().client_id();
+        let set_id = self.set_id();
+
+        spawn!(clone!(@weak self as obj => async move {
+            let result = functions::GetStickerSet::new()
+                .set_id(set_id)
+                .send(client_id)
+                .await;
+
+            match result {
+                Ok(enums::StickerSet::StickerSet(set)) => obj.set_sticker_set(set),
+                Err(e) => log::warn!("Failed to get a sticker set: {:?}", e),
+            }
+        }));
+    }
+
+    fn set_sticker_set(&self, set: StickerSet) {
+        let imp = self.imp();
+        let session = self.session();
+
+        imp.window_title.set_title(&set.title);
+        imp.window_title.set_subtitle(&ngettext!(
+            "{} sticker",
+            "{} stickers",
+            set.stickers.len() as u32,
+            set.stickers.len()
+        ));
+
+        for sticker in &set.stickers {
+            let button = sticker_button(sticker, session);
+            let sticker = BoxedSticker(sticker.clone());
+            button.connect_clicked(clone!(@weak self as obj => move |_| {
+                obj.emit_by_name::<()>("sticker-selected", &[&sticker]);
+                obj.close();
+            }));
+            imp.flow_box.insert(&button, -1);
+        }
+
+        self.update_install_button(set.is_installed && !set.is_archived);
+        imp.install_button.set_sensitive(true);
+    }
+
+    fn update_install_button(&self, is_installed: bool) {
+        let imp = self.imp();
+        imp.is_installed.set(is_installed);
+
+        if is_installed {
+            imp.install_button.set_label(&gettext("Remove Stickers"));
+            imp.install_button.remove_css_class("suggested-action");
+            imp.install_button.add_css_class("destructive-action");
+        } else {
+            imp.install_button.set_label(&gettext("Add Stickers"));
+            imp.install_button.remove_css_class("destructive-action");
+            imp.install_button.add_css_class("suggested-action");
+        }
+    }
+
+    fn toggle_installed(&self) {
+        let imp = self.imp();
+        let client_id = self.session().client_id();
+        let set_id = self.set_id();
+        let is_installed = !imp.is_installed.get();
+
+        imp.install_button.set_sensitive(false);
+
+        spawn!(clone!(@weak self as obj => async move {
+            let result = functions::ChangeStickerSet::new()
+                .set_id(set_id)
+                .is_installed(is_installed)
+                .is_archived(false)
+                .send(client_id)
+                .await;
+
+            match result {
+                Ok(_) => obj.update_install_button(is_installed),
+                Err(e) => log::warn!("Failed to change a sticker set: {:?}", e),
+            }
+
+            obj.imp().install_button.set_sensitive(true);
+        }));
+    }
+
+    pub fn connect_sticker_selected<F: Fn(&Self, &Sticker) + 'static>(
+        &self,
+        f: F,
+    ) -> glib::SignalHandlerId {
+        self.connect_local("sticker-selected", true, move |values| {
+            let obj = values[0].get::<Self>().unwrap();
+            let sticker = values[1].get::<BoxedSticker>().unwrap();
+            f(&obj, &sticker.0);
+
+            None
+        })
+    }
+
+    pub fn session(&self) -> &Session {
+        self.imp().session.get().unwrap()
+    }
+
+    pub fn set_id(&self) -> i64 {
+        self.imp().set_id.get()
+    }
+}
@@ -0,0 +1,108 @@
+use gtk::prelude::*;
+use gtk::{gdk, gdk_pixbuf, glib};
+use indexmap::IndexMap;
+
+use crate::RUNTIME;
+
+/// The maximum size of the decoded images kept in memory, in bytes
+const MAX_CACHE_SIZE: usize = 64 * 1024 * 1024;
+
+/// The key of a cached image: the id of its file and the size it was scaled to, if any
+type ImageKey = (i32, Option<i32>);
+
+/// An image decoded in a worker thread, ready to be turned into a texture
+struct DecodedImage {
+    bytes: glib::Bytes,
+    width: i32,
+    height: i32,
+    stride: usize,
+    has_alpha: bool,
+}
+
+/// An in-memory cache of the decoded images of a session, which keeps the most recently used
+/// ones until their total size exceeds `MAX_CACHE_SIZE`.
+#[derive(Debug, Default)]
+pub struct ImageCache {
+    textures: IndexMap<ImageKey, gdk::Texture>,
+    size: usize,
+}
+
+impl ImageCache {
+    pub fn get(&mut self, key: &ImageKey) -> Option<gdk::Texture> {
+        // Move the texture to the end, as it's now the most recently used one
+        let (key, texture) = self.textures.shift_remove_entry(key)?;
+        self.textures.insert(key, texture.clone());
+        Some(texture)
+    }
+
+    pub fn insert(&mut self, key: ImageKey, texture: gdk::Texture) {
+        self.size += texture_size(&texture);
+        if let Some(old_texture) = self.textures.insert(key, texture) {
+            self.size -= texture_size(&old_texture);
+        }
+
+        // Remove the least recently used textures, always keeping the last one
+        while self.size > MAX_CACHE_SIZE && self.textures.len() > 1 {
+            let (_, texture) = self.textures.shift_remove_index(0).unwrap();
+            self.size -= texture_size(&texture);
+        }
+    }
+}
+
+fn texture_size(texture: &gdk::Texture) -> usize {
+    texture.width() as usize * texture.height() as usize * 4
+}
+
+/// Decodes the image at the path in a worker thread, scaling it down to fit in a square of
+/// the given size, if any.
+pub async fn decode_image(path: String, size: Option<i32>) -> Result<gdk::Texture, glib::Error> {
+    let image = RUNTIME
+        .spawn_blocking(move || decode_image_blocking(&path, size))
+        .await
+        .map_err(|e| {
+            // The decoder panicked or the task was cancelled
+            glib::Error::new(
+                gdk_pixbuf::PixbufError::Failed,
+                &format!("Failed to decode the image: {}", e),
+            )
+        })??;
+
+    let format = if image.has_alpha {
+        gdk::MemoryFormat::R8g8b8a8
+    } else {
+        gdk::MemoryFormat::R8g8b8
+    };
+    let texture = gdk::MemoryTexture::new(
+        image.width,
+        image.height,
+        format,
+        &image.bytes,
+        image.stride,
+    );
+
+    Ok(texture.upcast())
+}
+
+fn decode_image_blocking(path: &str, size: Option<i32>) -> Result<DecodedImage, glib::Error> {
+    let pixbuf = match size {
+        // Don't scale up the images that are already small enough
+        Some(size) => match gdk_pixbuf::Pixbuf::file_info(path) {
+            Some((_, width, height)) if width > size || height > size => {
+                gdk_pixbuf::Pixbuf::from_file_at_scale(path, size, size, true)?
+            }
+            _ => gdk_pixbuf::Pixbuf::from_file(path)?,
+        },
+        None => gdk_pixbuf::Pixbuf::from_file(path)?,
+    };
+
+    // Rotate the photos taken with a rotated camera
+    let pixbuf = pixbuf.apply_embedded_orientation().unwrap_or(pixbuf);
+
+    Ok(DecodedImage {
+        bytes: pixbuf.read_pixel_bytes(),
+        width: pixbuf.width(),
+        height: pixbuf.height(),
+        stride: pixbuf.rowstride() as usize,
+        has_alpha: pixbuf.has_alpha(),
+    })
+}
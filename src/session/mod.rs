@@ -2,9 +2,12 @@ mod avatar;
 mod basic_group;
 mod basic_group_list;
 mod chat;
+mod chat_folder;
+mod chat_folder_list;
 mod chat_list;
 mod components;
 mod content;
+mod image_cache;
 mod secret_chat;
 mod secret_chat_list;
 mod sidebar;
@@ -17,8 +20,11 @@ use self::avatar::Avatar;
 use self::basic_group::BasicGroup;
 use self::basic_group_list::BasicGroupList;
 pub use self::chat::{Chat, ChatType};
+pub use self::chat_folder::{ChatFolder, ChatListType};
+use self::chat_folder_list::ChatFolderList;
 use self::chat_list::ChatList;
 use self::content::Content;
+use self::image_cache::ImageCache;
 use self::secret_chat::SecretChat;
 use self::secret_chat_list::SecretChatList;
 use self::sidebar::Sidebar;
@@ -31,14 +37,14 @@ use glib::{clone, SyncSender};
 use gtk::glib::WeakRef;
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
-use gtk::{glib, CompositeTemplate};
+use gtk::{gdk, glib, CompositeTemplate};
 use std::collections::hash_map::{Entry, HashMap};
 use tdgrand::enums::{NotificationSettingsScope, Update};
 use tdgrand::functions;
 use tdgrand::types::{File, ScopeNotificationSettings};
 
 use crate::session_manager::DatabaseInfo;
-use crate::utils::log_out;
+use crate::utils::{is_mute_active, log_out};
 use crate::RUNTIME;
 
 #[derive(Clone, Debug, glib::Boxed)]
@@ -62,6 +68,7 @@ mod imp {
         pub database_info: OnceCell<BoxedDatabaseInfo>,
         pub me: WeakRef<User>,
         pub chat_list: OnceCell<ChatList>,
+        pub chat_folder_list: OnceCell<ChatFolderList>,
         pub user_list: OnceCell<UserList>,
         pub basic_group_list: OnceCell<BasicGroupList>,
         pub supergroup_list: OnceCell<SupergroupList>,
@@ -70,11 +77,22 @@ mod imp {
         pub private_chats_notification_settings: RefCell<Option<BoxedScopeNotificationSettings>>,
         pub group_chats_notification_settings: RefCell<Option<BoxedScopeNotificationSettings>>,
         pub channel_chats_notification_settings: RefCell<Option<BoxedScopeNotificationSettings>>,
-        pub downloading_files: RefCell<HashMap<i32, Vec<SyncSender<File>>>>,
+        /// The unix times at which the notification settings of the scopes were received
+        pub private_chats_notification_settings_date: Cell<i64>,
+        pub group_chats_notification_settings_date: Cell<i64>,
+        pub channel_chats_notification_settings_date: Cell<i64>,
+        /// The senders of the download requests of the files, along with the ids of the requests
+        pub downloading_files: RefCell<HashMap<i32, Vec<(u32, SyncSender<File>)>>>,
+        pub last_download_id: Cell<u32>,
+        pub uploading_files: RefCell<HashMap<i32, Vec<SyncSender<File>>>>,
+        pub playing_media: WeakRef<gtk::MediaStream>,
+        pub image_cache: RefCell<ImageCache>,
         #[template_child]
         pub leaflet: TemplateChild<adw::Leaflet>,
         #[template_child]
         pub sidebar: TemplateChild<Sidebar>,
+        #[template_child]
+        pub content: TemplateChild<Content>,
     }
 
     #[glib::object_subclass]
@@ -137,6 +155,13 @@ mod imp {
                         ChatList::static_type(),
                         glib::ParamFlags::READABLE,
                     ),
+                    glib::ParamSpecObject::new(
+                        "chat-folder-list",
+                        "Chat Folder List",
+                        "The list of chat folders of this session",
+                        ChatFolderList::static_type(),
+                        glib::ParamFlags::READABLE,
+                    ),
                     glib::ParamSpecObject::new(
                         "user-list",
                         "User List",
@@ -241,6 +266,7 @@ mod imp {
                 "database-info" => obj.database_info().to_value(),
                 "me" => self.me.upgrade().to_value(),
                 "chat-list" => obj.chat_list().to_value(),
+                "chat-folder-list" => obj.chat_folder_list().to_value(),
                 "user-list" => obj.user_list().to_value(),
                 "basic-group-list" => obj.basic_group_list().to_value(),
                 "supergroup-list" => obj.supergroup_list().to_value(),
@@ -297,37 +323,48 @@ impl Session {
         match update {
             Update::NewMessage(_)
             | Update::MessageSendSucceeded(_)
+            | Update::MessageSendFailed(_)
             | Update::MessageContent(_)
+            | Update::MessageEdited(_)
             | Update::NewChat(_)
             | Update::ChatTitle(_)
             | Update::ChatPhoto(_)
             | Update::ChatLastMessage(_)
             | Update::ChatNotificationSettings(_)
             | Update::ChatPosition(_)
+            | Update::ChatIsMarkedAsUnread(_)
             | Update::ChatUnreadMentionCount(_)
             | Update::MessageMentionRead(_)
             | Update::ChatReadInbox(_)
+            | Update::ChatReadOutbox(_)
             | Update::ChatDraftMessage(_)
             | Update::DeleteMessages(_)
             | Update::ChatAction(_) => {
                 self.chat_list().handle_update(update);
             }
             Update::UnreadMessageCount(ref update_) => {
-                // TODO: Also handle archived chats
+                self.chat_folder_list().handle_update(&update);
+
                 if let tdgrand::enums::ChatList::Main = update_.chat_list {
                     self.chat_list().handle_update(update)
                 }
             }
+            Update::ChatFilters(_) => self.chat_folder_list().handle_update(&update),
             Update::ScopeNotificationSettings(update) => {
+                let imp = self.imp();
                 let settings = Some(BoxedScopeNotificationSettings(update.notification_settings));
+                let date = glib::real_time() / 1_000_000;
                 match update.scope {
                     NotificationSettingsScope::PrivateChats => {
+                        imp.private_chats_notification_settings_date.set(date);
                         self.set_private_chats_notification_settings(settings);
                     }
                     NotificationSettingsScope::GroupChats => {
+                        imp.group_chats_notification_settings_date.set(date);
                         self.set_group_chats_notification_settings(settings);
                     }
                     NotificationSettingsScope::ChannelChats => {
+                        imp.channel_chats_notification_settings_date.set(date);
                         self.set_channel_chats_notification_settings(settings);
                     }
                 }
@@ -345,14 +382,20 @@ impl Session {
         }
     }
 
-    pub fn download_file(&self, file_id: i32, sender: SyncSender<File>) {
-        let mut downloading_files = self.imp().downloading_files.borrow_mut();
+    /// Downloads the file, sending its updates to the sender until the download is completed.
+    /// Returns the id of the download request, which can be used to cancel it.
+    pub fn download_file(&self, file_id: i32, sender: SyncSender<File>) -> u32 {
+        let imp = self.imp();
+        let download_id = imp.last_download_id.get().wrapping_add(1);
+        imp.last_download_id.set(download_id);
+
+        let mut downloading_files = imp.downloading_files.borrow_mut();
         match downloading_files.entry(file_id) {
             Entry::Occupied(mut entry) => {
-                entry.get_mut().push(sender);
+                entry.get_mut().push((download_id, sender));
             }
             Entry::Vacant(entry) => {
-                entry.insert(vec![sender]);
+                entry.insert(vec![(download_id, sender)]);
 
                 let client_id = self.client_id();
                 RUNTIME.spawn(async move {
@@ -365,6 +408,86 @@ impl Session {
                 });
             }
         }
+
+        download_id
+    }
+
+    /// Cancels the download request with the specified id, so that its sender won't receive
+    /// any more updates. The download of the file is only cancelled if it isn't requested by
+    /// anyone else.
+    pub fn cancel_download_file(&self, file_id: i32, download_id: u32) {
+        let mut downloading_files = self.imp().downloading_files.borrow_mut();
+
+        let senders = match downloading_files.get_mut(&file_id) {
+            Some(senders) => senders,
+            None => return,
+        };
+
+        senders.retain(|(id, _)| *id != download_id);
+        if !senders.is_empty() {
+            return;
+        }
+
+        downloading_files.remove(&file_id);
+
+        let client_id = self.client_id();
+        RUNTIME.spawn(async move {
+            if let Err(e) = functions::CancelDownloadFile::new()
+                .file_id(file_id)
+                .only_if_pending(false)
+                .send(client_id)
+                .await
+            {
+                log::warn!("Failed to cancel the download of a file: {:?}", e);
+            }
+        });
+    }
+
+    /// Sends the updates of the specified file to the sender until its upload is completed.
+    pub fn watch_file_upload(&self, file_id: i32, sender: SyncSender<File>) {
+        self.imp()
+            .uploading_files
+            .borrow_mut()
+            .entry(file_id)
+            .or_default()
+            .push(sender);
+    }
+
+    /// Loads the image of a downloaded file, scaling it down to fit in a square of the given
+    /// size, if any. The images are decoded in a worker thread and the most recently used
+    /// ones are kept in memory.
+    pub async fn load_image(
+        &self,
+        file_id: i32,
+        path: &str,
+        size: Option<i32>,
+    ) -> Result<gdk::Texture, glib::Error> {
+        let key = (file_id, size);
+        if let Some(texture) = self.imp().image_cache.borrow_mut().get(&key) {
+            return Ok(texture);
+        }
+
+        let texture = image_cache::decode_image(path.to_owned(), size).await?;
+        self.imp()
+            .image_cache
+            .borrow_mut()
+            .insert(key, texture.clone());
+
+        Ok(texture)
+    }
+
+    /// Sets the media that has started playing, pausing the previous one, so that only one
+    /// media is played at a time in the whole session.
+    pub fn set_playing_media(&self, media: &gtk::MediaStream) {
+        let imp = self.imp();
+
+        if let Some(old_media) = imp.playing_media.upgrade() {
+            if &old_media != media {
+                old_media.pause();
+            }
+        }
+
+        imp.playing_media.set(Some(media));
     }
 
     pub fn begin_chats_search(&self) {
@@ -374,25 +497,22 @@ impl Session {
     }
 
     fn handle_file_update(&self, file: File) {
-        let mut downloading_files = self.imp().downloading_files.borrow_mut();
-        if let Entry::Occupied(mut entry) = downloading_files.entry(file.id) {
-            // Keep only the senders with which it was possible to send successfully.
-            // It is indeed possible that the object that created the sender and receiver and
-            // attached it to the default main context has been disposed in the meantime.
-            // This is problematic if it is now tried to upgrade a weak reference of this object in
-            // the receiver closure.
-            // It will either panic directly if `@default-panic` is used or the sender will return
-            // an error in the `SyncSender::send()` function if
-            // `default-return glib::Continue(false)` is used. In the latter case, the Receiver
-            // will be detached from the main context, which will cause the sending to fail.
-            entry
-                .get_mut()
-                .retain(|sender| sender.send(file.clone()).is_ok());
-
-            if file.local.is_downloading_completed || entry.get().is_empty() {
-                entry.remove();
-            }
-        }
+        let imp = self.imp();
+        let is_downloading_completed = file.local.is_downloading_completed;
+        let is_uploading_completed = file.remote.is_uploading_completed;
+
+        send_file_update(
+            &mut imp.downloading_files.borrow_mut(),
+            &file,
+            is_downloading_completed,
+            |(_, sender)| sender,
+        );
+        send_file_update(
+            &mut imp.uploading_files.borrow_mut(),
+            &file,
+            is_uploading_completed,
+            |sender| sender,
+        );
     }
 
     pub fn client_id(&self) -> i32 {
@@ -418,6 +538,10 @@ impl Session {
         self.imp().chat_list.get_or_init(|| ChatList::new(self))
     }
 
+    pub fn chat_folder_list(&self) -> &ChatFolderList {
+        self.imp().chat_folder_list.get_or_init(ChatFolderList::new)
+    }
+
     pub fn user_list(&self) -> &UserList {
         self.imp().user_list.get_or_init(|| UserList::new(self))
     }
@@ -436,11 +560,11 @@ impl Session {
             .get_or_init(|| SecretChatList::new(self))
     }
 
-    fn selected_chat(&self) -> Option<Chat> {
+    pub fn selected_chat(&self) -> Option<Chat> {
         self.imp().selected_chat.borrow().clone()
     }
 
-    fn set_selected_chat(&self, selected_chat: Option<Chat>) {
+    pub fn set_selected_chat(&self, selected_chat: Option<Chat>) {
         if self.selected_chat() == selected_chat {
             return;
         }
@@ -450,10 +574,85 @@ impl Session {
             imp.leaflet.navigate(adw::NavigationDirection::Forward);
         }
 
+        // Let TDLib know which chat is shown, so that it can mark its messages as read
+        let client_id = self.client_id();
+        let closed_chat_id = self.selected_chat().map(|chat| chat.id());
+        let opened_chat_id = selected_chat.as_ref().map(|chat| chat.id());
+        RUNTIME.spawn(async move {
+            if let Some(chat_id) = closed_chat_id {
+                if let Err(e) = functions::CloseChat::new()
+                    .chat_id(chat_id)
+                    .send(client_id)
+                    .await
+                {
+                    log::warn!("Failed to close a chat: {:?}", e);
+                }
+            }
+
+            if let Some(chat_id) = opened_chat_id {
+                if let Err(e) = functions::OpenChat::new()
+                    .chat_id(chat_id)
+                    .send(client_id)
+                    .await
+                {
+                    log::warn!("Failed to open a chat: {:?}", e);
+                }
+            }
+        });
+
         imp.selected_chat.replace(selected_chat);
         self.notify("selected-chat");
     }
 
+    /// Selects the chat, showing its history scrolled to the message.
+    pub fn select_chat_message(&self, chat: Chat, message_id: i64) {
+        let imp = self.imp();
+
+        if self.selected_chat().as_ref() == Some(&chat) {
+            imp.leaflet.navigate(adw::NavigationDirection::Forward);
+            imp.content.jump_to_message(message_id);
+        } else {
+            imp.content.set_initial_message_id(message_id);
+            self.set_selected_chat(Some(chat));
+        }
+    }
+
+    /// Returns the default notification settings of the chats of the type, if they're known.
+    pub fn scope_notification_settings(
+        &self,
+        chat_type: &ChatType,
+    ) -> Option<BoxedScopeNotificationSettings> {
+        self.scope_notification_settings_and_date(chat_type).0
+    }
+
+    /// Returns whether the chats of the type are muted by default, if the notification settings of
+    /// their scope are known.
+    pub fn is_scope_muted(&self, chat_type: &ChatType) -> Option<bool> {
+        let (settings, date) = self.scope_notification_settings_and_date(chat_type);
+        settings.map(|settings| is_mute_active(settings.0.mute_for, date))
+    }
+
+    fn scope_notification_settings_and_date(
+        &self,
+        chat_type: &ChatType,
+    ) -> (Option<BoxedScopeNotificationSettings>, i64) {
+        let imp = self.imp();
+        match chat_type {
+            ChatType::Private(_) | ChatType::Secret(_) => (
+                self.private_chats_notification_settings(),
+                imp.private_chats_notification_settings_date.get(),
+            ),
+            ChatType::Supergroup(supergroup) if supergroup.is_channel() => (
+                self.channel_chats_notification_settings(),
+                imp.channel_chats_notification_settings_date.get(),
+            ),
+            ChatType::BasicGroup(_) | ChatType::Supergroup(_) => (
+                self.group_chats_notification_settings(),
+                imp.group_chats_notification_settings_date.get(),
+            ),
+        }
+    }
+
     fn private_chats_notification_settings(&self) -> Option<BoxedScopeNotificationSettings> {
         self.imp()
             .private_chats_notification_settings
@@ -516,10 +715,39 @@ impl Session {
 
     pub fn fetch_chats(&self) {
         let client_id = self.imp().client_id.get();
-        self.chat_list().fetch(client_id);
+        self.chat_list().fetch(client_id, ChatListType::Main);
+        self.chat_list().fetch(client_id, ChatListType::Archive);
     }
 
     pub fn set_sessions(&self, sessions: &gtk::SelectionModel) {
         self.imp().sidebar.set_sessions(sessions, self);
     }
 }
+
+/// Sends the file update to the senders registered for the file, and removes them once the
+/// operation is completed.
+fn send_file_update<T>(
+    files: &mut HashMap<i32, Vec<T>>,
+    file: &File,
+    is_completed: bool,
+    sender: impl Fn(&T) -> &SyncSender<File>,
+) {
+    if let Entry::Occupied(mut entry) = files.entry(file.id) {
+        // Keep only the senders with which it was possible to send successfully.
+        // It is indeed possible that the object that created the sender and receiver and
+        // attached it to the default main context has been disposed in the meantime.
+        // This is problematic if it is now tried to upgrade a weak reference of this object in
+        // the receiver closure.
+        // It will either panic directly if `@default-panic` is used or the sender will return
+        // an error in the `SyncSender::send()` function if
+        // `default-return glib::Continue(false)` is used. In the latter case, the Receiver
+        // will be detached from the main context, which will cause the sending to fail.
+        entry
+            .get_mut()
+            .retain(|item| sender(item).send(file.clone()).is_ok());
+
+        if is_completed || entry.get().is_empty() {
+            entry.remove();
+        }
+    }
+}
@@ -6,6 +6,7 @@ use gtk::subclass::prelude::*;
 use gtk::{glib, gsk, CompositeTemplate};
 use tdgrand::enums::{UserStatus, UserType};
 
+use crate::session::chat::Message;
 use crate::session::user::BoxedUserStatus;
 use crate::session::{Chat, Session, User};
 
@@ -19,7 +20,7 @@ mod imp {
     #[derive(Debug, Default, CompositeTemplate)]
     #[template(resource = "/com/github/melix99/telegrand/ui/sidebar-avatar.ui")]
     pub struct Avatar {
-        /// A `Chat` or `User`
+        /// A `Chat`, `User`, `Message` or the `gtk::StringObject` title of a section
         pub item: RefCell<Option<glib::Object>>,
         pub binding: RefCell<Option<gtk::ExpressionWatch>>,
         pub is_online: Cell<bool>,
@@ -216,6 +217,13 @@ impl Avatar {
                 imp.avatar.set_item(Some(user.avatar().to_owned()));
 
                 self.setup_is_online_binding(user);
+            } else if let Some(message) = item.downcast_ref::<Message>() {
+                imp.avatar
+                    .set_item(Some(message.chat().avatar().to_owned()));
+                self.set_is_online(false);
+            } else if item.is::<gtk::StringObject>() {
+                // Section titles have no avatar
+                self.set_is_online(false);
             } else {
                 unreachable!("Unexpected item type: {:?}", item);
             }
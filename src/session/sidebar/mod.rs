@@ -5,18 +5,26 @@ mod session_switcher;
 use self::row::Row;
 use self::session_switcher::SessionSwitcher;
 
+use gettextrs::gettext;
 use glib::clone;
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
 use gtk::{gio, glib, CompositeTemplate};
+use std::time::Duration;
 use tdgrand::{enums, functions};
 
-use crate::session::{Chat, ChatType, User};
+use crate::session::chat::Message;
+use crate::session::{Chat, ChatFolder, ChatListType, ChatType, User};
 use crate::utils::do_async;
-use crate::Session;
+use crate::{spawn, Session};
 
 pub use self::avatar::Avatar;
 
+/// The time to wait for the user to stop typing before searching the messages
+const MESSAGES_SEARCH_DELAY: Duration = Duration::from_millis(300);
+/// The number of found messages requested at once
+const MESSAGES_SEARCH_LIMIT: i32 = 30;
+
 mod imp {
     use super::*;
     use once_cell::sync::Lazy;
@@ -29,12 +37,21 @@ mod imp {
     pub struct Sidebar {
         pub compact: Cell<bool>,
         pub selected_chat: RefCell<Option<Chat>>,
+        pub chat_list: Cell<ChatListType>,
         pub session: RefCell<Option<Session>>,
         pub filter: RefCell<Option<gtk::CustomFilter>>,
+        pub sorter: RefCell<Option<gtk::CustomSorter>>,
         pub selection: RefCell<Option<gtk::SingleSelection>>,
         pub searched_chats: RefCell<Vec<i64>>,
         pub searched_users: RefCell<Vec<i64>>,
         pub already_searched_users: RefCell<Vec<i64>>,
+        /// The messages found by the search, preceded by the title of their section
+        pub found_messages: RefCell<Option<gio::ListStore>>,
+        pub messages_search_source_id: RefCell<Option<glib::SourceId>>,
+        /// Increased at every new search, used to ignore the results of the previous ones
+        pub messages_search_id: Cell<u32>,
+        pub is_searching_messages: Cell<bool>,
+        pub has_more_found_messages: Cell<bool>,
         #[template_child]
         pub header_bar: TemplateChild<adw::HeaderBar>,
         #[template_child]
@@ -44,6 +61,10 @@ mod imp {
         #[template_child]
         pub search_entry: TemplateChild<gtk::SearchEntry>,
         #[template_child]
+        pub folders_scrolled_window: TemplateChild<gtk::ScrolledWindow>,
+        #[template_child]
+        pub folder_list_view: TemplateChild<gtk::ListView>,
+        #[template_child]
         pub scrolled_window: TemplateChild<gtk::ScrolledWindow>,
         #[template_child]
         pub list_view: TemplateChild<gtk::ListView>,
@@ -57,7 +78,6 @@ mod imp {
 
         fn class_init(klass: &mut Self::Class) {
             ComponentsAvatar::static_type();
-            Row::static_type();
             Self::bind_template(klass);
         }
 
@@ -91,6 +111,13 @@ mod imp {
                         Session::static_type(),
                         glib::ParamFlags::READWRITE | glib::ParamFlags::EXPLICIT_NOTIFY,
                     ),
+                    glib::ParamSpecBoxed::new(
+                        "chat-list",
+                        "Chat List",
+                        "The chat list shown in this sidebar",
+                        ChatListType::static_type(),
+                        glib::ParamFlags::READABLE,
+                    ),
                 ]
             });
             PROPERTIES.as_ref()
@@ -125,21 +152,47 @@ mod imp {
                 "compact" => self.compact.get().to_value(),
                 "selected-chat" => obj.selected_chat().to_value(),
                 "session" => obj.session().to_value(),
+                "chat-list" => obj.chat_list().to_value(),
                 _ => unimplemented!(),
             }
         }
 
         fn constructed(&self, obj: &Self::Type) {
+            // Pass the shown chat list to the rows, which depend on it for their pin icon and
+            // their menu
+            let factory = gtk::SignalListItemFactory::new();
+            factory.connect_setup(clone!(@weak obj => move |_, list_item| {
+                let row = Row::new();
+                row.set_margin_top(6);
+                row.set_margin_bottom(6);
+                list_item
+                    .property_expression("item")
+                    .bind(&row, "item", glib::Object::NONE);
+                obj.bind_property("chat-list", &row, "chat-list")
+                    .flags(glib::BindingFlags::SYNC_CREATE)
+                    .build();
+                list_item.set_child(Some(&row));
+            }));
+            self.list_view.set_factory(Some(&factory));
+
             self.search_entry
                 .connect_search_changed(clone!(@weak obj => move |entry| {
                     let query = entry.text().to_string();
                     obj.search(query);
                 }));
+
+            self.scrolled_window
+                .connect_edge_reached(clone!(@weak obj => move |_, position| {
+                    if position == gtk::PositionType::Bottom {
+                        obj.search_messages();
+                    }
+                }));
         }
 
         fn dispose(&self, _obj: &Self::Type) {
             self.header_bar.unparent();
             self.search_bar.unparent();
+            self.folders_scrolled_window.unparent();
             self.scrolled_window.unparent();
         }
     }
@@ -175,51 +228,49 @@ impl Sidebar {
         imp.searched_users.borrow_mut().clear();
         imp.already_searched_users.borrow_mut().clear();
 
+        if let Some(source_id) = imp.messages_search_source_id.take() {
+            source_id.remove();
+        }
+        imp.messages_search_id
+            .set(imp.messages_search_id.get().wrapping_add(1));
+        imp.is_searching_messages.set(false);
+        imp.has_more_found_messages.set(false);
+        if let Some(found_messages) = imp.found_messages.borrow().as_ref() {
+            found_messages.remove_all();
+        }
+
+        // The search is done in all the chat lists
+        imp.folders_scrolled_window.set_visible(query.is_empty());
+
         if query.is_empty() {
             if let Some(filter) = imp.filter.borrow().as_ref() {
                 filter.changed(gtk::FilterChange::Different);
             }
         } else {
-            let client_id = self
+            let session = self
                 .session()
-                .expect("The session needs to be set to be able to search")
-                .client_id();
+                .expect("The session needs to be set to be able to search");
+            let client_id = session.client_id();
 
             // Search chats
             let query_clone = query.clone();
-            do_async(
-                glib::PRIORITY_DEFAULT_IDLE,
-                async move {
-                    functions::SearchChats::new()
-                        .query(query_clone)
-                        .limit(100)
-                        .send(client_id)
-                        .await
-                },
-                clone!(@weak self as obj => move |result| async move {
-                    if let Ok(enums::Chats::Chats(chats)) = result {
-                        let imp = obj.imp();
+            spawn!(clone!(@weak self as obj, @weak session => async move {
+                if let Ok(chats) = session.chat_list().search(query_clone).await {
+                    let imp = obj.imp();
 
-                        if let Some(filter) = imp.filter.borrow().as_ref() {
-                            let session = obj
-                                .session()
-                                .expect("The session needs to be set to be able to search");
-                            let chat_list = session.chat_list();
-
-                            imp.already_searched_users.borrow_mut().extend(chats.chat_ids.iter()
-                                .map(|id| chat_list.get(*id))
-                                .filter_map(|chat| match chat.type_() {
-                                    ChatType::Private(user) => Some(user.id()),
-                                    _ => None
-                                }
-                            ));
-
-                            imp.searched_chats.borrow_mut().extend(chats.chat_ids);
-                            filter.changed(gtk::FilterChange::Different);
-                        }
+                    if let Some(filter) = imp.filter.borrow().as_ref() {
+                        imp.already_searched_users.borrow_mut().extend(chats.iter()
+                            .filter_map(|chat| match chat.type_() {
+                                ChatType::Private(user) => Some(user.id()),
+                                _ => None
+                            }
+                        ));
+
+                        imp.searched_chats.borrow_mut().extend(chats.iter().map(|chat| chat.id()));
+                        filter.changed(gtk::FilterChange::Different);
                     }
-                }),
-            );
+                }
+            }));
 
             // Search contacts
             do_async(
@@ -242,7 +293,103 @@ impl Sidebar {
                     }
                 }),
             );
+
+            // Search messages, but only once the user has stopped typing
+            let source_id = glib::timeout_add_local_once(
+                MESSAGES_SEARCH_DELAY,
+                clone!(@weak self as obj => move || {
+                    obj.imp().messages_search_source_id.take();
+                    obj.search_messages();
+                }),
+            );
+            imp.messages_search_source_id.replace(Some(source_id));
+        }
+    }
+
+    /// Loads the next page of messages matching the current query, if any.
+    fn search_messages(&self) {
+        let imp = self.imp();
+        let query = imp.search_entry.text().to_string();
+        let found_messages = match imp.found_messages.borrow().as_ref() {
+            Some(found_messages) => found_messages.clone(),
+            None => return,
+        };
+
+        // Continue from the last found message, if any
+        let last_message = found_messages
+            .item(found_messages.n_items().saturating_sub(1))
+            .and_then(|item| item.downcast::<Message>().ok());
+        let is_first_page = last_message.is_none();
+
+        if query.is_empty()
+            || imp.is_searching_messages.get()
+            || imp.messages_search_source_id.borrow().is_some()
+            || (!is_first_page && !imp.has_more_found_messages.get())
+        {
+            return;
         }
+
+        let session = self
+            .session()
+            .expect("The session needs to be set to be able to search");
+        let client_id = session.client_id();
+        let (offset_date, offset_chat_id, offset_message_id) = last_message
+            .map(|message| (message.date(), message.chat().id(), message.id()))
+            .unwrap_or_default();
+        let search_id = imp.messages_search_id.get();
+
+        imp.is_searching_messages.set(true);
+
+        spawn!(
+            clone!(@weak self as obj, @weak session, @weak found_messages => async move {
+                let result = functions::SearchMessages::new()
+                    .query(query)
+                    .offset_date(offset_date)
+                    .offset_chat_id(offset_chat_id)
+                    .offset_message_id(offset_message_id)
+                    .limit(MESSAGES_SEARCH_LIMIT)
+                    .send(client_id)
+                    .await;
+
+                let imp = obj.imp();
+
+                // Ignore the results of the previous searches
+                if imp.messages_search_id.get() != search_id {
+                    return;
+                }
+                imp.is_searching_messages.set(false);
+
+                match result {
+                    Ok(enums::Messages::Messages(result)) => {
+                        let mut items: Vec<glib::Object> = result
+                            .messages
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(|message| {
+                                let chat = session.chat_list().get(message.chat_id);
+                                Message::new(message, &chat).upcast()
+                            })
+                            .collect();
+
+                        if items.is_empty() {
+                            imp.has_more_found_messages.set(false);
+                            return;
+                        }
+
+                        if is_first_page {
+                            items.insert(0, gtk::StringObject::new(&gettext("Messages")).upcast());
+                        }
+
+                        found_messages.splice(found_messages.n_items(), 0, &items);
+
+                        // Don't count the section title
+                        let found_count = found_messages.n_items() as i32 - 1;
+                        imp.has_more_found_messages.set(found_count < result.total_count);
+                    }
+                    Err(e) => log::warn!("Failed to search messages: {:?}", e),
+                }
+            })
+        );
     }
 
     fn selected_chat(&self) -> Option<Chat> {
@@ -282,6 +429,8 @@ impl Sidebar {
             let list = gio::ListStore::new(gio::ListModel::static_type());
             list.append(session.chat_list());
             list.append(session.user_list());
+            let found_messages = gio::ListStore::new(glib::Object::static_type());
+            list.append(&found_messages);
             let model = gtk::FlattenListModel::new(Some(&list));
 
             let filter = gtk::CustomFilter::new(
@@ -298,32 +447,41 @@ impl Sidebar {
                             !imp.already_searched_users.borrow().contains(&user.id())
                                 && imp.searched_users.borrow().contains(&user.id())
                         } else {
-                            false
+                            // Found messages and their section title
+                            true
                         }
                     } else if let Some(chat) = item.downcast_ref::<Chat>() {
-                        chat.order() > 0
+                        chat.order(imp.chat_list.get()) > 0
                     } else {
                         false
                     }
                 }),
             );
-            let sorter = gtk::CustomSorter::new(move |obj1, obj2| {
-                let chat1 = obj1.downcast_ref::<Chat>();
-                let chat2 = obj2.downcast_ref::<Chat>();
-
-                // Always show chats first and then users
-                if let Some(chat1) = chat1 {
-                    if let Some(chat2) = chat2 {
-                        chat2.order().cmp(&chat1.order()).into()
-                    } else {
-                        gtk::Ordering::Smaller
+            let sorter = gtk::CustomSorter::new(
+                clone!(@weak self as obj => @default-return gtk::Ordering::Equal,
+                    move |item1, item2| {
+                        // Always show chats first, then users and then the found messages,
+                        // which are already sorted
+                        let rank = |item: &glib::Object| {
+                            if item.is::<Chat>() {
+                                0
+                            } else if item.is::<User>() {
+                                1
+                            } else {
+                                2
+                            }
+                        };
+
+                        match (item1.downcast_ref::<Chat>(), item2.downcast_ref::<Chat>()) {
+                            (Some(chat1), Some(chat2)) => {
+                                let chat_list = obj.imp().chat_list.get();
+                                chat2.order(chat_list).cmp(&chat1.order(chat_list)).into()
+                            }
+                            _ => rank(item1).cmp(&rank(item2)).into(),
+                        }
                     }
-                } else if chat2.is_some() {
-                    gtk::Ordering::Larger
-                } else {
-                    gtk::Ordering::Equal
-                }
-            });
+                ),
+            );
 
             session.chat_list().connect_positions_changed(
                 clone!(@weak filter, @weak sorter => move |_| {
@@ -342,6 +500,10 @@ impl Sidebar {
                     if let Some(item) = selection.selected_item() {
                         if let Some(chat) = item.downcast_ref::<Chat>() {
                             obj.set_selected_chat(Some(chat.to_owned()));
+                        } else if let Some(message) = item.downcast_ref::<Message>() {
+                            session.select_chat_message(message.chat(), message.id());
+                        } else if item.is::<gtk::StringObject>() {
+                            // The title of the found messages section can't be opened
                         } else if let Some(user) = item.downcast_ref::<User>() {
                             // Create a chat with the user and then select the created chat
                             let user_id = user.id();
@@ -371,7 +533,22 @@ impl Sidebar {
             );
 
             imp.list_view.set_model(Some(&selection));
+
+            let folder_selection = gtk::SingleSelection::new(Some(session.chat_folder_list()));
+            folder_selection.connect_selected_item_notify(
+                clone!(@weak self as obj => move |selection| {
+                    if let Some(folder) = selection
+                        .selected_item()
+                        .and_then(|item| item.downcast::<ChatFolder>().ok())
+                    {
+                        obj.set_chat_list(folder.chat_list());
+                    }
+                }),
+            );
+            imp.folder_list_view.set_model(Some(&folder_selection));
             imp.filter.replace(Some(filter));
+            imp.sorter.replace(Some(sorter));
+            imp.found_messages.replace(Some(found_messages));
             imp.selection.replace(Some(selection));
         }
 
@@ -379,6 +556,33 @@ impl Sidebar {
         self.notify("session");
     }
 
+    pub fn chat_list(&self) -> ChatListType {
+        self.imp().chat_list.get()
+    }
+
+    fn set_chat_list(&self, chat_list: ChatListType) {
+        if self.chat_list() == chat_list {
+            return;
+        }
+
+        let imp = self.imp();
+
+        if let Some(session) = self.session() {
+            session.chat_list().fetch(session.client_id(), chat_list);
+        }
+
+        imp.chat_list.set(chat_list);
+
+        if let Some(filter) = imp.filter.borrow().as_ref() {
+            filter.changed(gtk::FilterChange::Different);
+        }
+        if let Some(sorter) = imp.sorter.borrow().as_ref() {
+            sorter.changed(gtk::SorterChange::Different);
+        }
+
+        self.notify("chat-list");
+    }
+
     pub fn session(&self) -> Option<Session> {
         self.imp().session.borrow().to_owned()
     }
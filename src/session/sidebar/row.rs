@@ -1,31 +1,39 @@
 use gettextrs::gettext;
-use glib::closure;
+use glib::{clone, closure};
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
-use gtk::{glib, CompositeTemplate};
+use gtk::{gdk, gio, glib, CompositeTemplate};
 use std::borrow::Cow;
 use tdgrand::enums::{CallDiscardReason, InputMessageContent, MessageContent};
+use tdgrand::functions;
 use tdgrand::types::{DraftMessage, MessageCall};
 
 use crate::session::chat::{
-    BoxedChatNotificationSettings, BoxedDraftMessage, BoxedMessageContent, ChatAction,
-    ChatActionList, Message, MessageSender,
+    BoxedChatNotificationSettings, BoxedChatPositions, BoxedDraftMessage, BoxedMessageContent,
+    ChatAction, ChatActionList, Message, MessageSender,
 };
 use crate::session::sidebar::Avatar;
-use crate::session::{BoxedScopeNotificationSettings, Chat, ChatType, Session, User};
-use crate::utils::{dim_and_escape, escape, human_friendly_duration, MESSAGE_TRUNCATED_LENGTH};
+use crate::session::{BoxedScopeNotificationSettings, Chat, ChatListType, ChatType, Session, User};
+use crate::utils::{
+    dim_and_escape, escape, human_friendly_duration, MESSAGE_TRUNCATED_LENGTH,
+    MUTE_FOREVER_DURATION,
+};
+use crate::RUNTIME;
 
 mod imp {
     use super::*;
-    use once_cell::sync::Lazy;
-    use std::cell::RefCell;
+    use once_cell::sync::{Lazy, OnceCell};
+    use std::cell::{Cell, RefCell};
 
     #[derive(Debug, Default, CompositeTemplate)]
     #[template(resource = "/com/github/melix99/telegrand/ui/sidebar-row.ui")]
     pub struct Row {
-        /// A `Chat` or `User`
+        /// A `Chat`, `User`, `Message` or the `gtk::StringObject` title of a section
         pub item: RefCell<Option<glib::Object>>,
+        /// The chat list shown by the sidebar that contains this row
+        pub chat_list: Cell<ChatListType>,
         pub bindings: RefCell<Vec<gtk::ExpressionWatch>>,
+        pub menu: OnceCell<gtk::PopoverMenu>,
         #[template_child]
         pub avatar: TemplateChild<Avatar>,
         #[template_child]
@@ -54,6 +62,42 @@ mod imp {
 
         fn class_init(klass: &mut Self::Class) {
             Self::bind_template(klass);
+
+            klass.install_action("sidebar-row.pin", None, move |widget, _, _| {
+                widget.toggle_is_pinned(true);
+            });
+            klass.install_action("sidebar-row.unpin", None, move |widget, _, _| {
+                widget.toggle_is_pinned(false);
+            });
+            klass.install_action("sidebar-row.mute", Some("i"), move |widget, _, variant| {
+                let mute_for = variant.and_then(|v| v.get()).unwrap();
+                widget.set_mute_for(mute_for);
+            });
+            klass.install_action("sidebar-row.unmute", None, move |widget, _, _| {
+                widget.set_mute_for(0);
+            });
+            klass.install_action("sidebar-row.mark-as-read", None, move |widget, _, _| {
+                if let Some(chat) = widget.chat() {
+                    chat.mark_as_read();
+                }
+            });
+            klass.install_action("sidebar-row.mark-as-unread", None, move |widget, _, _| {
+                if let Some(chat) = widget.chat() {
+                    chat.mark_as_unread();
+                }
+            });
+            klass.install_action("sidebar-row.archive", None, move |widget, _, _| {
+                widget.move_to_chat_list(ChatListType::Archive);
+            });
+            klass.install_action("sidebar-row.unarchive", None, move |widget, _, _| {
+                widget.move_to_chat_list(ChatListType::Main);
+            });
+            klass.install_action("sidebar-row.delete-chat", None, move |widget, _, _| {
+                widget.show_delete_dialog();
+            });
+            klass.install_action("sidebar-row.leave-chat", None, move |widget, _, _| {
+                widget.show_delete_dialog();
+            });
         }
 
         fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
@@ -64,13 +108,22 @@ mod imp {
     impl ObjectImpl for Row {
         fn properties() -> &'static [glib::ParamSpec] {
             static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
-                vec![glib::ParamSpecObject::new(
-                    "item",
-                    "Item",
-                    "The item of this row",
-                    glib::Object::static_type(),
-                    glib::ParamFlags::READWRITE | glib::ParamFlags::EXPLICIT_NOTIFY,
-                )]
+                vec![
+                    glib::ParamSpecObject::new(
+                        "item",
+                        "Item",
+                        "The item of this row",
+                        glib::Object::static_type(),
+                        glib::ParamFlags::READWRITE | glib::ParamFlags::EXPLICIT_NOTIFY,
+                    ),
+                    glib::ParamSpecBoxed::new(
+                        "chat-list",
+                        "Chat List",
+                        "The chat list shown by the sidebar that contains this row",
+                        ChatListType::static_type(),
+                        glib::ParamFlags::READWRITE | glib::ParamFlags::EXPLICIT_NOTIFY,
+                    ),
+                ]
             });
             PROPERTIES.as_ref()
         }
@@ -84,6 +137,7 @@ mod imp {
         ) {
             match pspec.name() {
                 "item" => obj.set_item(value.get().unwrap()),
+                "chat-list" => obj.set_chat_list(value.get().unwrap()),
                 _ => unimplemented!(),
             }
         }
@@ -91,17 +145,98 @@ mod imp {
         fn property(&self, obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
             match pspec.name() {
                 "item" => obj.item().to_value(),
+                "chat-list" => obj.chat_list().to_value(),
                 _ => unimplemented!(),
             }
         }
 
+        fn constructed(&self, obj: &Self::Type) {
+            self.parent_constructed(obj);
+
+            let mute_menu_model = gio::Menu::new();
+            for (label, mute_for) in [
+                (gettext("For 1 _Hour"), 60 * 60),
+                (gettext("For _8 Hours"), 8 * 60 * 60),
+                (gettext("For 2 _Days"), 2 * 24 * 60 * 60),
+                (gettext("_Forever"), MUTE_FOREVER_DURATION),
+            ] {
+                let item = gio::MenuItem::new(Some(&label), None);
+                item.set_action_and_target_value(
+                    Some("sidebar-row.mute"),
+                    Some(&mute_for.to_variant()),
+                );
+                mute_menu_model.append_item(&item);
+            }
+
+            let menu_model = gio::Menu::new();
+            for (label, action) in [
+                (gettext("_Pin"), "sidebar-row.pin"),
+                (gettext("Un_pin"), "sidebar-row.unpin"),
+                (gettext("_Unmute"), "sidebar-row.unmute"),
+                (gettext("Mark as _Read"), "sidebar-row.mark-as-read"),
+                (gettext("Mark as _Unread"), "sidebar-row.mark-as-unread"),
+                (gettext("_Archive"), "sidebar-row.archive"),
+                (gettext("Un_archive"), "sidebar-row.unarchive"),
+            ] {
+                let item = gio::MenuItem::new(Some(&label), Some(action));
+                item.set_attribute_value("hidden-when", Some(&"action-disabled".to_variant()));
+                menu_model.append_item(&item);
+            }
+            menu_model.insert_submenu(2, Some(&gettext("_Mute")), &mute_menu_model);
+
+            let delete_section = gio::Menu::new();
+            for (label, action) in [
+                (gettext("_Delete Chat"), "sidebar-row.delete-chat"),
+                (gettext("_Leave"), "sidebar-row.leave-chat"),
+            ] {
+                let item = gio::MenuItem::new(Some(&label), Some(action));
+                item.set_attribute_value("hidden-when", Some(&"action-disabled".to_variant()));
+                delete_section.append_item(&item);
+            }
+            menu_model.append_section(None, &delete_section);
+
+            let menu = gtk::PopoverMenu::from_model(Some(&menu_model));
+            menu.set_parent(obj);
+            menu.set_has_arrow(false);
+            self.menu.set(menu).unwrap();
+
+            // Show the context menu on right click and on long press
+            let click_gesture = gtk::GestureClick::new();
+            click_gesture.set_button(gdk::BUTTON_SECONDARY);
+            click_gesture.connect_pressed(clone!(@weak obj => move |gesture, _, x, y| {
+                gesture.set_state(gtk::EventSequenceState::Claimed);
+                obj.popup_menu(x, y);
+            }));
+            obj.add_controller(&click_gesture);
+
+            let long_press_gesture = gtk::GestureLongPress::new();
+            long_press_gesture.set_touch_only(true);
+            long_press_gesture.connect_pressed(clone!(@weak obj => move |gesture, x, y| {
+                gesture.set_state(gtk::EventSequenceState::Claimed);
+                obj.popup_menu(x, y);
+            }));
+            obj.add_controller(&long_press_gesture);
+        }
+
         fn dispose(&self, _obj: &Self::Type) {
+            if let Some(menu) = self.menu.get() {
+                menu.unparent();
+            }
+
             self.avatar.unparent();
             self.main_box.unparent();
         }
     }
 
-    impl WidgetImpl for Row {}
+    impl WidgetImpl for Row {
+        fn size_allocate(&self, widget: &Self::Type, width: i32, height: i32, baseline: i32) {
+            self.parent_size_allocate(widget, width, height, baseline);
+
+            if let Some(menu) = self.menu.get() {
+                menu.present();
+            }
+        }
+    }
 }
 
 glib::wrapper! {
@@ -136,6 +271,9 @@ impl Row {
             binding.unwatch();
         }
 
+        self.remove_css_class("section-title");
+        imp.avatar.set_visible(true);
+
         if let Some(ref item) = item {
             if let Some(chat) = item.downcast_ref::<Chat>() {
                 imp.timestamp_label.set_visible(true);
@@ -146,7 +284,7 @@ impl Row {
                 let actions_expression = Chat::this_expression("actions");
                 let unread_mention_count_expression = Chat::this_expression("unread-mention-count");
                 let unread_count_expression = Chat::this_expression("unread-count");
-                let is_pinned_expression = Chat::this_expression("is-pinned");
+                let positions_expression = Chat::this_expression("positions");
                 let notification_settings_expression =
                     Chat::this_expression("notification-settings");
                 let session_expression = Chat::this_expression("session");
@@ -178,30 +316,8 @@ impl Row {
                         })
                     }),
                 )
-                .chain_closure::<glib::GString>(closure!(|_: Chat, date: i32| {
-                    let datetime_now = glib::DateTime::now_local().unwrap();
-                    let datetime = glib::DateTime::from_unix_utc(date as i64)
-                        .and_then(|t| t.to_local())
-                        .unwrap();
-
-                    let difference = datetime_now.difference(&datetime);
-                    let hours_difference = difference.as_hours();
-                    let days_difference = difference.as_days();
-
-                    if hours_difference <= 16 {
-                        // Show the time
-                        // Translators: This is a time format for the chat list without seconds
-                        datetime.format(&gettext("%l:%M %p")).unwrap()
-                    } else if days_difference < 6 {
-                        // Show the day of the week
-                        datetime.format("%a").unwrap()
-                    } else if days_difference < 364 {
-                        // Show the day and the month
-                        datetime.format("%d %b").unwrap()
-                    } else {
-                        // Show the entire date
-                        datetime.format("%x").unwrap()
-                    }
+                .chain_closure::<String>(closure!(|_: Chat, date: i32| {
+                    stringify_timestamp(date)
                 }))
                 .bind(&*imp.timestamp_label, "label", Some(chat));
                 bindings.push(timestamp_binding);
@@ -265,8 +381,16 @@ impl Row {
                 bindings.push(unread_mention_binding);
 
                 // Unread count binding
-                let unread_binding =
-                    unread_count_expression.bind(&*imp.unread_count_label, "label", Some(chat));
+                let unread_binding = unread_count_expression
+                    .chain_closure::<String>(closure!(|_: Chat, unread_count: i32| {
+                        // Chats marked as unread without unread messages show an empty badge
+                        if unread_count > 0 {
+                            unread_count.to_string()
+                        } else {
+                            String::new()
+                        }
+                    }))
+                    .bind(&*imp.unread_count_label, "label", Some(chat));
                 bindings.push(unread_binding);
 
                 // Unread count visibility binding
@@ -274,9 +398,14 @@ impl Row {
                     &[
                         unread_count_expression.clone().upcast(),
                         unread_mention_count_expression.upcast(),
+                        Chat::this_expression("is-marked-as-unread").upcast(),
                     ],
-                    closure!(|_: Chat, unread_count: i32, unread_mention_count: i32| {
-                        unread_count > 0 && (unread_mention_count != 1 || unread_count > 1)
+                    closure!(|_: Chat,
+                              unread_count: i32,
+                              unread_mention_count: i32,
+                              is_marked_as_unread: bool| {
+                        (unread_count > 0 && (unread_mention_count != 1 || unread_count > 1))
+                            || (unread_count == 0 && is_marked_as_unread)
                     }),
                 )
                 .bind(&*imp.unread_count_label, "visible", Some(chat));
@@ -330,14 +459,25 @@ impl Row {
                 .bind(&*imp.unread_count_label, "css-classes", Some(chat));
                 bindings.push(unread_binding);
 
-                // Pin icon visibility binding
+                // Pin icon visibility binding, which depends on the chat list shown in the
+                // sidebar
+                let chat_list_expression =
+                    gtk::ObjectExpression::new(self).chain_property::<Row>("chat-list");
                 let pin_binding = gtk::ClosureExpression::new::<bool, _, _>(
                     &[
-                        is_pinned_expression.upcast(),
+                        positions_expression.upcast(),
+                        chat_list_expression.upcast(),
                         unread_count_expression.upcast(),
                     ],
-                    closure!(|_: Chat, is_pinned: bool, unread_count: i32| {
-                        is_pinned && unread_count <= 0
+                    closure!(|_: Chat,
+                              positions: BoxedChatPositions,
+                              chat_list: ChatListType,
+                              unread_count: i32| {
+                        positions
+                            .0
+                            .get(&chat_list)
+                            .map_or(false, |position| position.is_pinned)
+                            && unread_count <= 0
                     }),
                 )
                 .bind(&*imp.pin_icon, "visible", Some(chat));
@@ -354,6 +494,31 @@ impl Row {
                     glib::Object::NONE,
                 );
                 bindings.push(title_binding);
+            } else if let Some(message) = item.downcast_ref::<Message>() {
+                imp.timestamp_label.set_visible(true);
+                imp.bottom_box.set_visible(true);
+                imp.pin_icon.set_visible(false);
+                imp.unread_mention_label.set_visible(false);
+                imp.unread_count_label.set_visible(false);
+
+                let chat = message.chat();
+
+                // Title label binding
+                let title_binding =
+                    Chat::this_expression("title").bind(&*imp.title_label, "label", Some(&chat));
+                bindings.push(title_binding);
+
+                imp.timestamp_label
+                    .set_label(&stringify_timestamp(message.date()));
+                imp.bottom_label
+                    .set_label(&stringify_message(message.to_owned()));
+            } else if let Some(section_title) = item.downcast_ref::<gtk::StringObject>() {
+                self.add_css_class("section-title");
+                imp.avatar.set_visible(false);
+                imp.timestamp_label.set_visible(false);
+                imp.bottom_box.set_visible(false);
+
+                imp.title_label.set_label(&section_title.string());
             } else {
                 unreachable!("Unexpected item type: {:?}", item);
             }
@@ -362,6 +527,249 @@ impl Row {
         imp.item.replace(item);
         self.notify("item");
     }
+
+    fn chat(&self) -> Option<Chat> {
+        self.item().and_then(|item| item.downcast().ok())
+    }
+
+    pub fn chat_list(&self) -> ChatListType {
+        self.imp().chat_list.get()
+    }
+
+    pub fn set_chat_list(&self, chat_list: ChatListType) {
+        if self.chat_list() == chat_list {
+            return;
+        }
+        self.imp().chat_list.set(chat_list);
+        self.notify("chat-list");
+    }
+
+    fn parent_window(&self) -> Option<gtk::Window> {
+        self.root()?.downcast().ok()
+    }
+
+    fn popup_menu(&self, x: f64, y: f64) {
+        // Only chats have a context menu
+        let chat = match self.chat() {
+            Some(chat) => chat,
+            None => return,
+        };
+
+        let is_pinned = chat.is_pinned(self.chat_list());
+        self.action_set_enabled("sidebar-row.pin", !is_pinned);
+        self.action_set_enabled("sidebar-row.unpin", is_pinned);
+
+        self.action_set_enabled("sidebar-row.unmute", chat.is_muted());
+
+        let is_unread = chat.unread_count() > 0 || chat.is_marked_as_unread();
+        self.action_set_enabled("sidebar-row.mark-as-read", is_unread);
+        self.action_set_enabled("sidebar-row.mark-as-unread", !is_unread);
+
+        // Chats with myself can't be archived
+        let is_archived = chat.order(ChatListType::Archive) > 0;
+        let is_saved_messages = matches!(
+            chat.type_(),
+            ChatType::Private(user) if user.id() == chat.session().me().id()
+        );
+        self.action_set_enabled("sidebar-row.archive", !is_archived && !is_saved_messages);
+        self.action_set_enabled("sidebar-row.unarchive", is_archived);
+
+        let is_private = matches!(chat.type_(), ChatType::Private(_) | ChatType::Secret(_));
+        self.action_set_enabled("sidebar-row.delete-chat", is_private);
+        self.action_set_enabled("sidebar-row.leave-chat", !is_private);
+
+        let menu = self.imp().menu.get().unwrap();
+        menu.set_pointing_to(Some(&gdk::Rectangle::new(x as i32, y as i32, 0, 0)));
+        menu.popup();
+    }
+
+    fn toggle_is_pinned(&self, is_pinned: bool) {
+        if let Some(chat) = self.chat() {
+            let client_id = chat.session().client_id();
+            let chat_id = chat.id();
+            let chat_list = self.chat_list().to_td_object();
+
+            RUNTIME.spawn(async move {
+                if let Err(e) = functions::ToggleChatIsPinned::new()
+                    .chat_list(chat_list)
+                    .chat_id(chat_id)
+                    .is_pinned(is_pinned)
+                    .send(client_id)
+                    .await
+                {
+                    log::warn!("Failed to toggle the pinned state of the chat: {:?}", e);
+                }
+            });
+        }
+    }
+
+    fn set_mute_for(&self, mute_for: i32) {
+        if let Some(chat) = self.chat() {
+            let client_id = chat.session().client_id();
+            let chat_id = chat.id();
+            let mut notification_settings = chat.notification_settings().0;
+            notification_settings.use_default_mute_for = false;
+            notification_settings.mute_for = mute_for;
+
+            RUNTIME.spawn(async move {
+                if let Err(e) = functions::SetChatNotificationSettings::new()
+                    .chat_id(chat_id)
+                    .notification_settings(notification_settings)
+                    .send(client_id)
+                    .await
+                {
+                    log::warn!("Failed to update the notification settings: {:?}", e);
+                }
+            });
+        }
+    }
+
+    fn move_to_chat_list(&self, chat_list: ChatListType) {
+        if let Some(chat) = self.chat() {
+            let client_id = chat.session().client_id();
+            let chat_id = chat.id();
+
+            RUNTIME.spawn(async move {
+                if let Err(e) = functions::AddChatToList::new()
+                    .chat_id(chat_id)
+                    .chat_list(chat_list.to_td_object())
+                    .send(client_id)
+                    .await
+                {
+                    log::warn!("Failed to move the chat to another chat list: {:?}", e);
+                }
+            });
+        }
+    }
+
+    fn show_delete_dialog(&self) {
+        let chat = match self.chat() {
+            Some(chat) => chat,
+            None => return,
+        };
+        let parent_window = match self.parent_window() {
+            Some(parent_window) => parent_window,
+            None => return,
+        };
+
+        let (text, secondary_text, button_label) = match chat.type_() {
+            ChatType::Private(_) | ChatType::Secret(_) => (
+                gettext("Delete Chat?"),
+                gettext("The chat history will be deleted only for you."),
+                gettext("_Delete"),
+            ),
+            ChatType::Supergroup(supergroup) if supergroup.is_channel() => (
+                gettext("Leave Channel?"),
+                gettext("You will no longer receive the messages of this channel."),
+                gettext("_Leave"),
+            ),
+            _ => (
+                gettext("Leave Group?"),
+                gettext("You will no longer receive the messages of this group."),
+                gettext("_Leave"),
+            ),
+        };
+
+        let dialog = gtk::MessageDialog::builder()
+            .text(&text)
+            .secondary_text(&secondary_text)
+            .buttons(gtk::ButtonsType::Cancel)
+            .modal(true)
+            .transient_for(&parent_window)
+            .build();
+
+        dialog.add_action_widget(
+            &gtk::Button::builder()
+                .use_underline(true)
+                .label(&button_label)
+                .css_classes(vec!["destructive-action".to_string()])
+                .build(),
+            gtk::ResponseType::Accept,
+        );
+
+        dialog.run_async(clone!(@weak chat => move |dialog, response_id| {
+            dialog.close();
+
+            if response_id != gtk::ResponseType::Accept {
+                return;
+            }
+
+            let client_id = chat.session().client_id();
+            let chat_id = chat.id();
+            let secret_chat_id = match chat.type_() {
+                ChatType::Secret(secret_chat) => Some(secret_chat.id()),
+                _ => None,
+            };
+            let (leave, delete_history) = match chat.type_() {
+                ChatType::Private(_) | ChatType::Secret(_) => (false, true),
+                ChatType::BasicGroup(_) => (true, true),
+                // Leaving a supergroup or a channel already removes it from the chat lists
+                ChatType::Supergroup(_) => (true, false),
+            };
+
+            RUNTIME.spawn(async move {
+                // The secret chats must be closed before their history can be deleted
+                if let Some(secret_chat_id) = secret_chat_id {
+                    if let Err(e) = functions::CloseSecretChat::new()
+                        .secret_chat_id(secret_chat_id)
+                        .send(client_id)
+                        .await
+                    {
+                        log::warn!("Failed to close the secret chat: {:?}", e);
+                    }
+                }
+
+                if leave {
+                    if let Err(e) = functions::LeaveChat::new()
+                        .chat_id(chat_id)
+                        .send(client_id)
+                        .await
+                    {
+                        log::warn!("Failed to leave the chat: {:?}", e);
+                        return;
+                    }
+                }
+
+                if delete_history {
+                    if let Err(e) = functions::DeleteChatHistory::new()
+                        .chat_id(chat_id)
+                        .remove_from_chat_list(true)
+                        .send(client_id)
+                        .await
+                    {
+                        log::warn!("Failed to delete the chat: {:?}", e);
+                    }
+                }
+            });
+        }));
+    }
+}
+
+fn stringify_timestamp(date: i32) -> String {
+    let datetime_now = glib::DateTime::now_local().unwrap();
+    let datetime = glib::DateTime::from_unix_utc(date as i64)
+        .and_then(|t| t.to_local())
+        .unwrap();
+
+    let difference = datetime_now.difference(&datetime);
+    let hours_difference = difference.as_hours();
+    let days_difference = difference.as_days();
+
+    if hours_difference <= 16 {
+        // Show the time
+        // Translators: This is a time format for the chat list without seconds
+        datetime.format(&gettext("%l:%M %p")).unwrap()
+    } else if days_difference < 6 {
+        // Show the day of the week
+        datetime.format("%a").unwrap()
+    } else if days_difference < 364 {
+        // Show the day and the month
+        datetime.format("%d %b").unwrap()
+    } else {
+        // Show the entire date
+        datetime.format("%x").unwrap()
+    }
+    .to_string()
 }
 
 fn stringify_message(message: Message) -> String {
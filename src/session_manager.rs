@@ -86,6 +86,8 @@ pub enum ClientState {
 mod imp {
     use super::*;
 
+    use glib::subclass::Signal;
+    use once_cell::sync::Lazy;
     use std::cell::{Cell, RefCell};
     use std::collections::HashMap;
 
@@ -125,6 +127,16 @@ mod imp {
     }
 
     impl ObjectImpl for SessionManager {
+        fn signals() -> &'static [Signal] {
+            static SIGNALS: Lazy<Vec<Signal>> = Lazy::new(|| {
+                vec![
+                    Signal::builder("active-session-changed", &[], <()>::static_type().into())
+                        .build(),
+                ]
+            });
+            SIGNALS.as_ref()
+        }
+
         fn constructed(&self, obj: &Self::Type) {
             self.parent_constructed(obj);
 
@@ -197,7 +209,7 @@ glib::wrapper! {
 impl SessionManager {
     /// Returns the active client id if it is logged in or `None` if it isn't logged in
     /// (e.g. during authorization).
-    fn active_logged_in_client_id(&self) -> Option<i32> {
+    pub fn active_logged_in_client_id(&self) -> Option<i32> {
         let imp = self.imp();
 
         imp.main_stack
@@ -280,6 +292,33 @@ impl SessionManager {
         }
     }
 
+    /// Shows the session of the given client id and opens the chat with the given id in it.
+    ///
+    /// This is used to open the chat of a notification.
+    pub fn select_chat(&self, client_id: i32, chat_id: i64) {
+        if let Some(client) = self
+            .client(client_id)
+            .filter(|client| matches!(client.state, ClientState::LoggedIn))
+        {
+            let imp = self.imp();
+            imp.sessions.set_visible_child(&client.session);
+            imp.main_stack.set_visible_child(&*imp.sessions);
+
+            let chat = client.session.chat_list().get(chat_id);
+            client.session.set_selected_chat(Some(chat));
+        }
+    }
+
+    /// Returns whether the chat with the given id is the one currently shown in the active session.
+    pub fn is_chat_visible(&self, client_id: i32, chat_id: i64) -> bool {
+        self.active_logged_in_client_id() == Some(client_id)
+            && self
+                .client(client_id)
+                .and_then(|client| client.session.selected_chat())
+                .map(|chat| chat.id() == chat_id)
+                .unwrap_or_default()
+    }
+
     /// Returns sessions as selection model.
     ///
     /// Is mainly used by `Login` to check whether the back button should be visible on the phone
@@ -293,6 +332,7 @@ impl SessionManager {
     /// It does:
     ///   1. Update the online status of the clients
     ///   2. Update the order of the recently used sessions
+    ///   3. Emit the `active-session-changed` signal if the session is shown
     ///
     /// This is invoked when the visible child of the main stack or the sessions stack changes.
     fn on_active_session_changed(&self) {
@@ -315,10 +355,24 @@ impl SessionManager {
                 }
 
                 self.save_recently_used_sessions();
+
+                self.emit_by_name::<()>("active-session-changed", &[]);
             }
         }
     }
 
+    pub fn connect_active_session_changed<F: Fn(&Self) + 'static>(
+        &self,
+        f: F,
+    ) -> glib::SignalHandlerId {
+        self.connect_local("active-session-changed", true, move |values| {
+            let obj = values[0].get::<Self>().unwrap();
+            f(&obj);
+
+            None
+        })
+    }
+
     /// Sets the online status for the active logged in client. This will be called from the
     /// application `Window` when its active state has changed.
     pub fn set_active_client_online(&self, value: bool) {
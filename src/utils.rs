@@ -1,9 +1,12 @@
 use gettextrs::gettext;
-use gtk::glib;
+use gtk::{gdk, glib};
 use locale_config::Locale;
 use once_cell::sync::Lazy;
 use regex::Regex;
+use std::cell::RefCell;
+use std::cmp::Reverse;
 use std::future::Future;
+use std::ops::Range;
 use std::path::PathBuf;
 use tdgrand::enums::TextEntityType;
 use tdgrand::types::{self, FormattedText};
@@ -16,6 +19,30 @@ pub static PROTOCOL_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\w+://").unwrap
 
 pub const MESSAGE_TRUNCATED_LENGTH: usize = 21;
 
+/// The prefix of the links handled internally by the app, like the ones of the mentions
+const INTERNAL_LINK_PREFIX: &str = "telegrand://";
+
+thread_local! {
+    /// The pango attributes of the background of the hidden spoilers, which follows the
+    /// foreground color of the theme
+    static SPOILER_BACKGROUND: RefCell<String> =
+        RefCell::new(String::from("bgcolor='#000000' bgalpha='80%'"));
+}
+
+/// Sets the color of the background of the spoilers, usually to the foreground color of the
+/// theme.
+pub fn set_spoiler_color(color: &gdk::RGBA) {
+    let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    let background = format!(
+        "bgcolor='#{:02x}{:02x}{:02x}' bgalpha='{}%'",
+        channel(color.red()),
+        channel(color.green()),
+        channel(color.blue()),
+        (color.alpha().clamp(0.0, 1.0) * 100.0).round().max(1.0) as u8
+    );
+    SPOILER_BACKGROUND.with(|spoiler_background| spoiler_background.replace(background));
+}
+
 pub fn escape(text: &str) -> String {
     text.replace('&', "&amp;")
         .replace('<', "&lt;")
@@ -41,84 +68,641 @@ pub fn linkify(text: &str) -> String {
     }
 }
 
-pub fn convert_to_markup(text: String, entity: &TextEntityType) -> String {
+/// Returns the name of the pango tag used to format the text of the entity, if any.
+fn formatting_tag(entity: &TextEntityType) -> Option<&'static str> {
     match entity {
-        TextEntityType::Url => format!("<a href='{}'>{}</a>", linkify(&text), text),
-        TextEntityType::EmailAddress => format!("<a href='mailto:{0}'>{0}</a>", text),
-        TextEntityType::PhoneNumber => format!("<a href='tel:{0}'>{0}</a>", text),
-        TextEntityType::Bold => format!("<b>{}</b>", text),
-        TextEntityType::Italic => format!("<i>{}</i>", text),
-        TextEntityType::Underline => format!("<u>{}</u>", text),
-        TextEntityType::Strikethrough => format!("<s>{}</s>", text),
-        TextEntityType::Code | TextEntityType::Pre | TextEntityType::PreCode(_) => {
-            format!("<tt>{}</tt>", text)
+        TextEntityType::Bold => Some("b"),
+        TextEntityType::Italic => Some("i"),
+        TextEntityType::Underline => Some("u"),
+        TextEntityType::Strikethrough => Some("s"),
+        TextEntityType::Code | TextEntityType::Pre | TextEntityType::PreCode(_) => Some("tt"),
+        _ => None,
+    }
+}
+
+/// Returns the target of the link of the entity, if any. The text is the unescaped text of
+/// the whole entity.
+fn link_target(entity: &TextEntityType, text: &str) -> Option<String> {
+    let target = match entity {
+        TextEntityType::Url => linkify(text),
+        TextEntityType::EmailAddress => format!("mailto:{}", text),
+        TextEntityType::PhoneNumber => format!("tel:{}", text),
+        TextEntityType::TextUrl(data) => data.url.clone(),
+        TextEntityType::Mention => internal_link("mention", text.trim_start_matches('@')),
+        TextEntityType::MentionName(data) => internal_link("user", &data.user_id.to_string()),
+        TextEntityType::Hashtag | TextEntityType::Cashtag => internal_link("search", text),
+        TextEntityType::BotCommand => internal_link("bot-command", text),
+        TextEntityType::BankCardNumber => internal_link("bank-card", text),
+        TextEntityType::Spoiler => internal_link("spoiler", ""),
+        _ => return None,
+    };
+
+    Some(escape(&target))
+}
+
+fn internal_link(action: &str, value: &str) -> String {
+    format!(
+        "{}{}/{}",
+        INTERNAL_LINK_PREFIX,
+        action,
+        glib::uri_escape_string(value, None, true)
+    )
+}
+
+/// Parses a link created by `parse_formatted_text` for the entities handled internally by the
+/// app, returning its action and its value.
+pub fn parse_internal_link(uri: &str) -> Option<(&str, String)> {
+    let (action, value) = uri.strip_prefix(INTERNAL_LINK_PREFIX)?.split_once('/')?;
+    let value = glib::uri_unescape_string(value, None::<&str>)?;
+    Some((action, value.into()))
+}
+
+/// Returns whether the link is a `tg:` link or a link to a telegram domain, which may need to be
+/// handled by the app.
+pub fn is_telegram_link(uri: &str) -> bool {
+    let uri = uri.to_lowercase();
+    if uri.starts_with("tg:") {
+        return true;
+    }
+
+    let host = uri
+        .strip_prefix("https://")
+        .or_else(|| uri.strip_prefix("http://"))
+        .unwrap_or(&uri)
+        .split(&['/', '?', '#'][..])
+        .next()
+        .unwrap_or_default();
+
+    matches!(
+        host.strip_prefix("www.").unwrap_or(host),
+        "t.me" | "telegram.me" | "telegram.dog"
+    )
+}
+
+/// An entity of a formatted text, with its range in bytes of the text.
+struct EntitySpan<'a> {
+    range: Range<usize>,
+    r#type: &'a TextEntityType,
+}
+
+/// Converts the offsets of the entities, which tdlib stores as utf16 code units instead of
+/// regular code points, into byte ranges of the text.
+fn entity_spans(formatted_text: &FormattedText) -> Vec<EntitySpan<'_>> {
+    let text = &formatted_text.text;
+    let byte_index = |code_units_offset: i32| {
+        let mut code_units = 0;
+        text.char_indices()
+            .find(|(_, c)| {
+                let is_reached = code_units >= code_units_offset as usize;
+                code_units += c.len_utf16();
+                is_reached
+            })
+            .map_or(text.len(), |(index, _)| index)
+    };
+
+    formatted_text
+        .entities
+        .iter()
+        .map(|entity| {
+            let start = byte_index(entity.offset);
+            let end = byte_index(entity.offset + entity.length).max(start);
+            EntitySpan {
+                range: start..end,
+                r#type: &entity.r#type,
+            }
+        })
+        .collect()
+}
+
+enum SpanSegment {
+    Open(usize),
+    Close(usize),
+    Text(Range<usize>),
+}
+
+/// Splits the text at the boundaries of all the spans, returning the text segments along with
+/// the opening and closing of the nestable spans. The spans are always properly nested: the
+/// ones that partially overlap are closed and opened again around the boundaries.
+fn nest_spans(
+    text_len: usize,
+    spans: &[EntitySpan],
+    is_nestable: impl Fn(&TextEntityType) -> bool,
+) -> Vec<SpanSegment> {
+    let mut boundaries: Vec<usize> = spans
+        .iter()
+        .flat_map(|span| [span.range.start, span.range.end])
+        .chain([0, text_len])
+        .collect();
+    boundaries.sort_unstable();
+    boundaries.dedup();
+
+    // Open the longest spans first when they start at the same position
+    let mut spans_to_open: Vec<usize> = (0..spans.len())
+        .filter(|i| !spans[*i].range.is_empty() && is_nestable(spans[*i].r#type))
+        .collect();
+    spans_to_open.sort_by_key(|i| (spans[*i].range.start, Reverse(spans[*i].range.end)));
+    let mut spans_to_open = spans_to_open.into_iter().peekable();
+
+    let mut segments = vec![];
+    let mut stack: Vec<usize> = vec![];
+
+    for (index, position) in boundaries.iter().enumerate() {
+        // Close the spans ending here, along with the ones opened after them, which are then
+        // opened again if they don't end here too
+        if let Some(depth) = stack.iter().position(|i| spans[*i].range.end <= *position) {
+            let closed_spans = stack.split_off(depth);
+            segments.extend(closed_spans.iter().rev().map(|i| SpanSegment::Close(*i)));
+
+            for i in closed_spans {
+                if spans[i].range.end > *position {
+                    segments.push(SpanSegment::Open(i));
+                    stack.push(i);
+                }
+            }
+        }
+
+        while let Some(i) = spans_to_open.next_if(|i| spans[*i].range.start == *position) {
+            segments.push(SpanSegment::Open(i));
+            stack.push(i);
+        }
+
+        if let Some(next_position) = boundaries.get(index + 1) {
+            segments.push(SpanSegment::Text(*position..*next_position));
         }
-        TextEntityType::TextUrl(data) => format!("<a href='{}'>{}</a>", escape(&data.url), text),
-        _ => text,
     }
+
+    segments
 }
 
 pub fn parse_formatted_text(formatted_text: FormattedText) -> String {
-    let mut entities = formatted_text.entities.iter();
-    let mut entity = entities.next();
+    let text = &formatted_text.text;
+    let spans = entity_spans(&formatted_text);
     let mut output = String::new();
-    let mut buffer = String::new();
-    let mut is_inside_entity = false;
-
-    // This is the offset in utf16 code units of the text to parse. We need this variable
-    // because tdlib stores the offset and length parameters as utf16 code units instead
-    // of regular code points.
-    let mut code_units_offset = 0;
-
-    for c in formatted_text.text.chars() {
-        if !is_inside_entity
-            && entity.is_some()
-            && code_units_offset >= entity.unwrap().offset as usize
+
+    let is_formatting_entity = |entity: &TextEntityType| formatting_tag(entity).is_some();
+
+    for segment in nest_spans(text.len(), &spans, is_formatting_entity) {
+        match segment {
+            SpanSegment::Open(i) => {
+                output.push_str(&format!("<{}>", formatting_tag(spans[i].r#type).unwrap()));
+            }
+            SpanSegment::Close(i) => {
+                output.push_str(&format!("</{}>", formatting_tag(spans[i].r#type).unwrap()));
+            }
+            SpanSegment::Text(range) => {
+                let segment_text = escape(&text[range.clone()]);
+
+                // Links can't be nested, so only use the link of the innermost entity, unless
+                // the segment is part of a spoiler, which must be revealed first
+                let link_span = spans
+                    .iter()
+                    .filter(|span| {
+                        span.range.start <= range.start
+                            && range.end <= span.range.end
+                            && link_target(span.r#type, "").is_some()
+                    })
+                    .max_by_key(|span| {
+                        (
+                            matches!(span.r#type, TextEntityType::Spoiler),
+                            span.range.start,
+                            Reverse(span.range.end),
+                        )
+                    });
+
+                match link_span {
+                    Some(span) => {
+                        let target = link_target(span.r#type, &text[span.range.clone()]).unwrap();
+                        if let TextEntityType::Spoiler = span.r#type {
+                            let background = SPOILER_BACKGROUND.with(|b| b.borrow().clone());
+                            output.push_str(&format!(
+                                "<a href='{}'><span {} fgalpha='1' underline='none'>{}</span></a>",
+                                target, background, segment_text
+                            ));
+                        } else {
+                            output.push_str(&format!("<a href='{}'>{}</a>", target, segment_text));
+                        }
+                    }
+                    None => output.push_str(&segment_text),
+                }
+            }
+        }
+    }
+
+    output
+}
+
+/// Parses the markdown-style formatting of the text (`**bold**`, `_italic_`, `__underline__`,
+/// `~~strikethrough~~`, `` `code` ``, ```` ```pre``` ```` and `[text](url)`) into a
+/// `FormattedText`. Markup characters can be escaped with a backslash, and they're ignored
+/// inside code and urls. The links to `tg://user?id=` urls are parsed as mentions of the user,
+/// while closing parentheses and backslashes in the urls of the links must be escaped.
+pub fn parse_markdown(text: &str) -> FormattedText {
+    let chars: Vec<char> = text.chars().collect();
+    let mut formatted_text = FormattedText::default();
+
+    parse_markdown_range(&chars, 0..chars.len(), &mut formatted_text);
+
+    formatted_text
+}
+
+const MARKDOWN_CHARS: [char; 6] = ['*', '_', '~', '`', '[', '\\'];
+
+/// The prefix of the urls of the links to users, which are parsed as mentions by name
+const MENTION_NAME_URL_PREFIX: &str = "tg://user?id=";
+
+struct MarkdownEntity {
+    r#type: TextEntityType,
+    /// The range of the content, without the markup characters
+    content: Range<usize>,
+    /// The index after the end of the entity
+    end: usize,
+}
+
+/// Parses the markdown of the chars in the range, appending the text and the entities to the
+/// formatted text.
+fn parse_markdown_range(chars: &[char], range: Range<usize>, formatted_text: &mut FormattedText) {
+    let mut index = range.start;
+
+    while index < range.end {
+        // Copy the urls as they are, so that their underscores aren't parsed
+        if let Some(url_end) = url_end(chars, index, range.end) {
+            formatted_text.text.extend(&chars[index..url_end]);
+            index = url_end;
+            continue;
+        }
+
+        if let Some(entity) = parse_markdown_entity(chars, index, range.end) {
+            // The offsets are in utf16 code units, as required by tdlib
+            let offset = formatted_text.text.encode_utf16().count();
+            let position = formatted_text.entities.len();
+
+            if matches!(entity.r#type, TextEntityType::Code | TextEntityType::Pre) {
+                formatted_text.text.extend(&chars[entity.content]);
+            } else {
+                parse_markdown_range(chars, entity.content, formatted_text);
+            }
+
+            let length = formatted_text.text.encode_utf16().count() - offset;
+            formatted_text.entities.insert(
+                position,
+                types::TextEntity {
+                    offset: offset as i32,
+                    length: length as i32,
+                    r#type: entity.r#type,
+                },
+            );
+
+            index = entity.end;
+            continue;
+        }
+
+        // Skip the backslash of escaped markup characters
+        if is_escape(chars, index, range.end) {
+            index += 1;
+        }
+
+        formatted_text.text.push(chars[index]);
+        index += 1;
+    }
+}
+
+/// Parses the markdown entity starting at the specified index, if any.
+fn parse_markdown_entity(chars: &[char], start: usize, end: usize) -> Option<MarkdownEntity> {
+    let delimiters = [
+        ("```", TextEntityType::Pre),
+        ("`", TextEntityType::Code),
+        ("**", TextEntityType::Bold),
+        ("~~", TextEntityType::Strikethrough),
+        ("__", TextEntityType::Underline),
+        ("_", TextEntityType::Italic),
+    ];
+
+    for (delimiter, r#type) in delimiters {
+        if !starts_with_at(chars, start, end, delimiter) {
+            continue;
+        }
+
+        let delimiter_len = delimiter.chars().count();
+        let content_start = start + delimiter_len;
+
+        let content_end = match r#type {
+            // The code is taken as it is, without parsing the markup characters in it
+            TextEntityType::Pre | TextEntityType::Code => {
+                find_at(chars, content_start, end, delimiter)?
+            }
+            // Underscores are only parsed at the boundaries of words, so that the ones in
+            // identifiers are left untouched
+            TextEntityType::Underline | TextEntityType::Italic => {
+                if !is_underscore_opening(chars, start, end, delimiter) {
+                    return None;
+                }
+
+                find_closing_delimiter(chars, content_start, end, |i| {
+                    is_underscore_closing(chars, i, end, delimiter)
+                })?
+            }
+            _ => find_closing_delimiter(chars, content_start, end, |i| {
+                starts_with_at(chars, i, end, delimiter)
+            })?,
+        };
+
+        // Remove the newlines around the code in pre blocks
+        let mut content = content_start..content_end;
+        if let TextEntityType::Pre = r#type {
+            while content.start < content.end && chars[content.start] == '\n' {
+                content.start += 1;
+            }
+            while content.start < content.end && chars[content.end - 1] == '\n' {
+                content.end -= 1;
+            }
+        }
+
+        if content.is_empty() {
+            return None;
+        }
+
+        return Some(MarkdownEntity {
+            r#type,
+            content,
+            end: content_end + delimiter_len,
+        });
+    }
+
+    if chars[start] == '[' {
+        let text_end = find_closing_delimiter(chars, start + 1, end, |i| {
+            starts_with_at(chars, i, end, "](")
+        })?;
+        let url_start = text_end + 2;
+
+        // The url ends at the first closing parenthesis that isn't escaped with a backslash
+        let mut url = String::new();
+        let mut url_end = url_start;
+        while url_end < end && chars[url_end] != ')' {
+            if chars[url_end] == '\\'
+                && url_end + 1 < end
+                && matches!(chars[url_end + 1], ')' | '\\')
+            {
+                url_end += 1;
+            }
+            url.push(chars[url_end]);
+            url_end += 1;
+        }
+
+        if url_end == end
+            || text_end == start + 1
+            || url.is_empty()
+            || url.contains(char::is_whitespace)
         {
-            is_inside_entity = true;
+            return None;
+        }
 
-            if !buffer.is_empty() {
-                output.push_str(&escape(&buffer));
-                buffer = String::new();
+        let mention_user_id = url
+            .strip_prefix(MENTION_NAME_URL_PREFIX)
+            .and_then(|user_id| user_id.parse().ok());
+        let r#type = match mention_user_id {
+            Some(user_id) => {
+                TextEntityType::MentionName(types::TextEntityTypeMentionName { user_id })
             }
+            None => TextEntityType::TextUrl(types::TextEntityTypeTextUrl { url }),
+        };
+
+        return Some(MarkdownEntity {
+            r#type,
+            content: start + 1..text_end,
+            end: url_end + 1,
+        });
+    }
+
+    None
+}
+
+/// Returns whether an underscore delimiter can start at the index, which is only at the
+/// beginning of a word (e.g. not in `snake_case` or `self.__init__`).
+fn is_underscore_opening(chars: &[char], start: usize, end: usize, delimiter: &str) -> bool {
+    let is_after_word =
+        start > 0 && (chars[start - 1].is_alphanumeric() || matches!(chars[start - 1], '_' | '.'));
+    let content_start = start + delimiter.chars().count();
+
+    !is_after_word
+        && content_start < end
+        && !chars[content_start].is_whitespace()
+        && chars[content_start] != '_'
+}
+
+/// Returns whether an underscore delimiter can end at the index, which is only at the end of
+/// a word (e.g. not in `__init__.py` or `__init__()`).
+fn is_underscore_closing(chars: &[char], index: usize, end: usize, delimiter: &str) -> bool {
+    let after = index + delimiter.chars().count();
+    let is_before_word = match chars.get(after) {
+        Some(c) if c.is_alphanumeric() || *c == '_' => true,
+        Some('.' | '(') => chars
+            .get(after + 1)
+            .map_or(false, |c| c.is_alphanumeric() || *c == ')'),
+        _ => false,
+    };
+
+    starts_with_at(chars, index, end, delimiter)
+        && !chars[index - 1].is_whitespace()
+        && chars[index - 1] != '_'
+        && !is_before_word
+}
+
+/// Finds the first index after `from` for which `is_closing` returns true, skipping the
+/// escaped characters, the code and the urls.
+fn find_closing_delimiter(
+    chars: &[char],
+    from: usize,
+    end: usize,
+    is_closing: impl Fn(usize) -> bool,
+) -> Option<usize> {
+    let mut index = from;
+
+    while index < end {
+        if is_escape(chars, index, end) {
+            index += 2;
+        } else if is_closing(index) {
+            return Some(index);
+        } else if let Some(skip_end) =
+            code_end(chars, index, end).or_else(|| url_end(chars, index, end))
+        {
+            index = skip_end;
+        } else {
+            index += 1;
         }
+    }
 
-        buffer.push(c);
-        code_units_offset += c.len_utf16();
+    None
+}
 
-        if let Some(entity_) = entity {
-            if code_units_offset >= (entity_.offset + entity_.length) as usize {
-                buffer = escape(&buffer);
+/// Returns the index after the end of the code starting at the specified index, if any.
+fn code_end(chars: &[char], start: usize, end: usize) -> Option<usize> {
+    let delimiter = ["```", "`"]
+        .into_iter()
+        .find(|delimiter| starts_with_at(chars, start, end, delimiter))?;
+    let delimiter_len = delimiter.chars().count();
 
-                entity = loop {
-                    let entity = entities.next();
+    find_at(chars, start + delimiter_len, end, delimiter).map(|i| i + delimiter_len)
+}
 
-                    // Handle eventual nested entities
-                    match entity {
-                        Some(entity) => {
-                            if entity.offset == entity_.offset {
-                                buffer = convert_to_markup(buffer, &entity.r#type);
-                            } else {
-                                break Some(entity);
-                            }
-                        }
-                        None => break None,
+/// Returns the index after the end of the url starting at the specified index, if any. The
+/// urls are detected by their scheme (e.g. `https://`) or by the `www.` prefix, and they end
+/// at the first whitespace, excluding the trailing punctuation.
+fn url_end(chars: &[char], start: usize, end: usize) -> Option<usize> {
+    if start > 0 && chars[start - 1].is_alphanumeric() || !chars[start].is_ascii_alphabetic() {
+        return None;
+    }
+
+    let scheme_end = (start..end)
+        .find(|i| !(chars[*i].is_ascii_alphanumeric() || matches!(chars[*i], '+' | '-' | '.')))
+        .unwrap_or(end);
+    let is_url =
+        starts_with_at(chars, scheme_end, end, "://") || starts_with_at(chars, start, end, "www.");
+    if !is_url {
+        return None;
+    }
+
+    let mut url_end = (start..end)
+        .find(|i| chars[*i].is_whitespace())
+        .unwrap_or(end);
+    while url_end > start
+        && matches!(
+            chars[url_end - 1],
+            '.' | ',' | ';' | ':' | '!' | '?' | '\'' | '"' | ')' | ']' | '*' | '_' | '~' | '`'
+        )
+    {
+        url_end -= 1;
+    }
+
+    Some(url_end)
+}
+
+/// Returns whether the char at the specified index is a backslash escaping a markup character.
+fn is_escape(chars: &[char], index: usize, end: usize) -> bool {
+    chars[index] == '\\' && index + 1 < end && MARKDOWN_CHARS.contains(&chars[index + 1])
+}
+
+fn starts_with_at(chars: &[char], index: usize, end: usize, pattern: &str) -> bool {
+    pattern
+        .chars()
+        .enumerate()
+        .all(|(i, c)| index + i < end && chars[index + i] == c)
+}
+
+fn find_at(chars: &[char], from: usize, end: usize, pattern: &str) -> Option<usize> {
+    (from..end).find(|i| starts_with_at(chars, *i, end, pattern))
+}
+
+/// Converts the formatted text into the markdown-style formatting parsed by `parse_markdown`,
+/// like to edit a message. The markup characters in the text are escaped, when needed.
+pub fn formatted_text_to_markdown(formatted_text: &FormattedText) -> String {
+    let text = &formatted_text.text;
+    let chars: Vec<char> = text.chars().collect();
+    let spans = entity_spans(formatted_text);
+    let mut output = String::new();
+
+    // The index in chars of the text segment and the number of code entities opened
+    let mut char_index = 0;
+    let mut code_depth = 0;
+
+    for segment in nest_spans(text.len(), &spans, |entity| {
+        markdown_delimiter(entity).is_some()
+    }) {
+        match segment {
+            // Tdlib doesn't allow other entities inside code, so there's no need to handle them
+            SpanSegment::Open(i) | SpanSegment::Close(i)
+                if code_depth > 0 && !is_code_entity(spans[i].r#type) => {}
+            SpanSegment::Open(i) => {
+                let r#type = spans[i].r#type;
+                if is_code_entity(r#type) {
+                    code_depth += 1;
+                }
+
+                output.push_str(markdown_delimiter(r#type).unwrap());
+            }
+            SpanSegment::Close(i) => match spans[i].r#type {
+                TextEntityType::TextUrl(data) => {
+                    let url = data.url.replace('\\', "\\\\").replace(')', "\\)");
+                    output.push_str(&format!("]({})", url));
+                }
+                TextEntityType::MentionName(data) => {
+                    output.push_str(&format!("]({}{})", MENTION_NAME_URL_PREFIX, data.user_id));
+                }
+                r#type => {
+                    if is_code_entity(r#type) {
+                        code_depth -= 1;
                     }
-                };
 
-                output.push_str(&convert_to_markup(buffer, &entity_.r#type));
-                buffer = String::new();
-                is_inside_entity = false;
+                    output.push_str(markdown_delimiter(r#type).unwrap());
+                }
+            },
+            SpanSegment::Text(range) => {
+                for c in text[range].chars() {
+                    // Escape the markup characters that would otherwise be parsed, leaving the
+                    // ones in code and urls as they are
+                    if code_depth == 0
+                        && (is_escape(&chars, char_index, chars.len())
+                            || parse_markdown_entity(&chars, char_index, chars.len()).is_some())
+                        && !is_inside_url(&chars, char_index)
+                    {
+                        output.push('\\');
+                    }
+
+                    output.push(c);
+                    char_index += 1;
+                }
             }
         }
     }
 
-    // Add the eventual leftovers from the buffer to the output
-    if !buffer.is_empty() {
-        output.push_str(&escape(&buffer));
+    output
+}
+
+fn markdown_delimiter(entity: &TextEntityType) -> Option<&'static str> {
+    match entity {
+        TextEntityType::Bold => Some("**"),
+        TextEntityType::Italic => Some("_"),
+        TextEntityType::Underline => Some("__"),
+        TextEntityType::Strikethrough => Some("~~"),
+        TextEntityType::Code => Some("`"),
+        TextEntityType::Pre | TextEntityType::PreCode(_) => Some("```"),
+        TextEntityType::TextUrl(_) | TextEntityType::MentionName(_) => Some("["),
+        _ => None,
     }
+}
 
-    output
+fn is_code_entity(entity: &TextEntityType) -> bool {
+    matches!(
+        entity,
+        TextEntityType::Code | TextEntityType::Pre | TextEntityType::PreCode(_)
+    )
+}
+
+/// Returns whether the char at the specified index is part of an url.
+fn is_inside_url(chars: &[char], index: usize) -> bool {
+    let word_start = chars[..index]
+        .iter()
+        .rposition(|c| c.is_whitespace())
+        .map_or(0, |i| i + 1);
+
+    (word_start..=index)
+        .any(|start| url_end(chars, start, chars.len()).map_or(false, |end| index < end))
+}
+
+/// Like `parse_formatted_text`, but with the spoilers revealed.
+pub fn parse_formatted_text_with_revealed_spoilers(mut formatted_text: FormattedText) -> String {
+    formatted_text
+        .entities
+        .retain(|entity| !matches!(entity.r#type, TextEntityType::Spoiler));
+    parse_formatted_text(formatted_text)
+}
+
+/// The duration in seconds of a mute that never expires, like when a chat is muted forever
+pub const MUTE_FOREVER_DURATION: i32 = i32::MAX;
+
+/// Returns whether a mute of `mute_for` seconds, whose settings were received at the unix time
+/// `date`, didn't expire yet.
+pub fn is_mute_active(mute_for: i32, date: i64) -> bool {
+    mute_for > 0 && glib::real_time() / 1_000_000 < date + mute_for as i64
 }
 
 pub fn human_friendly_duration(mut seconds: i32) -> String {
@@ -147,6 +731,27 @@ pub fn human_friendly_duration(mut seconds: i32) -> String {
     }
 }
 
+/// Decodes a WebP image, like the ones used by the stickers, into a texture.
+pub fn decode_webp(bytes: &[u8]) -> Option<gdk::Texture> {
+    let image = webp::Decoder::new(bytes).decode()?.to_image().into_rgba8();
+
+    let flat_samples = image.into_flat_samples();
+
+    let (stride, width, height) = flat_samples.extents();
+    let gtk_stride = stride * width;
+
+    let bytes = glib::Bytes::from_owned(flat_samples.samples);
+    let texture = gdk::MemoryTexture::new(
+        width as i32,
+        height as i32,
+        gdk::MemoryFormat::R8g8b8a8,
+        &bytes,
+        gtk_stride,
+    );
+
+    Some(texture.upcast())
+}
+
 /// Returns the Telegrand data directory (e.g. /home/bob/.local/share/telegrand).
 pub fn data_dir() -> &'static PathBuf {
     &APPLICATION_OPTS.get().unwrap().data_dir
@@ -230,3 +835,428 @@ macro_rules! spawn {
         ctx.spawn_local_with_priority($priority, $future);
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entity(offset: i32, length: i32, r#type: TextEntityType) -> types::TextEntity {
+        types::TextEntity {
+            offset,
+            length,
+            r#type,
+        }
+    }
+
+    fn formatted_text(text: &str, entities: Vec<types::TextEntity>) -> FormattedText {
+        FormattedText {
+            text: text.to_string(),
+            entities,
+        }
+    }
+
+    fn mention_name(user_id: i64) -> TextEntityType {
+        TextEntityType::MentionName(types::TextEntityTypeMentionName { user_id })
+    }
+
+    fn text_url(url: &str) -> TextEntityType {
+        TextEntityType::TextUrl(types::TextEntityTypeTextUrl {
+            url: url.to_string(),
+        })
+    }
+
+    #[test]
+    fn parse_formatted_text_without_entities() {
+        let text = formatted_text("a < b && c", vec![]);
+        assert_eq!(parse_formatted_text(text), "a &lt; b &amp;&amp; c");
+    }
+
+    #[test]
+    fn parse_formatted_text_nested_entities() {
+        let text = formatted_text(
+            "abcdefghij",
+            vec![
+                entity(0, 10, TextEntityType::Bold),
+                entity(2, 3, TextEntityType::Italic),
+            ],
+        );
+        assert_eq!(parse_formatted_text(text), "<b>ab<i>cde</i>fghij</b>");
+
+        let text = formatted_text(
+            "abcdef",
+            vec![
+                entity(0, 6, TextEntityType::Bold),
+                entity(0, 6, TextEntityType::Italic),
+                entity(3, 3, TextEntityType::Underline),
+            ],
+        );
+        assert_eq!(parse_formatted_text(text), "<b><i>abc<u>def</u></i></b>");
+    }
+
+    #[test]
+    fn parse_formatted_text_nested_entity_listed_first() {
+        let text = formatted_text(
+            "abcdef",
+            vec![
+                entity(2, 2, TextEntityType::Italic),
+                entity(0, 6, TextEntityType::Bold),
+            ],
+        );
+        assert_eq!(parse_formatted_text(text), "<b>ab<i>cd</i>ef</b>");
+    }
+
+    #[test]
+    fn parse_formatted_text_overlapping_entities() {
+        let text = formatted_text(
+            "abcdefgh",
+            vec![
+                entity(0, 4, TextEntityType::Bold),
+                entity(2, 4, TextEntityType::Italic),
+            ],
+        );
+        assert_eq!(parse_formatted_text(text), "<b>ab<i>cd</i></b><i>ef</i>gh");
+    }
+
+    #[test]
+    fn parse_formatted_text_surrogate_pairs() {
+        let text = formatted_text("😀 bold 😀", vec![entity(3, 4, TextEntityType::Bold)]);
+        assert_eq!(parse_formatted_text(text), "😀 <b>bold</b> 😀");
+
+        let text = formatted_text("a😀b", vec![entity(1, 2, TextEntityType::Italic)]);
+        assert_eq!(parse_formatted_text(text), "a<i>😀</i>b");
+
+        let text = formatted_text(
+            "😀😀 x",
+            vec![
+                entity(0, 4, TextEntityType::Bold),
+                entity(2, 2, TextEntityType::Strikethrough),
+            ],
+        );
+        assert_eq!(parse_formatted_text(text), "<b>😀<s>😀</s></b> x");
+    }
+
+    #[test]
+    fn parse_formatted_text_out_of_range_entity() {
+        let text = formatted_text("abc", vec![entity(1, 10, TextEntityType::Bold)]);
+        assert_eq!(parse_formatted_text(text), "a<b>bc</b>");
+    }
+
+    #[test]
+    fn parse_formatted_text_link_targets() {
+        let text = formatted_text(
+            "@foo & #tag",
+            vec![
+                entity(0, 4, TextEntityType::Mention),
+                entity(0, 4, TextEntityType::Bold),
+                entity(7, 4, TextEntityType::Hashtag),
+            ],
+        );
+        assert_eq!(
+            parse_formatted_text(text),
+            "<b><a href='telegrand://mention/foo'>@foo</a></b> &amp; \
+            <a href='telegrand://search/%23tag'>#tag</a>"
+        );
+
+        let text = formatted_text("x.org/a?b&c", vec![entity(0, 11, TextEntityType::Url)]);
+        assert_eq!(
+            parse_formatted_text(text),
+            "<a href='http://x.org/a?b&amp;c'>x.org/a?b&amp;c</a>"
+        );
+    }
+
+    #[test]
+    fn parse_formatted_text_links_are_not_nested() {
+        let text = formatted_text(
+            "see @foo",
+            vec![
+                entity(0, 8, text_url("https://example.com")),
+                entity(4, 4, TextEntityType::Mention),
+            ],
+        );
+        assert_eq!(
+            parse_formatted_text(text),
+            "<a href='https://example.com'>see </a>\
+            <a href='telegrand://mention/foo'>@foo</a>"
+        );
+
+        let text = formatted_text(
+            "ab",
+            vec![
+                entity(0, 2, text_url("https://example.com")),
+                entity(1, 1, TextEntityType::Spoiler),
+            ],
+        );
+        assert_eq!(
+            parse_formatted_text(text),
+            "<a href='https://example.com'>a</a>\
+            <a href='telegrand://spoiler/'><span bgcolor='#000000' bgalpha='80%' fgalpha='1' \
+            underline='none'>b</span></a>"
+        );
+    }
+
+    fn entities_of(formatted_text: &FormattedText) -> Vec<(i32, i32, String)> {
+        formatted_text
+            .entities
+            .iter()
+            .map(|entity| (entity.offset, entity.length, format!("{:?}", entity.r#type)))
+            .collect()
+    }
+
+    fn assert_markdown(markdown: &str, text: &str, entities: Vec<types::TextEntity>) {
+        let formatted_text = parse_markdown(markdown);
+        assert_eq!(formatted_text.text, text);
+        assert_eq!(
+            entities_of(&formatted_text),
+            entities_of(&FormattedText {
+                text: text.to_string(),
+                entities,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_markdown_entities() {
+        assert_markdown(
+            "**bold**, _italic_, __underline__ and ~~strike~~",
+            "bold, italic, underline and strike",
+            vec![
+                entity(0, 4, TextEntityType::Bold),
+                entity(6, 6, TextEntityType::Italic),
+                entity(14, 9, TextEntityType::Underline),
+                entity(28, 6, TextEntityType::Strikethrough),
+            ],
+        );
+        assert_markdown(
+            "`code` and ```\nlet a = 1;\n```",
+            "code and let a = 1;",
+            vec![
+                entity(0, 4, TextEntityType::Code),
+                entity(9, 10, TextEntityType::Pre),
+            ],
+        );
+        assert_markdown(
+            "[link](https://example.com)",
+            "link",
+            vec![entity(0, 4, text_url("https://example.com"))],
+        );
+    }
+
+    #[test]
+    fn parse_markdown_nested_entities() {
+        assert_markdown(
+            "**bold _both_** [_link_](https://example.com)",
+            "bold both link",
+            vec![
+                entity(0, 9, TextEntityType::Bold),
+                entity(5, 4, TextEntityType::Italic),
+                entity(10, 4, text_url("https://example.com")),
+                entity(10, 4, TextEntityType::Italic),
+            ],
+        );
+    }
+
+    #[test]
+    fn parse_markdown_surrogate_pairs() {
+        assert_markdown(
+            "😀 **bold** 😀 _a_",
+            "😀 bold 😀 a",
+            vec![
+                entity(3, 4, TextEntityType::Bold),
+                entity(11, 1, TextEntityType::Italic),
+            ],
+        );
+    }
+
+    #[test]
+    fn parse_markdown_underscores_in_words() {
+        for text in [
+            "snake_case_name",
+            "__init__.py",
+            "def __init__(self):",
+            "self.__init__()",
+            "a__b__c",
+            "_ not italic _",
+        ] {
+            assert_markdown(text, text, vec![]);
+        }
+    }
+
+    #[test]
+    fn parse_markdown_urls() {
+        for text in [
+            "https://x.org/a_b_c",
+            "see www.example.com/__a__ now",
+            "(https://x.org/**a**)",
+        ] {
+            assert_markdown(text, text, vec![]);
+        }
+
+        assert_markdown(
+            "_see https://x.org/a_b_",
+            "see https://x.org/a_b",
+            vec![entity(0, 21, TextEntityType::Italic)],
+        );
+    }
+
+    #[test]
+    fn parse_markdown_code_spans() {
+        assert_markdown(
+            "`a_b_c` and `**x**`",
+            "a_b_c and **x**",
+            vec![
+                entity(0, 5, TextEntityType::Code),
+                entity(10, 5, TextEntityType::Code),
+            ],
+        );
+        assert_markdown(
+            "_a `b_c` d_",
+            "a b_c d",
+            vec![
+                entity(0, 7, TextEntityType::Italic),
+                entity(2, 3, TextEntityType::Code),
+            ],
+        );
+    }
+
+    #[test]
+    fn parse_markdown_escaped_characters() {
+        assert_markdown("\\*\\*not bold\\*\\* \\_a\\_", "**not bold** _a_", vec![]);
+        assert_markdown("a \\ b", "a \\ b", vec![]);
+    }
+
+    #[test]
+    fn formatted_text_to_markdown_entities() {
+        let text = formatted_text(
+            "hello world code link",
+            vec![
+                entity(0, 5, TextEntityType::Bold),
+                entity(6, 5, TextEntityType::Italic),
+                entity(12, 4, TextEntityType::Code),
+                entity(17, 4, text_url("https://example.com")),
+            ],
+        );
+        assert_eq!(
+            formatted_text_to_markdown(&text),
+            "**hello** _world_ `code` [link](https://example.com)"
+        );
+    }
+
+    #[test]
+    fn formatted_text_to_markdown_escaping() {
+        let text = formatted_text("snake_case https://x.org/a_b_c", vec![]);
+        assert_eq!(formatted_text_to_markdown(&text), text.text);
+
+        let text = formatted_text("**x** and `_a_`", vec![]);
+        assert_eq!(formatted_text_to_markdown(&text), "\\**x** and \\`\\_a_`");
+
+        let text = formatted_text("_a_", vec![entity(0, 3, TextEntityType::Code)]);
+        assert_eq!(formatted_text_to_markdown(&text), "`_a_`");
+    }
+
+    #[test]
+    fn formatted_text_to_markdown_links() {
+        let text = formatted_text("hi Alice", vec![entity(3, 5, mention_name(42))]);
+        assert_eq!(
+            formatted_text_to_markdown(&text),
+            "hi [Alice](tg://user?id=42)"
+        );
+
+        let text = formatted_text(
+            "wiki",
+            vec![entity(0, 4, text_url("https://x.org/a_(b)\\c"))],
+        );
+        assert_eq!(
+            formatted_text_to_markdown(&text),
+            "[wiki](https://x.org/a_(b\\)\\\\c)"
+        );
+    }
+
+    #[test]
+    fn formatted_text_to_markdown_round_trip() {
+        let texts = [
+            formatted_text(
+                "😀 bold both",
+                vec![
+                    entity(3, 9, TextEntityType::Bold),
+                    entity(8, 4, TextEntityType::Italic),
+                ],
+            ),
+            formatted_text(
+                "abcd efgh",
+                vec![
+                    entity(0, 4, TextEntityType::Bold),
+                    entity(0, 9, TextEntityType::Strikethrough),
+                ],
+            ),
+            formatted_text(
+                "**x** _y_ \\*",
+                vec![entity(0, 5, TextEntityType::Underline)],
+            ),
+            formatted_text(
+                "hi Alice and Bob",
+                vec![
+                    entity(3, 5, mention_name(42)),
+                    entity(13, 3, mention_name(7)),
+                    entity(13, 3, TextEntityType::Bold),
+                ],
+            ),
+            formatted_text(
+                "wiki and path",
+                vec![
+                    entity(
+                        0,
+                        4,
+                        text_url("https://en.wikipedia.org/wiki/Rust_(language)"),
+                    ),
+                    entity(9, 4, text_url("file:///C:\\dir\\")),
+                ],
+            ),
+        ];
+
+        for text in texts {
+            let markdown = formatted_text_to_markdown(&text);
+            let parsed_text = parse_markdown(&markdown);
+            assert_eq!(parsed_text.text, text.text, "{}", markdown);
+
+            // The order of the entities doesn't matter
+            let mut parsed_entities = entities_of(&parsed_text);
+            let mut entities = entities_of(&text);
+            parsed_entities.sort();
+            entities.sort();
+            assert_eq!(parsed_entities, entities, "{}", markdown);
+        }
+    }
+
+    #[test]
+    fn is_telegram_link_matches_telegram_links() {
+        let links = [
+            "tg://proxy?server=1.2.3.4&port=443&secret=abc",
+            "TG://socks?server=1.2.3.4&port=1080",
+            "https://t.me/proxy?server=1.2.3.4&port=443&secret=abc",
+            "HTTPS://T.ME/socks?server=1.2.3.4&port=1080",
+            "http://www.t.me/proxy?server=1.2.3.4&port=443",
+            "https://telegram.me/socks?server=1.2.3.4&port=1080",
+            "telegram.dog/username",
+            "https://t.me?start=1",
+        ];
+
+        for link in links {
+            assert!(is_telegram_link(link), "{}", link);
+        }
+    }
+
+    #[test]
+    fn is_telegram_link_ignores_other_links() {
+        let links = [
+            "https://example.com/proxy?server=1.2.3.4&port=443",
+            "https://t.me.example.com/proxy?server=1.2.3.4&port=443",
+            "https://example.com/?url=https://t.me/proxy",
+            "mailto:someone@t.me",
+            "https://nott.me/proxy",
+        ];
+
+        for link in links {
+            assert!(!is_telegram_link(link), "{}", link);
+        }
+    }
+}
@@ -12,9 +12,10 @@ use tdgrand::types::{self, Message as TelegramMessage};
 use tokio::task;
 
 use crate::config::{APP_ID, PROFILE};
+use crate::notification_policy;
 use crate::session::{Chat, ChatType};
 use crate::session_manager::{ClientState, SessionManager};
-use crate::utils::MESSAGE_TRUNCATED_LENGTH;
+use crate::utils::{self, MESSAGE_TRUNCATED_LENGTH};
 use crate::{Application, RUNTIME};
 
 mod imp {
@@ -33,6 +34,8 @@ mod imp {
         pub receiver_handle: RefCell<Option<task::JoinHandle<()>>>,
         pub receiver_should_stop: Arc<AtomicBool>,
 
+        #[template_child]
+        pub toast_overlay: TemplateChild<adw::ToastOverlay>,
         #[template_child]
         pub session_manager: TemplateChild<SessionManager>,
     }
@@ -48,6 +51,7 @@ mod imp {
                 settings: gio::Settings::new(APP_ID),
                 receiver_handle: RefCell::default(),
                 receiver_should_stop: Arc::default(),
+                toast_overlay: TemplateChild::default(),
                 session_manager: TemplateChild::default(),
             }
         }
@@ -97,7 +101,20 @@ mod imp {
         }
     }
 
-    impl WidgetImpl for Window {}
+    impl WidgetImpl for Window {
+        fn css_changed(&self, widget: &Self::Type, change: &gtk::CssStyleChange) {
+            self.parent_css_changed(widget, change);
+
+            // Keep the spoilers of the messages in sync with the theme
+            let style_context = widget.style_context();
+            if let Some(color) = style_context
+                .lookup_color("window_fg_color")
+                .or_else(|| style_context.lookup_color("theme_fg_color"))
+            {
+                utils::set_spoiler_color(&color);
+            }
+        }
+    }
     impl WindowImpl for Window {
         // Save window state on delete event
         fn close_request(&self, obj: &Self::Type) -> gtk::Inhibit {
@@ -133,6 +150,10 @@ impl Window {
         &*self.imp().session_manager
     }
 
+    pub fn add_toast(&self, toast: &adw::Toast) {
+        self.imp().toast_overlay.add_toast(toast);
+    }
+
     fn start_receiver(&self) {
         let imp = self.imp();
         let receiver_should_stop = imp.receiver_should_stop.clone();
@@ -219,30 +240,78 @@ impl Window {
         if let Some(ref client) =
             client.filter(|client| matches!(client.state, ClientState::LoggedIn))
         {
+            let imp = self.imp();
             let app = self.application().unwrap();
             let chat = client.session.chat_list().get(chat_id);
 
+            let is_chat_visible =
+                self.is_active() && imp.session_manager.is_chat_visible(client_id, chat_id);
+            let is_session_active =
+                imp.session_manager.active_logged_in_client_id() == Some(client_id);
+            if !notification_policy::should_notify(
+                &chat,
+                is_chat_visible,
+                is_session_active,
+                &imp.settings,
+            ) {
+                return;
+            }
+
+            let show_sender = notification_policy::should_show_sender(&imp.settings);
+            let show_preview = notification_policy::should_show_preview(&chat, &imp.settings);
+            let target = (client_id, chat_id).to_variant();
+            let icon = chat
+                .avatar()
+                .image_path()
+                .filter(|_| show_sender)
+                .map(|path| gio::FileIcon::new(&gio::File::for_path(path)));
+
             for notification in notifications {
                 let notification_id = notification.id;
+                let button_target = (client_id, chat_id, notification_id).to_variant();
                 let notification = match notification.r#type {
                     enums::NotificationType::NewMessage(data) => {
-                        let mut title = chat.title();
-                        let body = stringify_message_content(&data.message, &chat);
+                        let title = if show_sender {
+                            let mut title = chat.title();
+
+                            // Add the sender's name to the title if the chat is a group
+                            if let ChatType::BasicGroup(_) | ChatType::Supergroup(_) = chat.type_()
+                            {
+                                let sender_name = sender_name(&data.message.sender_id, &chat);
+                                title.insert_str(0, &format!("{} – ", sender_name));
+                            }
 
-                        // Add the sender's name to the title if the chat is a group
-                        if let ChatType::BasicGroup(_) | ChatType::Supergroup(_) = chat.type_() {
-                            let sender_name = sender_name(&data.message.sender_id, &chat);
-                            title.insert_str(0, &format!("{} – ", sender_name));
-                        }
+                            title
+                        } else {
+                            gettext("New Message")
+                        };
 
                         let notification = gio::Notification::new(&title);
-                        notification.set_body(Some(&body));
+                        if show_preview {
+                            let body = stringify_message_content(&data.message, &chat);
+                            notification.set_body(Some(&body));
+                        }
+                        notification.add_button_with_target_value(
+                            &gettext("Mark as Read"),
+                            "app.mark-chat-as-read",
+                            Some(&button_target),
+                        );
+                        notification.add_button_with_target_value(
+                            &gettext("Reply"),
+                            "app.reply-to-chat",
+                            Some(&button_target),
+                        );
 
                         Some(notification)
                     }
                     enums::NotificationType::NewCall(_) => {
                         let body = gettext("Incoming call");
-                        let notification = gio::Notification::new(&chat.title());
+                        let title = if show_sender {
+                            chat.title()
+                        } else {
+                            gettext("New Call")
+                        };
+                        let notification = gio::Notification::new(&title);
                         notification.set_body(Some(&body));
 
                         Some(notification)
@@ -251,6 +320,12 @@ impl Window {
                 };
 
                 if let Some(notification) = notification {
+                    notification
+                        .set_default_action_and_target_value("app.open-chat", Some(&target));
+                    if let Some(ref icon) = icon {
+                        notification.set_icon(icon);
+                    }
+
                     app.send_notification(Some(&notification_id.to_string()), &notification);
                 }
             }